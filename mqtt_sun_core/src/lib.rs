@@ -0,0 +1,10 @@
+//! Phase and transit math shared by the daemon and its embedded companions.
+//!
+//! Everything here is `no_std` and works on plain floating point values:
+//! times are Julian days or fractions of a day and angles are degrees unless
+//! stated otherwise.
+
+#![no_std]
+
+pub mod phase;
+pub mod transit;
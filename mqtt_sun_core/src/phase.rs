@@ -0,0 +1,254 @@
+//! Classification of the sun altitude into day phases.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SunPosition {
+    Night,
+    AstronomicalDawn,
+    NauticalDawn,
+    CivilDawn,
+    Sunrise,
+    Sunset,
+    CivilDusk,
+    NauticalDusk,
+    AstronomicalDusk,
+    SolarNoon,
+}
+
+impl From<&SunPosition> for &'static str {
+    fn from(s: &SunPosition) -> Self {
+        match s {
+            SunPosition::Night => "night",
+            SunPosition::AstronomicalDawn => "astronomicalDawn",
+            SunPosition::NauticalDawn => "nauticalDawn",
+            SunPosition::CivilDawn => "civilDawn",
+            SunPosition::Sunrise => "sunrise",
+            SunPosition::Sunset => "sunset",
+            SunPosition::CivilDusk => "civilDusk",
+            SunPosition::NauticalDusk => "nauticalDusk",
+            SunPosition::AstronomicalDusk => "astronomicalDusk",
+            SunPosition::SolarNoon => "solarNoon",
+        }
+    }
+}
+
+impl SunPosition {
+    /// Every phase, in the order of their codes.
+    pub const ALL: [SunPosition; 10] = [
+        SunPosition::Night,
+        SunPosition::AstronomicalDawn,
+        SunPosition::NauticalDawn,
+        SunPosition::CivilDawn,
+        SunPosition::Sunrise,
+        SunPosition::SolarNoon,
+        SunPosition::Sunset,
+        SunPosition::CivilDusk,
+        SunPosition::NauticalDusk,
+        SunPosition::AstronomicalDusk,
+    ];
+
+    /// Stable numeric codes of the phases, in the order they occur during the
+    /// day, for protocols that carry integers only. They are never reused or
+    /// renumbered.
+    ///
+    /// | Code | Phase              |
+    /// |------|--------------------|
+    /// | 0    | `night`            |
+    /// | 1    | `astronomicalDawn` |
+    /// | 2    | `nauticalDawn`     |
+    /// | 3    | `civilDawn`        |
+    /// | 4    | `sunrise`          |
+    /// | 5    | `solarNoon`        |
+    /// | 6    | `sunset`           |
+    /// | 7    | `civilDusk`        |
+    /// | 8    | `nauticalDusk`     |
+    /// | 9    | `astronomicalDusk` |
+    pub const CODES: [(SunPosition, u8); 10] = [
+        (SunPosition::Night, 0),
+        (SunPosition::AstronomicalDawn, 1),
+        (SunPosition::NauticalDawn, 2),
+        (SunPosition::CivilDawn, 3),
+        (SunPosition::Sunrise, 4),
+        (SunPosition::SolarNoon, 5),
+        (SunPosition::Sunset, 6),
+        (SunPosition::CivilDusk, 7),
+        (SunPosition::NauticalDusk, 8),
+        (SunPosition::AstronomicalDusk, 9),
+    ];
+
+    /// Whether the sun is above the horizon during the phase: from sunrise
+    /// through the afternoon, which is the `sunset` phase until the sun goes
+    /// down into civil dusk.
+    pub fn is_day(&self) -> bool {
+        matches!(
+            self,
+            SunPosition::Sunrise | SunPosition::SolarNoon | SunPosition::Sunset
+        )
+    }
+
+    /// Numeric code of the phase, from [`SunPosition::CODES`].
+    pub fn code(&self) -> u8 {
+        Self::CODES
+            .iter()
+            .find(|(x, _)| x == self)
+            .map(|(_, code)| *code)
+            .unwrap_or_default()
+    }
+}
+
+/// Altitudes, in degrees, at which the phases begin: astronomical, nautical
+/// and civil twilight and the sun being up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    pub astronomical: f64,
+    pub nautical: f64,
+    pub civil: f64,
+    pub horizon: f64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            astronomical: -18.0,
+            nautical: -12.0,
+            civil: -6.0,
+            horizon: 0.0,
+        }
+    }
+}
+
+impl Thresholds {
+    /// Threshold altitudes with the phases entered when the sun rises and
+    /// sets through them.
+    pub fn crossings(&self) -> [(f64, SunPosition, SunPosition); 4] {
+        [
+            (
+                self.astronomical,
+                SunPosition::AstronomicalDawn,
+                SunPosition::AstronomicalDusk,
+            ),
+            (
+                self.nautical,
+                SunPosition::NauticalDawn,
+                SunPosition::NauticalDusk,
+            ),
+            (self.civil, SunPosition::CivilDawn, SunPosition::CivilDusk),
+            (self.horizon, SunPosition::Sunrise, SunPosition::Sunset),
+        ]
+    }
+
+    /// Returns the phase for the sun at `altitude` radians, rising if
+    /// `is_morning` and setting otherwise.
+    pub fn classify(&self, altitude: f64, is_morning: bool) -> SunPosition {
+        let altitude = altitude.to_degrees();
+        let band = if altitude >= self.horizon {
+            3
+        } else if altitude >= self.civil {
+            2
+        } else if altitude >= self.nautical {
+            1
+        } else if altitude >= self.astronomical {
+            0
+        } else {
+            return SunPosition::Night;
+        };
+        let (_, dawn, dusk) = self.crossings()[band];
+        if is_morning {
+            dawn
+        } else {
+            dusk
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify(altitude: f64, is_morning: bool) -> SunPosition {
+        Thresholds::default().classify(altitude.to_radians(), is_morning)
+    }
+
+    #[test]
+    fn morning() {
+        assert_eq!(classify(-30.0, true), SunPosition::Night);
+        assert_eq!(classify(-17.9, true), SunPosition::AstronomicalDawn);
+        assert_eq!(classify(-15.0, true), SunPosition::AstronomicalDawn);
+        assert_eq!(classify(-11.9, true), SunPosition::NauticalDawn);
+        assert_eq!(classify(-3.0, true), SunPosition::CivilDawn);
+        assert_eq!(classify(0.1, true), SunPosition::Sunrise);
+        assert_eq!(classify(60.0, true), SunPosition::Sunrise);
+    }
+
+    #[test]
+    fn evening() {
+        assert_eq!(classify(60.0, false), SunPosition::Sunset);
+        assert_eq!(classify(0.1, false), SunPosition::Sunset);
+        assert_eq!(classify(-0.1, false), SunPosition::CivilDusk);
+        assert_eq!(classify(-6.1, false), SunPosition::NauticalDusk);
+        assert_eq!(classify(-12.1, false), SunPosition::AstronomicalDusk);
+        assert_eq!(classify(-18.1, false), SunPosition::Night);
+    }
+
+    #[test]
+    fn custom_thresholds() {
+        let thresholds = Thresholds {
+            horizon: -0.833,
+            ..Thresholds::default()
+        };
+        assert_eq!(
+            thresholds.classify((-0.5f64).to_radians(), true),
+            SunPosition::Sunrise
+        );
+        assert_eq!(
+            thresholds.classify((-1.0f64).to_radians(), false),
+            SunPosition::CivilDusk
+        );
+    }
+
+    #[test]
+    fn crossings() {
+        let thresholds = Thresholds::default();
+        let crossings = thresholds.crossings();
+        // From the lowest threshold up, entering the phase of that band
+        for window in crossings.windows(2) {
+            assert!(window[0].0 < window[1].0);
+        }
+        for (threshold, dawn, dusk) in crossings.iter() {
+            assert_eq!(classify(*threshold + 0.1, true), *dawn);
+            assert_eq!(classify(*threshold + 0.1, false), *dusk);
+        }
+    }
+
+    #[test]
+    fn is_day() {
+        for position in SunPosition::ALL.iter() {
+            let expected = matches!(
+                position,
+                SunPosition::Sunrise | SunPosition::SolarNoon | SunPosition::Sunset
+            );
+            assert_eq!(position.is_day(), expected, "{:?}", position);
+        }
+        // Every phase entered above the horizon is daytime
+        assert!(classify(10.0, true).is_day());
+        assert!(classify(10.0, false).is_day());
+        assert!(!classify(-1.0, true).is_day());
+        assert!(!classify(-1.0, false).is_day());
+    }
+
+    #[test]
+    fn codes() {
+        for (i, position) in SunPosition::ALL.iter().enumerate() {
+            assert_eq!(position.code() as usize, i);
+        }
+        for (i, (_, code)) in SunPosition::CODES.iter().enumerate() {
+            assert_eq!(*code as usize, i);
+        }
+        for (i, a) in SunPosition::ALL.iter().enumerate() {
+            for b in &SunPosition::ALL[i + 1..] {
+                assert_ne!(a, b);
+                let (a, b): (&'static str, &'static str) = (a.into(), b.into());
+                assert_ne!(a, b);
+            }
+        }
+    }
+}
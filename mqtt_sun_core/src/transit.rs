@@ -0,0 +1,83 @@
+//! Time of the solar transit (solar noon), found by driving the local hour
+//! angle of the sun to zero.
+
+use core::f64::consts::{PI, TAU};
+
+/// Local hour angle in radians, in `[-π, π)`, from the Greenwich hour angle
+/// in radians and the longitude in degrees east.
+pub fn local_hour_angle(gha: f64, longitude: f64) -> f64 {
+    let angle = (gha + longitude.to_radians() + PI) % TAU;
+    if angle < 0.0 {
+        angle + PI
+    } else {
+        angle - PI
+    }
+}
+
+/// Fraction of the UTC day at which the mean sun transits the meridian of
+/// `longitude` degrees east, four minutes earlier per degree.
+pub fn mean_noon(longitude: f64) -> f64 {
+    0.5 - longitude / 360.0
+}
+
+/// Julian day of the transit over the meridian of `longitude` degrees east
+/// closest to the Julian day `near`, given `gha`, the Greenwich hour angle
+/// of the sun in radians at a Julian day.
+pub fn transit(near: f64, longitude: f64, gha: impl Fn(f64) -> f64) -> f64 {
+    let mut transit = near;
+    for _ in 0..3 {
+        transit -= local_hour_angle(gha(transit), longitude) / TAU;
+    }
+    transit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hour angle of the mean sun, which transits Greenwich at 12h UT, when
+    /// Julian days begin.
+    fn mean_gha(jd: f64) -> f64 {
+        jd % 1.0 * TAU
+    }
+
+    #[test]
+    fn hour_angles() {
+        assert!(local_hour_angle(0.0, 0.0).abs() < 1e-12);
+        assert!((local_hour_angle(PI / 2.0, 0.0) - PI / 2.0).abs() < 1e-12);
+        assert!((local_hour_angle(3.0 * PI / 2.0, 0.0) + PI / 2.0).abs() < 1e-12);
+        assert!((local_hour_angle(0.0, -90.0) + PI / 2.0).abs() < 1e-12);
+        assert!((local_hour_angle(-TAU - 0.1, 0.0) + 0.1).abs() < 1e-12);
+        assert!((local_hour_angle(PI, 0.0) + PI).abs() < 1e-12);
+    }
+
+    #[test]
+    fn mean_noons() {
+        assert_eq!(mean_noon(0.0), 0.5);
+        assert_eq!(mean_noon(90.0), 0.25);
+        assert_eq!(mean_noon(-180.0), 1.0);
+    }
+
+    #[test]
+    fn transits_of_the_mean_sun() {
+        // 2000 January 1.0 UT
+        let midnight = 2451544.5;
+        for &longitude in &[0.0, 11.3, -77.0, 179.0] {
+            let expected = midnight + mean_noon(longitude);
+            let found = transit(expected + 0.1, longitude, mean_gha);
+            assert!((found - expected).abs() < 1e-9, "{}: {}", longitude, found);
+        }
+    }
+
+    #[test]
+    fn transits_with_an_equation_of_time() {
+        // A sun running ten minutes fast
+        let fast = |jd: f64| mean_gha(jd) + (10.0f64 / 4.0).to_radians();
+        let found = transit(2451545.0, 0.0, fast);
+        assert!(
+            (found - (2451545.0 - 10.0 / 1440.0)).abs() < 1e-9,
+            "{}",
+            found
+        );
+    }
+}
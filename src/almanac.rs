@@ -0,0 +1,53 @@
+//! Nautical almanac data for celestial navigation.
+
+use crate::ephemeris;
+use crate::phase::{SunPosition, Thresholds};
+use crate::schedule;
+
+/// Formats an angle in degrees as degrees and decimal arc minutes, the way
+/// almanacs print them.
+fn degrees_minutes(angle: f64) -> String {
+    let minutes = (angle.abs() * 60.0 * 10.0).round() / 10.0;
+    format!("{}°{:04.1}'", (minutes / 60.0).floor(), minutes % 60.0)
+}
+
+/// Greenwich hour angle and declination of the sun at `timestamp`.
+pub fn position(timestamp: i64) -> serde_json::Value {
+    let (gha, dec) = ephemeris::sun_gha_dec(ephemeris::julian_day(timestamp));
+    let (gha, dec) = (gha.to_degrees(), dec.to_degrees());
+    serde_json::json!({
+        "timestamp": timestamp,
+        "gha": gha,
+        "declination": dec,
+        "gha_dm": degrees_minutes(gha),
+        "declination_dm": format!(
+            "{} {}",
+            if dec >= 0.0 { "N" } else { "S" },
+            degrees_minutes(dec)
+        ),
+    })
+}
+
+/// Civil twilight, sunrise and sunset times on the UTC day `date`.
+pub fn twilight(
+    date: chrono::NaiveDate,
+    coords: &astro::coords::GeographPoint,
+    thresholds: &Thresholds,
+) -> serde_json::Value {
+    let start = date.and_hms(0, 0, 0).timestamp();
+    let mut times = serde_json::json!({ "date": date.to_string() });
+    for event in schedule::events_between(start, start + 24 * 3600, coords, thresholds) {
+        let key = match event.position {
+            SunPosition::CivilDawn => "civil_dawn",
+            SunPosition::Sunrise => "sunrise",
+            SunPosition::Sunset => "sunset",
+            SunPosition::CivilDusk => "civil_dusk",
+            _ => continue,
+        };
+        times[key] = chrono::NaiveDateTime::from_timestamp(event.timestamp, 0)
+            .format("%Y-%m-%dT%H:%M:%SZ")
+            .to_string()
+            .into();
+    }
+    times
+}
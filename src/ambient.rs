@@ -0,0 +1,88 @@
+//! Fusion of a real light sensor with the astronomical illuminance model.
+//!
+//! Storms or snow cover make it darker or brighter than the sun position
+//! alone suggests. The measured illuminance is blended with the modelled one
+//! (in logarithmic space, as illuminance spans many orders of magnitude) and
+//! "effective" dawn and dusk events fire when the blend crosses a threshold.
+
+use crate::clear_sky;
+use std::time::{Duration, Instant};
+
+/// Measurements older than this are considered stale and ignored.
+const MAX_MEASUREMENT_AGE: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug)]
+pub struct AmbientLight {
+    pub topic: String,
+    /// Trust in the sensor, from 0 (ignore it) to 1 (ignore the model)
+    weight: f64,
+    /// Illuminance in lux below which it is considered dark
+    threshold: f64,
+    measured: Option<(Instant, f64)>,
+    is_light: Option<bool>,
+}
+
+impl AmbientLight {
+    /// Reads `LUX_SENSOR_TOPIC`, `LUX_SENSOR_WEIGHT` and `LUX_THRESHOLD`.
+    pub fn from_env() -> Option<Self> {
+        let topic = std::env::var("LUX_SENSOR_TOPIC").ok()?;
+        let weight: f64 = std::env::var("LUX_SENSOR_WEIGHT")
+            .map(|x| x.parse().expect("Invalid lux sensor weight"))
+            .unwrap_or(0.5);
+        assert!(
+            (0.0..=1.0).contains(&weight),
+            "The lux sensor weight must be between 0 and 1"
+        );
+        Some(Self {
+            topic,
+            weight,
+            threshold: std::env::var("LUX_THRESHOLD")
+                .map(|x| x.parse().expect("Invalid lux threshold"))
+                .unwrap_or(10.0),
+            measured: None,
+            is_light: None,
+        })
+    }
+
+    /// Records a sensor reading, either a bare number or a JSON object with
+    /// an `illuminance_lux`, `illuminance` or `lux` field.
+    pub fn record(&mut self, payload: &[u8]) {
+        let text = String::from_utf8_lossy(payload);
+        let lux = text.trim().parse::<f64>().ok().or_else(|| {
+            let json: serde_json::Value = serde_json::from_str(&text).ok()?;
+            ["illuminance_lux", "illuminance", "lux"]
+                .iter()
+                .find_map(|key| json.get(key).and_then(|x| x.as_f64()))
+        });
+        match lux {
+            Some(lux) => self.measured = Some((Instant::now(), lux)),
+            None => log::warn!("Ignoring unreadable lux sensor payload `{}`", text),
+        }
+    }
+
+    pub fn effective_illuminance(&self, altitude: f64) -> f64 {
+        let modelled = clear_sky::illuminance(altitude);
+        match self.measured {
+            Some((at, measured)) if at.elapsed() < MAX_MEASUREMENT_AGE => {
+                let log_lux = (1.0 - self.weight) * modelled.log10()
+                    + self.weight * measured.max(clear_sky::illuminance(-90.0)).log10();
+                10f64.powf(log_lux)
+            }
+            _ => modelled,
+        }
+    }
+
+    /// Returns the effective dawn or dusk event, if one just happened.
+    pub fn update(&mut self, altitude: f64) -> Option<&'static str> {
+        let is_light = self.effective_illuminance(altitude) >= self.threshold;
+        if self.is_light == Some(is_light) {
+            return None;
+        }
+        self.is_light = Some(is_light);
+        Some(if is_light {
+            "effectiveDawn"
+        } else {
+            "effectiveDusk"
+        })
+    }
+}
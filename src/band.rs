@@ -0,0 +1,114 @@
+/// Splits the sky into discrete elevation bands delimited by `edges`
+/// (ascending, in degrees).
+#[derive(Debug)]
+pub struct ElevationBands {
+    edges: Vec<f64>,
+}
+
+impl ElevationBands {
+    /// Reads the comma separated band edges from `ELEVATION_BANDS`,
+    /// defaulting to `0,15,35`.
+    pub fn from_env() -> Self {
+        let mut edges: Vec<f64> = std::env::var("ELEVATION_BANDS")
+            .unwrap_or_else(|_| "0,15,35".to_owned())
+            .split(',')
+            .map(|x| {
+                x.trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|x| x.is_finite())
+                    .expect("Invalid elevation band edge")
+            })
+            .collect();
+        edges.sort_by(f64::total_cmp);
+        Self { edges }
+    }
+
+    pub fn edges(&self) -> &[f64] {
+        &self.edges
+    }
+
+    /// Returns the label of the band containing `altitude`, such as `15-35`,
+    /// `35+` for the topmost band or `<0` below the lowest edge.
+    pub fn label(&self, altitude: f64) -> String {
+        match self.edges.iter().rposition(|&edge| altitude >= edge) {
+            None => format!("<{}", self.edges[0]),
+            Some(i) if i == self.edges.len() - 1 => format!("{}+", self.edges[i]),
+            Some(i) => format!("{}-{}", self.edges[i], self.edges[i + 1]),
+        }
+    }
+}
+
+/// Range of altitudes between two bounds in degrees, telling when the sun
+/// enters or leaves it.
+#[derive(Debug)]
+pub struct Band {
+    low: f64,
+    high: f64,
+    inside: Option<bool>,
+}
+
+impl Band {
+    /// Reads the bounds from `variable`, such as `-4,6`, or from `default`.
+    pub fn from_env(variable: &str, default: &str) -> Self {
+        let bounds: Vec<f64> = std::env::var(variable)
+            .unwrap_or_else(|_| default.to_owned())
+            .split(',')
+            .map(|x| {
+                x.trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|x| x.is_finite())
+                    .unwrap_or_else(|| panic!("Invalid {} bound", variable))
+            })
+            .collect();
+        match bounds.as_slice() {
+            &[low, high] if low < high => Self::new(low, high),
+            _ => panic!("{} needs two increasing values", variable),
+        }
+    }
+
+    pub fn new(low: f64, high: f64) -> Self {
+        Self {
+            low,
+            high,
+            inside: None,
+        }
+    }
+
+    /// Lowest and highest altitude of the band in degrees.
+    pub fn bounds(&self) -> [f64; 2] {
+        [self.low, self.high]
+    }
+
+    /// Feeds the sun altitude in degrees, returning `Some(true)` if the sun
+    /// entered the band and `Some(false)` if it left it. Being outside at
+    /// the first altitude is no change.
+    pub fn update(&mut self, altitude: f64) -> Option<bool> {
+        let inside = altitude >= self.low && altitude < self.high;
+        if self.inside == Some(inside) {
+            return None;
+        }
+        let first = self.inside.is_none();
+        self.inside = Some(inside);
+        Some(inside).filter(|x| *x || !first)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entering_and_leaving() {
+        let mut band = Band::new(-4.0, 6.0);
+        assert_eq!(band.update(10.0), None);
+        assert_eq!(band.update(8.0), None);
+        assert_eq!(band.update(6.0), None);
+        assert_eq!(band.update(5.9), Some(true));
+        assert_eq!(band.update(0.0), None);
+        assert_eq!(band.update(-4.1), Some(false));
+        let mut band = Band::new(-4.0, 6.0);
+        assert_eq!(band.update(-4.0), Some(true));
+    }
+}
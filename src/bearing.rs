@@ -0,0 +1,154 @@
+use std::str::FromStr;
+
+/// Way the azimuth moves through a bearing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Clockwise,
+    Counterclockwise,
+    Either,
+}
+
+/// An event fired when the azimuth of the sun crosses `bearing`, such as the
+/// sun coming around the corner of a building.
+#[derive(Debug)]
+pub struct Crossing {
+    pub name: String,
+    /// Degrees clockwise from north
+    pub bearing: f64,
+    /// Altitude in degrees below which crossings are ignored
+    pub min_altitude: Option<f64>,
+    pub direction: Direction,
+}
+
+/// Signed difference `to - from` between two angles in degrees, in
+/// `(-180, 180]`.
+fn difference(from: f64, to: f64) -> f64 {
+    180.0 - (180.0 - (to - from)).rem_euclid(360.0)
+}
+
+/// Whether the azimuth moved through `bearing`, either way, going from
+/// `previous` to `azimuth`, in degrees.
+pub fn passes(previous: f64, azimuth: f64, bearing: f64) -> bool {
+    let moved = difference(previous, azimuth);
+    let to_bearing = difference(previous, bearing);
+    (moved > 0.0 && to_bearing > 0.0 && to_bearing <= moved)
+        || (moved < 0.0 && to_bearing < 0.0 && to_bearing >= moved)
+}
+
+impl Crossing {
+    /// Whether the sun crossed the bearing moving from `previous` to
+    /// `azimuth`, in degrees, while at `altitude`.
+    pub fn is_crossed(&self, previous: f64, azimuth: f64, altitude: f64) -> bool {
+        if self.min_altitude.map(|x| altitude < x).unwrap_or(false) {
+            return false;
+        }
+        if !passes(previous, azimuth, self.bearing) {
+            return false;
+        }
+        let clockwise = difference(previous, azimuth) > 0.0;
+        match self.direction {
+            Direction::Clockwise => clockwise,
+            Direction::Counterclockwise => !clockwise,
+            Direction::Either => true,
+        }
+    }
+}
+
+/// Parses `name:bearing[:min_altitude[:direction]]`, where the direction is
+/// `cw`, `ccw` or `any` (the default), e.g. `terrace:245:5:cw`. The minimum
+/// altitude can be left empty, as in `terrace:245::cw`.
+impl FromStr for Crossing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split(':');
+        let name = parts
+            .next()
+            .filter(|x| !x.is_empty())
+            .ok_or_else(|| format!("missing name in azimuth crossing `{}`", s))?;
+        let parse_angle = |x: &str| {
+            x.trim()
+                .parse::<f64>()
+                .map_err(|_| format!("invalid angle `{}` in azimuth crossing `{}`", x, s))
+        };
+        let bearing = parse_angle(
+            parts
+                .next()
+                .ok_or_else(|| format!("missing bearing in azimuth crossing `{}`", s))?,
+        )?;
+        let min_altitude = match parts.next().map(str::trim) {
+            None | Some("") => None,
+            Some(x) => Some(parse_angle(x)?),
+        };
+        let direction = match parts.next().map(str::trim) {
+            None | Some("any") => Direction::Either,
+            Some("cw") => Direction::Clockwise,
+            Some("ccw") => Direction::Counterclockwise,
+            Some(x) => {
+                return Err(format!(
+                    "invalid direction `{}` in azimuth crossing `{}`, expected cw, ccw or any",
+                    x, s
+                ))
+            }
+        };
+        Ok(Self {
+            name: name.to_owned(),
+            bearing: bearing.rem_euclid(360.0),
+            min_altitude,
+            direction,
+        })
+    }
+}
+
+/// Reads the crossings from the comma separated `AZIMUTH_CROSSINGS` variable.
+pub fn from_env() -> Vec<Crossing> {
+    std::env::var("AZIMUTH_CROSSINGS")
+        .map(|x| {
+            x.split(',')
+                .filter(|c| !c.trim().is_empty())
+                .map(|c| {
+                    c.parse()
+                        .unwrap_or_else(|e| panic!("Invalid azimuth crossing: {}", e))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crossing(direction: Direction) -> Crossing {
+        Crossing {
+            name: "terrace".to_owned(),
+            bearing: 245.0,
+            min_altitude: Some(5.0),
+            direction,
+        }
+    }
+
+    #[test]
+    fn directions() {
+        for (direction, clockwise, counterclockwise) in [
+            (Direction::Either, true, true),
+            (Direction::Clockwise, true, false),
+            (Direction::Counterclockwise, false, true),
+        ] {
+            let crossing = crossing(direction);
+            assert_eq!(crossing.is_crossed(240.0, 250.0, 10.0), clockwise);
+            assert_eq!(crossing.is_crossed(250.0, 240.0, 10.0), counterclockwise);
+            assert!(!crossing.is_crossed(250.0, 260.0, 10.0));
+            assert!(!crossing.is_crossed(240.0, 250.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn passes_through_north() {
+        assert!(passes(350.0, 10.0, 0.0));
+        assert!(passes(10.0, 350.0, 0.0));
+        assert!(passes(350.0, 10.0, 355.0));
+        assert!(!passes(350.0, 10.0, 180.0));
+        assert!(!passes(10.0, 10.0, 10.0));
+    }
+}
@@ -0,0 +1,124 @@
+//! Resolution of the address of the MQTT broker.
+
+use log::{info, warn};
+use std::time::{Duration, Instant};
+
+const MDNS_SERVICE: &str = "_mqtt._tcp.local.";
+const SRV_PREFIX: &str = "_mqtt._tcp.";
+const MDNS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Returns the host and port of the broker, from `MQTT_BROKER` and
+/// `MQTT_PORT` or, if no broker is configured, by looking for one
+/// advertising itself on the local network.
+///
+/// A broker such as `_mqtt._tcp.example.com` is looked up through its DNS
+/// SRV records instead, and one such as `wss://example.com/mqtt` selects the
+/// transport through its scheme.
+pub fn resolve() -> (String, u16) {
+    let host = match std::env::var("MQTT_BROKER") {
+        Ok(host) if host.starts_with(SRV_PREFIX) => {
+            return lookup_srv(&host)
+                .unwrap_or_else(|e| panic!("Could not resolve MQTT broker {}: {}", host, e))
+        }
+        Ok(host) => host,
+        Err(_) => {
+            return discover().expect("Please provide a MQTT broker, none was found via mDNS")
+        }
+    };
+    let (host, port) = match host.split_once("://") {
+        Some((scheme, rest)) => parse_uri(scheme, rest),
+        None => (host, None),
+    };
+    let port = port.unwrap_or_else(|| {
+        let default_port = crate::mqtt::default_port();
+        std::env::var("MQTT_PORT")
+            .map(|x| x.parse().unwrap_or(default_port))
+            .unwrap_or(default_port)
+    });
+    (host, port)
+}
+
+/// Splits the part of a broker URI after `scheme://` into the host and the
+/// port, exporting the transport the scheme stands for as `MQTT_TLS` and
+/// `MQTT_TRANSPORT` and the path as `MQTT_WS_PATH`.
+fn parse_uri(scheme: &str, rest: &str) -> (String, Option<u16>) {
+    let (tls, transport) = match scheme {
+        "mqtt" | "tcp" => (false, "tcp"),
+        "mqtts" | "ssl" => (true, "tcp"),
+        "ws" => (false, "websocket"),
+        "wss" => (true, "websocket"),
+        _ => panic!("Unsupported MQTT broker scheme {}", scheme),
+    };
+    std::env::set_var("MQTT_TLS", tls.to_string());
+    std::env::set_var("MQTT_TRANSPORT", transport);
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, ""),
+    };
+    if !path.is_empty() {
+        std::env::set_var("MQTT_WS_PATH", path);
+    }
+    match authority.rsplit_once(':') {
+        Some((host, port)) => (
+            host.to_owned(),
+            Some(
+                port.parse()
+                    .unwrap_or_else(|_| panic!("Invalid MQTT broker port {}", port)),
+            ),
+        ),
+        None => (authority.to_owned(), None),
+    }
+}
+
+/// Browses mDNS for a `_mqtt._tcp` service, returning the first one resolved.
+fn discover() -> Option<(String, u16)> {
+    info!("No MQTT broker configured, looking for one via mDNS");
+    let mdns = mdns_sd::ServiceDaemon::new()
+        .map_err(|e| warn!("Could not start mDNS discovery: {}", e))
+        .ok()?;
+    let events = mdns
+        .browse(MDNS_SERVICE)
+        .map_err(|e| warn!("Could not browse mDNS: {}", e))
+        .ok()?;
+    let deadline = Instant::now() + MDNS_TIMEOUT;
+    let mut found = None;
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        match events.recv_timeout(remaining) {
+            Ok(mdns_sd::ServiceEvent::ServiceResolved(service)) => {
+                if let Some(address) = service.get_addresses().iter().next() {
+                    info!(
+                        "Found MQTT broker {} at {}:{}",
+                        service.get_fullname(),
+                        address,
+                        service.get_port()
+                    );
+                    found = Some((address.to_string(), service.get_port()));
+                    break;
+                }
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    let _ = mdns.shutdown();
+    found
+}
+
+/// Picks the SRV record with the lowest priority and, among those, the
+/// highest weight.
+fn lookup_srv(name: &str) -> Result<(String, u16), String> {
+    let resolver = hickory_resolver::Resolver::from_system_conf().map_err(|e| e.to_string())?;
+    let records = resolver.srv_lookup(name).map_err(|e| e.to_string())?;
+    let record = records
+        .iter()
+        .min_by_key(|x| (x.priority(), std::cmp::Reverse(x.weight())))
+        .ok_or_else(|| "no SRV records".to_owned())?;
+    let host = record.target().to_utf8().trim_end_matches('.').to_owned();
+    info!(
+        "Resolved MQTT broker {} to {}:{}",
+        name,
+        host,
+        record.port()
+    );
+    Ok((host, record.port()))
+}
@@ -0,0 +1,109 @@
+//! Colour temperature and brightness for adaptive lighting on
+//! `sun/circadian`, following the altitude of the sun: warm and dim while
+//! it is below the lower end of `CIRCADIAN_ELEVATION` (default `-6,30`),
+//! cool and bright above the upper end.
+//!
+//! `CIRCADIAN_KELVIN` (default `2000,6500`) and `CIRCADIAN_BRIGHTNESS`
+//! (percentages, default `30,100`) bound the outputs, and `CIRCADIAN_CURVE`
+//! shapes the way in between: `sine` (the default) eases in and out,
+//! `linear` follows the altitude, and a number such as `2` raises the
+//! linear progress to that power.
+
+pub const TOPIC: &str = "sun/circadian";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Curve {
+    Linear,
+    Sine,
+    Power(f64),
+}
+
+#[derive(Debug)]
+pub struct Circadian {
+    /// Altitudes of the sun in degrees between which the lighting changes
+    elevation: (f64, f64),
+    /// Colour temperature in K
+    kelvin: (f64, f64),
+    /// Brightness in percent
+    brightness: (f64, f64),
+    curve: Curve,
+}
+
+/// Reads two comma separated values from `name`, the first smaller.
+fn range(name: &str, default: &str) -> (f64, f64) {
+    let values: Vec<f64> = std::env::var(name)
+        .unwrap_or_else(|_| default.to_owned())
+        .split(',')
+        .map(|x| {
+            x.trim()
+                .parse()
+                .unwrap_or_else(|_| panic!("Invalid {}", name))
+        })
+        .collect();
+    match values.as_slice() {
+        &[low, high] if low < high => (low, high),
+        _ => panic!("{} needs two increasing values", name),
+    }
+}
+
+impl Circadian {
+    /// Enabled by `CIRCADIAN=true`.
+    pub fn from_env() -> Option<Self> {
+        let enabled: bool = std::env::var("CIRCADIAN")
+            .ok()?
+            .parse()
+            .expect("Invalid CIRCADIAN, expected true or false");
+        if !enabled {
+            return None;
+        }
+        let kelvin = range("CIRCADIAN_KELVIN", "2000,6500");
+        assert!(kelvin.0 > 0.0, "CIRCADIAN_KELVIN must be positive");
+        let brightness = range("CIRCADIAN_BRIGHTNESS", "30,100");
+        assert!(
+            brightness.0 >= 0.0 && brightness.1 <= 100.0,
+            "CIRCADIAN_BRIGHTNESS must be between 0 and 100"
+        );
+        let curve = match std::env::var("CIRCADIAN_CURVE").as_deref() {
+            Ok("sine") | Err(_) => Curve::Sine,
+            Ok("linear") => Curve::Linear,
+            Ok(x) => match x.parse::<f64>() {
+                Ok(exponent) if exponent > 0.0 => Curve::Power(exponent),
+                _ => panic!(
+                    "Invalid CIRCADIAN_CURVE {}, expected sine, linear or a positive exponent",
+                    x
+                ),
+            },
+        };
+        Some(Self {
+            elevation: range("CIRCADIAN_ELEVATION", "-6,30"),
+            kelvin,
+            brightness,
+            curve,
+        })
+    }
+
+    /// Progress from night to full day in `[0, 1]` at `altitude` degrees.
+    fn progress(&self, altitude: f64) -> f64 {
+        let (low, high) = self.elevation;
+        let linear = ((altitude - low) / (high - low)).clamp(0.0, 1.0);
+        match self.curve {
+            Curve::Linear => linear,
+            Curve::Sine => (1.0 - (linear * std::f64::consts::PI).cos()) / 2.0,
+            Curve::Power(exponent) => linear.powf(exponent),
+        }
+    }
+
+    /// Message of `sun/circadian` for the sun at `altitude` degrees, with
+    /// the colour temperature in K and in mired and the brightness in
+    /// percent.
+    pub fn message(&self, altitude: f64) -> serde_json::Value {
+        let progress = self.progress(altitude);
+        let kelvin = self.kelvin.0 + (self.kelvin.1 - self.kelvin.0) * progress;
+        let brightness = self.brightness.0 + (self.brightness.1 - self.brightness.0) * progress;
+        serde_json::json!({
+            "kelvin": kelvin.round(),
+            "mired": (1e6 / kelvin).round(),
+            "brightness": brightness.round(),
+        })
+    }
+}
@@ -0,0 +1,58 @@
+//! Clear-sky atmospheric models driven by the solar altitude.
+
+/// Relative optical air mass for the sun at `altitude` degrees, using the
+/// Kasten–Young (1989) formula. There is no direct light path, hence no air
+/// mass, while the sun is below the horizon.
+pub fn air_mass(altitude: f64) -> Option<f64> {
+    if altitude <= 0.0 {
+        return None;
+    }
+    let zenith = 90.0 - altitude;
+    Some(1.0 / (zenith.to_radians().cos() + 0.50572 * (96.07995 - zenith).powf(-1.6364)))
+}
+
+/// Clear-sky direct normal irradiance in W/m², using the Meinel model.
+pub fn direct_normal_irradiance(altitude: f64) -> f64 {
+    air_mass(altitude)
+        .map(|am| 1353.0 * 0.7f64.powf(am.powf(0.678)))
+        .unwrap_or(0.0)
+}
+
+/// Clear-sky diffuse irradiance on a horizontal surface in W/m², roughly a
+/// tenth of the direct component.
+pub fn diffuse_horizontal_irradiance(altitude: f64) -> f64 {
+    0.1 * direct_normal_irradiance(altitude)
+}
+
+/// Clear-sky global irradiance on a horizontal surface in W/m².
+pub fn global_horizontal_irradiance(altitude: f64) -> f64 {
+    direct_normal_irradiance(altitude) * altitude.to_radians().sin().max(0.0)
+        + diffuse_horizontal_irradiance(altitude)
+}
+
+/// Approximate luminous efficacy of daylight, in lm/W.
+const LUMINOUS_EFFICACY: f64 = 110.0;
+/// Decades of illuminance lost for every degree of solar depression during
+/// twilight.
+const TWILIGHT_DECADES_PER_DEGREE: f64 = 0.36;
+/// Illuminance of a moonless night sky, in lux.
+const NIGHT_SKY_ILLUMINANCE: f64 = 0.001;
+
+/// Clear-sky outdoor illuminance on a horizontal surface in lux. While the
+/// sun is below the horizon, the illuminance at sunrise decays exponentially
+/// with the solar depression down to the night sky level.
+pub fn illuminance(altitude: f64) -> f64 {
+    let daylight = LUMINOUS_EFFICACY * global_horizontal_irradiance(altitude.max(0.01));
+    let twilight = 10f64.powf(TWILIGHT_DECADES_PER_DEGREE * altitude.min(0.0));
+    (daylight * twilight).max(NIGHT_SKY_ILLUMINANCE)
+}
+
+/// Clear-sky UV index for the sun at `altitude` degrees with a total ozone
+/// column of `ozone` Dobson units, from the Madronich (2007) approximation.
+pub fn uv_index(altitude: f64, ozone: f64) -> f64 {
+    let cos_zenith = altitude.to_radians().sin();
+    if cos_zenith <= 0.0 {
+        return 0.0;
+    }
+    12.5 * cos_zenith.powf(2.42) * (ozone / 300.0).powf(-1.23)
+}
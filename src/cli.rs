@@ -0,0 +1,75 @@
+//! Command-line arguments. Options given here take precedence over the
+//! environment, which in turn overrides the configuration file.
+
+use clap::{Parser, Subcommand};
+
+/// Publishes the position of the sun and the phases of the day over MQTT.
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+    /// TOML configuration file
+    #[arg(long, global = true)]
+    config: Option<String>,
+    /// Latitude in degrees, positive north
+    #[arg(long, allow_negative_numbers = true, global = true)]
+    lat: Option<f64>,
+    /// Longitude in degrees, positive east
+    #[arg(long, allow_negative_numbers = true, global = true)]
+    lon: Option<f64>,
+    /// Maidenhead grid locator, instead of the coordinates
+    #[arg(long, global = true, conflicts_with_all = ["lat", "lon"])]
+    grid: Option<String>,
+    /// Host name of the MQTT broker, or a URI such as wss://example.com/mqtt
+    #[arg(long, global = true)]
+    broker: Option<String>,
+    /// Port of the MQTT broker
+    #[arg(long, global = true)]
+    port: Option<u16>,
+    /// Topic the phase events are published on
+    #[arg(long, global = true)]
+    topic: Option<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Query the events and the samples recorded in the event log
+    Log(crate::event_log::LogArgs),
+    /// Show a live dashboard in the terminal
+    Watch,
+}
+
+impl Cli {
+    /// Parses the arguments, exporting the options as the environment
+    /// variables they stand for and loading the configuration file.
+    pub fn from_args() -> Self {
+        let cli = Self::parse();
+        let options = [
+            ("LAT", cli.lat.map(|x| x.to_string())),
+            ("LON", cli.lon.map(|x| x.to_string())),
+            ("GRID", cli.grid.clone()),
+            ("MQTT_BROKER", cli.broker.clone()),
+            ("MQTT_PORT", cli.port.map(|x| x.to_string())),
+            ("EVENT_TOPIC", cli.topic.clone()),
+        ];
+        // The coordinates and the grid locator given here replace each other
+        // in the environment
+        if cli.lat.is_some() || cli.lon.is_some() {
+            std::env::remove_var("GRID");
+        }
+        if cli.grid.is_some() {
+            std::env::remove_var("LAT");
+            std::env::remove_var("LON");
+        }
+        for (name, value) in options.iter() {
+            if let Some(value) = value {
+                std::env::set_var(name, value);
+            }
+        }
+        if let Some(path) = &cli.config {
+            crate::config::load(path);
+        }
+        cli
+    }
+}
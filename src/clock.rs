@@ -0,0 +1,43 @@
+//! Detection of the system clock being stepped, by NTP or by hand, which
+//! makes the schedule computed so far and the sleeps derived from it wrong.
+
+use std::time::{Duration, Instant};
+
+pub struct JumpDetector {
+    /// Jumps smaller than this are ignored
+    threshold: Duration,
+    /// Monotonic and wall clock at the previous check
+    last: Option<(Instant, Duration)>,
+}
+
+impl JumpDetector {
+    /// Reads the smallest jump worth a recomputation in seconds from
+    /// `CLOCK_JUMP_THRESHOLD`, default 30.
+    pub fn from_env() -> Self {
+        let threshold = Duration::from_secs(
+            std::env::var("CLOCK_JUMP_THRESHOLD")
+                .map(|x| x.parse().expect("Invalid CLOCK_JUMP_THRESHOLD"))
+                .unwrap_or(30),
+        );
+        assert!(
+            !threshold.is_zero(),
+            "The CLOCK_JUMP_THRESHOLD must be positive"
+        );
+        Self {
+            threshold,
+            last: None,
+        }
+    }
+
+    /// Feeds the wall clock as time since the Unix epoch, returning the
+    /// seconds it jumped by since the previous call, negative if backwards,
+    /// when they reach the threshold.
+    pub fn check(&mut self, wall: Duration) -> Option<f64> {
+        let now = Instant::now();
+        let last = self.last.replace((now, wall));
+        let (instant, previous) = last?;
+        let expected = previous + now.duration_since(instant);
+        let jump = wall.as_secs_f64() - expected.as_secs_f64();
+        Some(jump).filter(|x| x.abs() >= self.threshold.as_secs_f64())
+    }
+}
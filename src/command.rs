@@ -0,0 +1,19 @@
+//! Plain text commands published on [`TOPIC`].
+
+pub const TOPIC: &str = "sun/cmd";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    /// Publish the current phase, altitude and upcoming events right away,
+    /// for subscribers that need to resynchronise
+    Refresh,
+}
+
+impl Command {
+    pub fn parse(payload: &[u8]) -> Result<Self, String> {
+        match String::from_utf8_lossy(payload).trim() {
+            "refresh" => Ok(Self::Refresh),
+            x => Err(format!("unknown command `{}`", x)),
+        }
+    }
+}
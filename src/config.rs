@@ -0,0 +1,773 @@
+//! Configuration file, given with `--config`, as an alternative to the
+//! environment variables.
+//!
+//! Every setting in the file is exported as the environment variable the
+//! rest of the daemon reads, unless that variable is already set, so the
+//! environment overrides the file. For example
+//!
+//! ```toml
+//! [location]
+//! lat = 44.5
+//! lon = 11.3
+//! # Used in the topic template
+//! name = "home"
+//! # Metres above sea level, for the dip of the horizon
+//! elevation = 120
+//! # Local times in this zone rather than in the one of the system
+//! timezone = "Europe/Rome"
+//! # Km to move before following the device on topics.owntracks
+//! owntracks_min_distance = 10
+//!
+//! # Or several locations, published under sun/office/... and so on
+//! [locations.office]
+//! lat = 44.49
+//! lon = 11.34
+//! [locations.cabin]
+//! grid = "JN56ab"
+//!
+//! [mqtt]
+//! broker = "broker.local"
+//! port = 8883
+//! # Tried in turn when the broker above keeps failing
+//! fallback_brokers = ["backup.local", "cloud.example.com:8884"]
+//! failover_attempts = 5
+//! client_id = "sun_home"
+//! client_id_suffix = true
+//! tls = true
+//! # Or "websocket", reaching the broker at wss://broker.local:8883/mqtt
+//! transport = "tcp"
+//! ca_cert = "/etc/mqtt_sun/ca.pem"
+//! client_cert = "/etc/mqtt_sun/client.pem"
+//! client_key = "/etc/mqtt_sun/client.key"
+//! username = "sun"
+//! password_file = "/etc/mqtt_sun.password"
+//! qos_events = 1
+//! qos_telemetry = 0
+//! # Retain the phase and the other states for new subscribers
+//! retain_state = true
+//! # Do not announce again at startup the phase retained on the broker
+//! read_retained_state = true
+//! # Events kept while the broker is unreachable
+//! event_buffer_size = 500
+//! # JSON objects with the time and the position of the sun
+//! payload_format = "json"
+//! # Or strings of their own, for consumers expecting a given format
+//! payload_event_template = "SUN {{event}} {{iso_time}}"
+//! payload_value_template = "{{value}}"
+//! # The phase as an integer on sun/code too
+//! phase_codes = true
+//! # Seconds between messages on the heartbeat topic
+//! heartbeat_interval = 300
+//!
+//! [topics]
+//! # Publishes sun/info as house/home/info
+//! prefix = "house"
+//! # And moon/illumination as sky/home/illumination
+//! moon_prefix = "sky"
+//! template = "{prefix}/{location}/{event}"
+//! lux_sensor = "garden/lux"
+//! owntracks = "owntracks/user/phone"
+//! availability = "sun/availability"
+//!
+//! # Names of the phases in the payloads
+//! [event_names]
+//! night = "NIGHT"
+//! sunrise = "DAY"
+//!
+//! # Messages at fixed offsets from the sun events
+//! [[offset_events]]
+//! event = "sunset"
+//! minutes = -30
+//! topic = "home/blinds"
+//! payload = "close"
+//!
+//! [home_assistant]
+//! discovery = true
+//! node_id = "sun_home"
+//!
+//! [homie]
+//! enabled = true
+//! device_id = "sun"
+//!
+//! [logging]
+//! level = "debug"
+//! syslog = false
+//!
+//! # What was published, so that restarts do not announce it again
+//! [state]
+//! file = "/var/lib/mqtt_sun/state.json"
+//! # Announce what happened while down on sun/missed, or also one by one
+//! # on sun/late with "late"
+//! missed_events = "summary"
+//!
+//! # Hills around the garden, as azimuth and elevation in degrees, or a CSV
+//! # file of them with file = "horizon.csv"
+//! [horizon]
+//! profile = [[0, 5], [90, 12], [180, 3], [270, 8]]
+//!
+//! # Clear-sky estimate of a photovoltaic array on sun/pv_estimate
+//! [pv]
+//! azimuth = 180
+//! tilt = 30
+//! peak_power = 3000
+//! losses = 0.14
+//! # Or, without the peak power, the area in m² and the panel efficiency
+//! # area = 16
+//! # efficiency = 0.2
+//!
+//! # Setpoints for a solar tracker every minute, stowed flat at night
+//! [tracker]
+//! interval = 60
+//! min_elevation = 5
+//! stow = [180, 90]
+//!
+//! # Colour temperature and brightness for adaptive lighting
+//! [circadian]
+//! enabled = true
+//! kelvin = [2200, 5500]
+//! brightness = [20, 100]
+//! elevation = [-6, 30]
+//! curve = "linear"
+//!
+//! # Shadow of a 2 m pergola
+//! [shadow]
+//! height = 2
+//!
+//! # Prayer times on sun/prayer, with the depressions at Fajr and Isha
+//! # optionally replacing those of the method
+//! [prayer]
+//! method = "mwl"
+//! angles = [18, 17]
+//! asr = "hanafi"
+//!
+//! # The NREL Solar Position Algorithm instead of the default approximation
+//! [ephemeris]
+//! backend = "spa"
+//! # Atmosphere for the refraction, in hPa and °C
+//! pressure = 1000
+//! temperature = 15
+//!
+//! # Any other setting, by the name of its environment variable
+//! [env]
+//! UPCOMING_EVENTS = 3
+//! ```
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    location: Location,
+    locations: BTreeMap<String, NamedLocation>,
+    mqtt: Mqtt,
+    topics: Topics,
+    event_names: BTreeMap<String, String>,
+    offset_events: Vec<OffsetEvent>,
+    home_assistant: HomeAssistant,
+    homie: Homie,
+    logging: Logging,
+    state: State,
+    horizon: Horizon,
+    pv: Pv,
+    tracker: Tracker,
+    circadian: Circadian,
+    shadow: Shadow,
+    prayer: Prayer,
+    ephemeris: Ephemeris,
+    env: BTreeMap<String, toml::Value>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Location {
+    lat: Option<f64>,
+    lon: Option<f64>,
+    grid: Option<String>,
+    name: Option<String>,
+    elevation: Option<f64>,
+    timezone: Option<String>,
+    owntracks_min_distance: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct NamedLocation {
+    lat: Option<f64>,
+    lon: Option<f64>,
+    grid: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OffsetEvent {
+    event: String,
+    minutes: i64,
+    topic: String,
+    payload: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Mqtt {
+    broker: Option<String>,
+    port: Option<u16>,
+    fallback_brokers: Option<Vec<String>>,
+    failover_attempts: Option<u32>,
+    client_id: Option<String>,
+    client_id_suffix: Option<bool>,
+    tls: Option<bool>,
+    transport: Option<String>,
+    ws_path: Option<String>,
+    ca_cert: Option<String>,
+    client_cert: Option<String>,
+    client_key: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    password_file: Option<String>,
+    qos_events: Option<u8>,
+    qos_telemetry: Option<u8>,
+    qos_state: Option<u8>,
+    retain_state: Option<bool>,
+    read_retained_state: Option<bool>,
+    event_buffer_size: Option<usize>,
+    payload_format: Option<String>,
+    payload_event_template: Option<String>,
+    payload_value_template: Option<String>,
+    phase_codes: Option<bool>,
+    heartbeat_interval: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Topics {
+    prefix: Option<String>,
+    moon_prefix: Option<String>,
+    template: Option<String>,
+    lux_sensor: Option<String>,
+    owntracks: Option<String>,
+    availability: Option<String>,
+    birth: Option<String>,
+    heartbeat: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct HomeAssistant {
+    discovery: Option<bool>,
+    prefix: Option<String>,
+    node_id: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Homie {
+    enabled: Option<bool>,
+    device_id: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Logging {
+    level: Option<String>,
+    syslog: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct State {
+    file: Option<String>,
+    missed_events: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Horizon {
+    profile: Option<Vec<(f64, f64)>>,
+    file: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Pv {
+    azimuth: Option<f64>,
+    tilt: Option<f64>,
+    peak_power: Option<f64>,
+    area: Option<f64>,
+    efficiency: Option<f64>,
+    losses: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Tracker {
+    interval: Option<u64>,
+    min_elevation: Option<f64>,
+    stow: Option<(f64, f64)>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Circadian {
+    enabled: Option<bool>,
+    kelvin: Option<(f64, f64)>,
+    brightness: Option<(f64, f64)>,
+    elevation: Option<(f64, f64)>,
+    curve: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Shadow {
+    height: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Prayer {
+    method: Option<String>,
+    angles: Option<(f64, f64)>,
+    asr: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Ephemeris {
+    backend: Option<String>,
+    pressure: Option<f64>,
+    temperature: Option<f64>,
+}
+
+impl Config {
+    /// Checks the values and returns the environment variables they map to.
+    fn variables(self) -> Result<Vec<(String, String)>, String> {
+        let mut variables = Vec::new();
+        let mut set = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                variables.push((name.to_owned(), value));
+            }
+        };
+        let location = self.location;
+        if let Some(lat) = location.lat.filter(|x| !(-90.0..=90.0).contains(x)) {
+            return Err(format!("location.lat {} is not between -90 and 90", lat));
+        }
+        if let Some(lon) = location.lon.filter(|x| !(-180.0..=180.0).contains(x)) {
+            return Err(format!("location.lon {} is not between -180 and 180", lon));
+        }
+        if location.lat.is_some() != location.lon.is_some() {
+            return Err("location.lat and location.lon must be given together".to_owned());
+        }
+        if location.grid.is_some() && location.lat.is_some() {
+            return Err(
+                "location.grid cannot be given with location.lat and location.lon".to_owned(),
+            );
+        }
+        if let Some(grid) = &location.grid {
+            crate::location::from_maidenhead(grid)
+                .map_err(|e| format!("location.grid {}: {}", grid, e))?;
+        }
+        set("LAT", location.lat.map(|x| x.to_string()));
+        set("LON", location.lon.map(|x| x.to_string()));
+        set("GRID", location.grid);
+        set("LOCATION_NAME", location.name);
+        set(
+            "OBSERVER_ELEVATION",
+            location.elevation.map(|x| x.to_string()),
+        );
+        set("TIMEZONE", location.timezone);
+        if let Some(distance) = location.owntracks_min_distance.filter(|x| *x < 0.0) {
+            return Err(format!(
+                "location.owntracks_min_distance {} is negative",
+                distance
+            ));
+        }
+        set(
+            "OWNTRACKS_MIN_DISTANCE",
+            location.owntracks_min_distance.map(|x| x.to_string()),
+        );
+        let mut locations = Vec::new();
+        for (name, location) in self.locations {
+            let location = match (location.lat, location.lon, location.grid) {
+                (Some(lat), Some(lon), None) => format!("{}={},{}", name, lat, lon),
+                (None, None, Some(grid)) => format!("{}={}", name, grid),
+                _ => {
+                    return Err(format!(
+                        "locations.{} needs either lat and lon or grid",
+                        name
+                    ))
+                }
+            };
+            locations.push(location);
+        }
+        let locations = locations.join(";");
+        crate::location::parse_named(&locations).map_err(|e| format!("locations: {}", e))?;
+        set("LOCATIONS", Some(locations).filter(|x| !x.is_empty()));
+
+        if self.mqtt.port == Some(0) {
+            return Err("mqtt.port must not be 0".to_owned());
+        }
+        set("MQTT_BROKER", self.mqtt.broker);
+        set("MQTT_PORT", self.mqtt.port.map(|x| x.to_string()));
+        if let Some(broker) = self
+            .mqtt
+            .fallback_brokers
+            .iter()
+            .flatten()
+            .find(|x| x.is_empty() || x.contains(',') || x.contains("://"))
+        {
+            return Err(format!(
+                "mqtt.fallback_brokers {} is not a host or host:port",
+                broker
+            ));
+        }
+        set(
+            "MQTT_FALLBACK_BROKERS",
+            self.mqtt.fallback_brokers.map(|x| x.join(",")),
+        );
+        if self.mqtt.failover_attempts == Some(0) {
+            return Err("mqtt.failover_attempts must not be 0".to_owned());
+        }
+        set(
+            "MQTT_FAILOVER_ATTEMPTS",
+            self.mqtt.failover_attempts.map(|x| x.to_string()),
+        );
+        if self.mqtt.client_id.as_deref() == Some("") {
+            return Err("mqtt.client_id must not be empty".to_owned());
+        }
+        set("MQTT_CLIENT_ID", self.mqtt.client_id);
+        set(
+            "MQTT_CLIENT_ID_SUFFIX",
+            self.mqtt.client_id_suffix.map(|x| x.to_string()),
+        );
+        if (self.mqtt.ca_cert.is_some() || self.mqtt.client_cert.is_some())
+            && self.mqtt.tls != Some(true)
+        {
+            return Err("mqtt.ca_cert and mqtt.client_cert require mqtt.tls = true".to_owned());
+        }
+        if self.mqtt.client_cert.is_some() != self.mqtt.client_key.is_some() {
+            return Err("mqtt.client_cert and mqtt.client_key must be given together".to_owned());
+        }
+        set("MQTT_TLS", self.mqtt.tls.map(|x| x.to_string()));
+        match self.mqtt.transport.as_deref() {
+            None | Some("tcp") | Some("websocket") => {}
+            Some(x) => return Err(format!("mqtt.transport {} is neither tcp nor websocket", x)),
+        }
+        if self.mqtt.ws_path.is_some() && self.mqtt.transport.as_deref() != Some("websocket") {
+            return Err("mqtt.ws_path requires mqtt.transport = \"websocket\"".to_owned());
+        }
+        set("MQTT_TRANSPORT", self.mqtt.transport);
+        set("MQTT_WS_PATH", self.mqtt.ws_path);
+        set("MQTT_CA_CERT", self.mqtt.ca_cert);
+        set("MQTT_CLIENT_CERT", self.mqtt.client_cert);
+        set("MQTT_CLIENT_KEY", self.mqtt.client_key);
+        if self.mqtt.username.is_none()
+            && (self.mqtt.password.is_some() || self.mqtt.password_file.is_some())
+        {
+            return Err("mqtt.password requires mqtt.username".to_owned());
+        }
+        set("MQTT_USERNAME", self.mqtt.username);
+        set("MQTT_PASSWORD", self.mqtt.password);
+        set("MQTT_PASSWORD_FILE", self.mqtt.password_file);
+        for (key, name, qos) in [
+            ("qos_events", "MQTT_QOS_EVENTS", self.mqtt.qos_events),
+            (
+                "qos_telemetry",
+                "MQTT_QOS_TELEMETRY",
+                self.mqtt.qos_telemetry,
+            ),
+            ("qos_state", "MQTT_QOS_STATE", self.mqtt.qos_state),
+        ] {
+            if let Some(qos) = qos.filter(|x| *x > 2) {
+                return Err(format!("mqtt.{} {} is not 0, 1 or 2", key, qos));
+            }
+            set(name, qos.map(|x| x.to_string()));
+        }
+        set(
+            "RETAIN_STATE",
+            self.mqtt.retain_state.map(|x| x.to_string()),
+        );
+        set(
+            "READ_RETAINED_STATE",
+            self.mqtt.read_retained_state.map(|x| x.to_string()),
+        );
+        set(
+            "EVENT_BUFFER_SIZE",
+            self.mqtt.event_buffer_size.map(|x| x.to_string()),
+        );
+        match self.mqtt.payload_format.as_deref() {
+            None | Some("plain") | Some("json") => {}
+            Some(x) => {
+                return Err(format!(
+                    "mqtt.payload_format {} is neither plain nor json",
+                    x
+                ))
+            }
+        }
+        set("PAYLOAD_FORMAT", self.mqtt.payload_format);
+        set("PAYLOAD_EVENT_TEMPLATE", self.mqtt.payload_event_template);
+        set("PAYLOAD_VALUE_TEMPLATE", self.mqtt.payload_value_template);
+        set("PHASE_CODES", self.mqtt.phase_codes.map(|x| x.to_string()));
+
+        if let Some(template) = self
+            .topics
+            .template
+            .as_ref()
+            .filter(|x| !x.contains("{event}"))
+        {
+            return Err(format!(
+                "topics.template {} does not contain {{event}}",
+                template
+            ));
+        }
+        set("TOPIC_PREFIX", self.topics.prefix);
+        set("MOON_TOPIC_PREFIX", self.topics.moon_prefix);
+        set("TOPIC_TEMPLATE", self.topics.template);
+        set("LUX_SENSOR_TOPIC", self.topics.lux_sensor);
+        set("OWNTRACKS_TOPIC", self.topics.owntracks);
+        set("AVAILABILITY_TOPIC", self.topics.availability);
+        set("BIRTH_TOPIC", self.topics.birth);
+        set("HEARTBEAT_TOPIC", self.topics.heartbeat);
+
+        if let Some((event, _)) = self.event_names.iter().find(|(_, x)| x.contains(',')) {
+            return Err(format!("event_names.{} contains a comma", event));
+        }
+        let names = self
+            .event_names
+            .iter()
+            .map(|(event, name)| format!("{}={}", event, name))
+            .collect::<Vec<_>>()
+            .join(",");
+        crate::payload::parse_names(&names).map_err(|e| format!("event_names: {}", e))?;
+        set("EVENT_NAMES", Some(names).filter(|x| !x.is_empty()));
+
+        let mut offset_events = Vec::new();
+        for event in self.offset_events {
+            if event.topic.contains(':') || event.payload.contains(';') {
+                return Err(format!(
+                    "offset_events {}: the topic must not contain : nor the payload ;",
+                    event.payload
+                ));
+            }
+            let event = format!(
+                "{}{:+}:{}:{}",
+                event.event, event.minutes, event.topic, event.payload
+            );
+            event
+                .parse::<crate::offsets::OffsetEvent>()
+                .map_err(|e| format!("offset_events: {}", e))?;
+            offset_events.push(event);
+        }
+        let offset_events = offset_events.join(";");
+        set(
+            "OFFSET_EVENTS",
+            Some(offset_events).filter(|x| !x.is_empty()),
+        );
+
+        set(
+            "HA_DISCOVERY",
+            self.home_assistant.discovery.map(|x| x.to_string()),
+        );
+        set("HA_DISCOVERY_PREFIX", self.home_assistant.prefix);
+        set("HA_NODE_ID", self.home_assistant.node_id);
+        set("HOMIE", self.homie.enabled.map(|x| x.to_string()));
+        set("HOMIE_DEVICE_ID", self.homie.device_id);
+
+        if let Some(level) = &self.logging.level {
+            level
+                .parse::<log::LevelFilter>()
+                .map_err(|_| format!("logging.level {} is not a log level", level))?;
+        }
+        set("LOG_LEVEL", self.logging.level);
+        set("LOG_SYSLOG", self.logging.syslog.map(|x| x.to_string()));
+
+        match self.state.missed_events.as_deref() {
+            None | Some("summary") | Some("late") | Some("off") => {}
+            Some(x) => {
+                return Err(format!(
+                    "state.missed_events {} is not summary, late or off",
+                    x
+                ))
+            }
+        }
+        set("STATE_FILE", self.state.file);
+        set("MISSED_EVENTS", self.state.missed_events);
+
+        if self.horizon.profile.is_some() && self.horizon.file.is_some() {
+            return Err("horizon.profile and horizon.file cannot be given together".to_owned());
+        }
+        if let Some(profile) = self.horizon.profile {
+            let profile = profile
+                .iter()
+                .map(|(azimuth, elevation)| format!("{}:{}", azimuth, elevation))
+                .collect::<Vec<_>>()
+                .join(",");
+            crate::horizon::Horizon::parse(&profile)
+                .map_err(|e| format!("horizon.profile: {}", e))?;
+            set("HORIZON_PROFILE", Some(profile));
+        }
+        set("HORIZON_PROFILE_FILE", self.horizon.file);
+
+        if let Some(peak_power) = self.pv.peak_power.filter(|x| *x <= 0.0) {
+            return Err(format!("pv.peak_power {} is not positive", peak_power));
+        }
+        if let Some(area) = self.pv.area.filter(|x| *x <= 0.0) {
+            return Err(format!("pv.area {} is not positive", area));
+        }
+        if let Some(efficiency) = self.pv.efficiency.filter(|x| !(*x > 0.0 && *x <= 1.0)) {
+            return Err(format!(
+                "pv.efficiency {} is not between 0 and 1",
+                efficiency
+            ));
+        }
+        if let Some(losses) = self.pv.losses.filter(|x| !(0.0..1.0).contains(x)) {
+            return Err(format!("pv.losses {} is not between 0 and 1", losses));
+        }
+        set("PV_AZIMUTH", self.pv.azimuth.map(|x| x.to_string()));
+        set("PV_TILT", self.pv.tilt.map(|x| x.to_string()));
+        set("PV_PEAK_POWER", self.pv.peak_power.map(|x| x.to_string()));
+        set("PV_AREA", self.pv.area.map(|x| x.to_string()));
+        set("PV_EFFICIENCY", self.pv.efficiency.map(|x| x.to_string()));
+        set("PV_LOSSES", self.pv.losses.map(|x| x.to_string()));
+
+        if self.tracker.interval == Some(0) {
+            return Err("tracker.interval must be positive".to_owned());
+        }
+        if let Some((_, elevation)) = self
+            .tracker
+            .stow
+            .filter(|(_, x)| !(-90.0..=90.0).contains(x))
+        {
+            return Err(format!(
+                "tracker.stow elevation {} is not between -90 and 90",
+                elevation
+            ));
+        }
+        set(
+            "TRACKER_INTERVAL",
+            self.tracker.interval.map(|x| x.to_string()),
+        );
+        set(
+            "TRACKER_MIN_ELEVATION",
+            self.tracker.min_elevation.map(|x| x.to_string()),
+        );
+        set(
+            "TRACKER_STOW",
+            self.tracker
+                .stow
+                .map(|(azimuth, elevation)| format!("{},{}", azimuth, elevation)),
+        );
+
+        set("CIRCADIAN", self.circadian.enabled.map(|x| x.to_string()));
+        for (name, key, range) in [
+            ("CIRCADIAN_KELVIN", "kelvin", self.circadian.kelvin),
+            (
+                "CIRCADIAN_BRIGHTNESS",
+                "brightness",
+                self.circadian.brightness,
+            ),
+            ("CIRCADIAN_ELEVATION", "elevation", self.circadian.elevation),
+        ] {
+            if let Some((low, high)) = range.filter(|(low, high)| low >= high) {
+                return Err(format!(
+                    "circadian.{} {}, {} is not increasing",
+                    key, low, high
+                ));
+            }
+            set(name, range.map(|(low, high)| format!("{},{}", low, high)));
+        }
+        set("CIRCADIAN_CURVE", self.circadian.curve);
+
+        if let Some(height) = self.shadow.height.filter(|x| *x <= 0.0) {
+            return Err(format!("shadow.height {} is not positive", height));
+        }
+        set(
+            "SHADOW_OBJECT_HEIGHT",
+            self.shadow.height.map(|x| x.to_string()),
+        );
+
+        match self.prayer.method.as_deref() {
+            None | Some("mwl") | Some("isna") | Some("egypt") | Some("makkah")
+            | Some("karachi") | Some("tehran") => {}
+            Some(x) => return Err(format!("prayer.method {} is not a known method", x)),
+        }
+        match self.prayer.asr.as_deref() {
+            None | Some("standard") | Some("hanafi") => {}
+            Some(x) => return Err(format!("prayer.asr {} is neither standard nor hanafi", x)),
+        }
+        if let Some((fajr, isha)) = self.prayer.angles.filter(|(x, y)| *x <= 0.0 || *y <= 0.0) {
+            return Err(format!(
+                "prayer.angles {}, {} must be positive depressions",
+                fajr, isha
+            ));
+        }
+        set("PRAYER_METHOD", self.prayer.method);
+        set(
+            "PRAYER_ANGLES",
+            self.prayer
+                .angles
+                .map(|(fajr, isha)| format!("{},{}", fajr, isha)),
+        );
+        set("PRAYER_ASR", self.prayer.asr);
+
+        match self.ephemeris.backend.as_deref() {
+            None | Some("simple") | Some("spa") => {}
+            Some(x) => return Err(format!("ephemeris.backend {} is neither simple nor spa", x)),
+        }
+        set("SOLAR_BACKEND", self.ephemeris.backend);
+        if let Some(pressure) = self.ephemeris.pressure.filter(|x| *x <= 0.0) {
+            return Err(format!("ephemeris.pressure {} is not positive", pressure));
+        }
+        set(
+            "ATMOSPHERIC_PRESSURE",
+            self.ephemeris.pressure.map(|x| x.to_string()),
+        );
+        set(
+            "AIR_TEMPERATURE",
+            self.ephemeris.temperature.map(|x| x.to_string()),
+        );
+
+        for (name, value) in self.env {
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+            {
+                return Err(format!("env.{} is not an environment variable name", name));
+            }
+            let value = match value {
+                toml::Value::String(x) => x,
+                toml::Value::Integer(x) => x.to_string(),
+                toml::Value::Float(x) => x.to_string(),
+                toml::Value::Boolean(x) => x.to_string(),
+                _ => return Err(format!("env.{} must be a string, number or boolean", name)),
+            };
+            set(&name, Some(value));
+        }
+        Ok(variables)
+    }
+}
+
+/// Variables giving the same setting in different ways.
+const ALTERNATIVES: [&[&str]; 1] = [&["LAT", "LON", "GRID"]];
+
+/// Loads the file at `path` into the environment, without replacing the
+/// variables that are already set.
+pub fn load(path: &str) {
+    let text = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Could not read the configuration {}: {}", path, e));
+    let variables = toml::from_str::<Config>(&text)
+        .map_err(|e| e.to_string())
+        .and_then(Config::variables)
+        .unwrap_or_else(|e| panic!("Invalid configuration {}: {}", path, e));
+    // Any of them in the environment overrides all of them in the file
+    let overridden: Vec<_> = ALTERNATIVES
+        .iter()
+        .filter(|group| group.iter().any(|name| std::env::var_os(name).is_some()))
+        .flat_map(|group| group.iter())
+        .collect();
+    for (name, value) in variables {
+        if std::env::var_os(&name).is_none() && !overridden.contains(&&name.as_str()) {
+            std::env::set_var(name, value);
+        }
+    }
+}
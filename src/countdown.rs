@@ -0,0 +1,137 @@
+//! Minutes left until chosen phases on `sun/countdown/<event>`, named as on
+//! the `sun` topic, for displays showing the time until the sun goes down
+//! (`civilDusk`).
+
+use crate::phase::{SunPosition, Thresholds};
+use crate::schedule;
+
+pub struct Countdown {
+    events: Vec<SunPosition>,
+    /// Seconds before each event during which the countdown runs
+    window: i64,
+    /// Minutes last published for each event, while its countdown runs
+    last: Vec<Option<i64>>,
+}
+
+impl Countdown {
+    /// Counts down to the comma separated `COUNTDOWN_EVENTS`, such as
+    /// `civilDusk,night`, during the last `COUNTDOWN_WINDOW` minutes
+    /// (default 60) before each.
+    pub fn from_env() -> Option<Self> {
+        let events: Vec<_> = std::env::var("COUNTDOWN_EVENTS")
+            .ok()?
+            .split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .map(|name| {
+                *SunPosition::ALL
+                    .iter()
+                    .find(|x| <&'static str>::from(*x) == name)
+                    .unwrap_or_else(|| panic!("Invalid event {} in COUNTDOWN_EVENTS", name))
+            })
+            .collect();
+        let window: i64 = std::env::var("COUNTDOWN_WINDOW")
+            .map(|x| x.parse().expect("Invalid COUNTDOWN_WINDOW"))
+            .unwrap_or(60);
+        assert!(window > 0, "COUNTDOWN_WINDOW must be positive");
+        Some(Self {
+            last: vec![None; events.len()],
+            events,
+            window: window * 60,
+        })
+    }
+
+    /// Returns the topics and minutes left that changed since the previous
+    /// call, rounding up so that 0 is only published once the event is due.
+    pub fn update(
+        &mut self,
+        now: i64,
+        coords: &astro::coords::GeographPoint,
+        thresholds: &Thresholds,
+    ) -> Vec<(String, i64)> {
+        let upcoming = schedule::phases_between(now, now + self.window + 1, coords, thresholds);
+        let mut changed = Vec::new();
+        for (event, last) in self.events.iter().zip(self.last.iter_mut()) {
+            let minutes = upcoming
+                .iter()
+                .find(|e| e.position == *event)
+                .map(|e| (e.timestamp - now + 59) / 60);
+            let minutes = match (minutes, *last) {
+                (Some(minutes), _) => Some(minutes),
+                // The event just happened
+                (None, Some(_)) => Some(0),
+                (None, None) => None,
+            };
+            if minutes != *last {
+                if let Some(minutes) = minutes {
+                    let name: &'static str = event.into();
+                    changed.push((format!("sun/countdown/{}", name), minutes));
+                }
+            }
+            *last = minutes.filter(|x| *x > 0);
+        }
+        changed
+    }
+
+    /// Time at which the next countdown starts, within a day, so that the
+    /// first value is published on time.
+    pub fn next_start(
+        &self,
+        now: i64,
+        coords: &astro::coords::GeographPoint,
+        thresholds: &Thresholds,
+    ) -> Option<i64> {
+        let from = now + self.window;
+        schedule::phases_between(from, from + 24 * 3600, coords, thresholds)
+            .iter()
+            .find(|e| self.events.contains(&e.position))
+            .map(|e| e.timestamp - self.window)
+    }
+
+    /// Whether a countdown is running, so that it is updated every minute.
+    pub fn running(&self) -> bool {
+        self.last.iter().any(|x| x.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_start() {
+        let coords = astro::coords::GeographPoint {
+            lat: 44.5,
+            long: 11.3,
+        };
+        let thresholds = Thresholds::default();
+        let mut countdown = Countdown {
+            events: vec![SunPosition::CivilDusk],
+            window: 3600,
+            last: vec![None],
+        };
+        // 2021-03-01 at midnight UTC
+        let midnight = 1_614_556_800;
+        let dusk = schedule::phases_between(midnight, midnight + 24 * 3600, &coords, &thresholds)
+            .into_iter()
+            .find(|e| e.position == SunPosition::CivilDusk)
+            .unwrap()
+            .timestamp;
+        let start = countdown
+            .next_start(midnight, &coords, &thresholds)
+            .unwrap();
+        assert_eq!(start, dusk - 3600);
+        assert!(countdown
+            .update(start - 60, &coords, &thresholds)
+            .is_empty());
+        assert_eq!(
+            countdown.update(start + 1, &coords, &thresholds),
+            vec![("sun/countdown/civilDusk".to_owned(), 60)]
+        );
+        assert!(countdown.running());
+        // Inside the window, the next one is tomorrow's at the earliest
+        assert!(countdown
+            .next_start(start + 1, &coords, &thresholds)
+            .is_none_or(|next| next > dusk));
+    }
+}
@@ -0,0 +1,103 @@
+//! Table of sun positions over a whole day, for devices that interpolate it
+//! locally instead of depending on the broker all day.
+//!
+//! The table is published retained on [`CURVE_TOPIC`] every UTC day and on
+//! request: a message on [`REQUEST_TOPIC`], either empty or such as
+//! `{"date": "2021-08-01", "step": 300}`, is answered on `reply_to`
+//! (defaulting to [`RESPONSE_TOPIC`]), a `sun/.../response` topic. The
+//! payload is
+//! `{"date": ..., "step": ..., "samples": [[timestamp, altitude, azimuth], ...]}`
+//! with angles in degrees, rounded to hundredths.
+
+use serde::Deserialize;
+
+pub const CURVE_TOPIC: &str = "sun/curve";
+pub const REQUEST_TOPIC: &str = "sun/cmd/curve";
+pub const RESPONSE_TOPIC: &str = "sun/cmd/curve/response";
+
+/// Default spacing of the samples in seconds.
+const DEFAULT_STEP: i64 = 600;
+/// Finest spacing accepted on request, to bound the payload size.
+const MIN_STEP: i64 = 60;
+
+#[derive(Debug, Default, Deserialize)]
+struct Request {
+    date: Option<String>,
+    step: Option<i64>,
+    reply_to: Option<String>,
+}
+
+fn round(x: f64) -> f64 {
+    (x * 100.0).round() / 100.0
+}
+
+/// Samples every `step` seconds over the UTC day `date`, both ends included.
+pub fn table(
+    date: chrono::NaiveDate,
+    step: i64,
+    coords: &astro::coords::GeographPoint,
+) -> serde_json::Value {
+    let start = date.and_hms(0, 0, 0).timestamp();
+    let samples: Vec<_> = (start..=start + 24 * 3600)
+        .step_by(step as usize)
+        .map(|timestamp| {
+            let position = crate::solar::pos(timestamp * 1000, coords.lat, coords.long);
+            serde_json::json!([
+                timestamp,
+                round(position.altitude.to_degrees()),
+                round(position.azimuth.to_degrees()),
+            ])
+        })
+        .collect();
+    serde_json::json!({
+        "date": date.to_string(),
+        "step": step,
+        "samples": samples,
+    })
+}
+
+pub fn today(coords: &astro::coords::GeographPoint) -> serde_json::Value {
+    table(chrono::Utc::today().naive_utc(), DEFAULT_STEP, coords)
+}
+
+fn answer(
+    request: &Request,
+    coords: &astro::coords::GeographPoint,
+) -> Result<serde_json::Value, String> {
+    let date = match &request.date {
+        Some(date) => chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|e| format!("invalid date `{}`: {}", date, e))?,
+        None => chrono::Utc::today().naive_utc(),
+    };
+    let step = request.step.unwrap_or(DEFAULT_STEP);
+    if step < MIN_STEP {
+        return Err(format!("the step must be at least {} seconds", MIN_STEP));
+    }
+    Ok(table(date, step, coords))
+}
+
+/// Handles a request payload, returning the topic and payload of the reply.
+pub fn handle(payload: &[u8], coords: &astro::coords::GeographPoint) -> (String, String) {
+    let request = if payload.is_empty() {
+        Ok(Request::default())
+    } else {
+        serde_json::from_slice::<Request>(payload)
+    };
+    match request {
+        Ok(request) => match crate::query::reply_topic(request.reply_to.clone(), RESPONSE_TOPIC) {
+            Ok(topic) => {
+                let reply =
+                    answer(&request, coords).unwrap_or_else(|e| serde_json::json!({ "error": e }));
+                (topic, reply.to_string())
+            }
+            Err(e) => (
+                RESPONSE_TOPIC.to_owned(),
+                serde_json::json!({ "error": e }).to_string(),
+            ),
+        },
+        Err(e) => (
+            RESPONSE_TOPIC.to_owned(),
+            serde_json::json!({ "error": format!("invalid request: {}", e) }).to_string(),
+        ),
+    }
+}
@@ -0,0 +1,128 @@
+//! Tonight's window of astronomical darkness with the moon below the
+//! horizon, the time that matters for visual astronomy.
+
+use crate::moon::Moon;
+use crate::phase::{SunPosition, Thresholds};
+use crate::schedule;
+
+const MOON_STEP: i64 = 5 * 60;
+
+fn moon_is_down(timestamp: i64, coords: &astro::coords::GeographPoint) -> bool {
+    Moon::at(timestamp, coords).altitude < 0.0
+}
+
+/// Bisects the instant in `[from, to]` at which `is_down` changes.
+fn refine(mut from: i64, mut to: i64, is_down: &impl Fn(i64) -> bool) -> i64 {
+    let down_at_start = is_down(from);
+    while to - from > 30 {
+        let mid = (from + to) / 2;
+        if is_down(mid) == down_at_start {
+            from = mid;
+        } else {
+            to = mid;
+        }
+    }
+    to
+}
+
+/// Longest stretch of `[start, end]` over which `is_down` holds, sampled
+/// every `MOON_STEP` seconds, including both ends.
+fn longest(start: i64, end: i64, is_down: impl Fn(i64) -> bool) -> Option<(i64, i64)> {
+    let mut best: Option<(i64, i64)> = None;
+    let mut keep = |from: i64, to: i64| {
+        if best.map(|(a, b)| to - from > b - a).unwrap_or(true) {
+            best = Some((from, to));
+        }
+    };
+    let mut window_start = if is_down(start) { Some(start) } else { None };
+    let mut t = start;
+    while t < end {
+        let next = (t + MOON_STEP).min(end);
+        let down = is_down(next);
+        match window_start {
+            None if down => window_start = Some(refine(t, next, &is_down)),
+            Some(from) if !down => {
+                keep(from, refine(t, next, &is_down));
+                window_start = None;
+            }
+            _ => {}
+        }
+        t = next;
+    }
+    // The window may still be open at the end, even if it opened in the
+    // last step
+    if let Some(from) = window_start {
+        keep(from, end);
+    }
+    best
+}
+
+/// Returns the longest moon-free stretch of the first astronomical night
+/// starting within a day from `now` (or already in progress), as start and
+/// end timestamps.
+pub fn tonight(
+    now: i64,
+    coords: &astro::coords::GeographPoint,
+    thresholds: &Thresholds,
+) -> Option<(i64, i64)> {
+    let events = schedule::events_between(now, now + 2 * 24 * 3600, coords, thresholds);
+    let already_dark = crate::solar::pos(now * 1000, coords.lat, coords.long)
+        .altitude
+        .to_degrees()
+        < thresholds.astronomical;
+    let start = if already_dark {
+        now
+    } else {
+        events
+            .iter()
+            .find(|e| e.position == SunPosition::AstronomicalDusk && e.timestamp < now + 24 * 3600)?
+            .timestamp
+    };
+    let end = events
+        .iter()
+        .find(|e| e.position == SunPosition::AstronomicalDawn && e.timestamp > start)?
+        .timestamp;
+
+    longest(start, end, |t| moon_is_down(t, coords))
+}
+
+pub fn to_json(window: Option<(i64, i64)>) -> serde_json::Value {
+    match window {
+        Some((start, end)) => serde_json::json!({
+            "start": start,
+            "end": end,
+            "duration": end - start,
+        }),
+        None => serde_json::json!({
+            "start": null,
+            "end": null,
+            "duration": 0,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_open_at_either_end() {
+        assert_eq!(longest(0, 3600, |_| true), Some((0, 3600)));
+        assert_eq!(longest(0, 3600, |_| false), None);
+        // The moon sets during the last step
+        let window = longest(0, 3600, |t| t >= 3500).unwrap();
+        assert!((3500..=3530).contains(&window.0), "{:?}", window);
+        assert_eq!(window.1, 3600);
+        // And rises during the first one
+        let window = longest(0, 3600, |t| t < 100).unwrap();
+        assert_eq!(window.0, 0);
+        assert!((100..=130).contains(&window.1), "{:?}", window);
+    }
+
+    #[test]
+    fn keeps_the_longest_window() {
+        let window = longest(0, 20_000, |t| !(4_000..15_000).contains(&t)).unwrap();
+        assert!((15_000..=15_030).contains(&window.0), "{:?}", window);
+        assert_eq!(window.1, 20_000);
+    }
+}
@@ -0,0 +1,42 @@
+//! Normalized position within the day, for syncing virtual daylight (such as
+//! a game server's clock) to the real sky.
+//!
+//! The value is 0 at sunrise, 0.5 at sunset and grows linearly in between
+//! and through the night. Where the sun does not rise or set it follows the
+//! local hour angle instead, with noon at 0.25 and midnight at 0.75.
+
+use crate::ephemeris;
+use crate::phase::{SunPosition, Thresholds};
+use crate::schedule;
+
+/// Length of a Minecraft day in ticks, with tick 0 at sunrise.
+pub const TICKS_PER_DAY: f64 = 24000.0;
+
+const SEARCH_SPAN: i64 = 26 * 3600;
+
+pub fn value(now: i64, coords: &astro::coords::GeographPoint, thresholds: &Thresholds) -> f64 {
+    let crossings: Vec<_> =
+        schedule::events_between(now - SEARCH_SPAN, now + SEARCH_SPAN, coords, thresholds)
+            .into_iter()
+            .filter(|e| e.position == SunPosition::Sunrise || e.position == SunPosition::Sunset)
+            .collect();
+    let previous = crossings.iter().rev().find(|e| e.timestamp <= now);
+    let next = crossings.iter().find(|e| e.timestamp > now);
+    match (previous, next) {
+        (Some(previous), Some(next)) if previous.position != next.position => {
+            let progress =
+                (now - previous.timestamp) as f64 / (next.timestamp - previous.timestamp) as f64;
+            let offset = if previous.position == SunPosition::Sunrise {
+                0.0
+            } else {
+                0.5
+            };
+            offset + 0.5 * progress
+        }
+        _ => {
+            let (gha, _) = ephemeris::sun_gha_dec(ephemeris::julian_day(now));
+            let hour_angle = gha + coords.long.to_radians();
+            (hour_angle / std::f64::consts::TAU + 0.25).rem_euclid(1.0)
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! Length of the day on `sun/day_length`, with how much longer or shorter it
+//! is than the day before, in seconds and as text such as `10h41m12s` and
+//! `-2m13s`.
+
+use crate::phase::Thresholds;
+use crate::schedule;
+
+pub const TOPIC: &str = "sun/day_length";
+
+/// Formats `seconds` as `10h41m12s`, leaving out the leading zero units.
+fn text(seconds: i64) -> String {
+    let sign = if seconds < 0 { "-" } else { "" };
+    let seconds = seconds.abs();
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}{}h{}m{}s", sign, hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}{}m{}s", sign, minutes, seconds)
+    } else {
+        format!("{}{}s", sign, seconds)
+    }
+}
+
+/// Length of the local day `date` and its change since the previous one.
+pub fn message(
+    date: chrono::NaiveDate,
+    coords: &astro::coords::GeographPoint,
+    thresholds: &Thresholds,
+) -> serde_json::Value {
+    let seconds = schedule::daylight(date, coords, thresholds);
+    let change = seconds - schedule::daylight(date.pred(), coords, thresholds);
+    serde_json::json!({
+        "date": date.to_string(),
+        "seconds": seconds,
+        "change": change,
+        "text": text(seconds),
+        "change_text": format!("{}{}", if change >= 0 { "+" } else { "" }, text(change)),
+    })
+}
@@ -0,0 +1,145 @@
+//! Home Assistant MQTT discovery, so that the phase, the elevation and the
+//! time of solar noon show up as entities of a single device.
+
+use crate::payload::Payloads;
+use crate::topics::Topics;
+
+pub const SOLAR_NOON_TOPIC: &str = "sun/solar_noon";
+
+pub struct Discovery {
+    prefix: String,
+    node_id: String,
+    name: String,
+}
+
+impl Discovery {
+    /// Enabled by `HA_DISCOVERY`, publishing under `HA_DISCOVERY_PREFIX`
+    /// (default `homeassistant`) for the node `HA_NODE_ID`, which defaults to
+    /// `MQTT_CLIENT_ID` and must be unique among the instances on a broker.
+    pub fn from_env() -> Option<Self> {
+        let enabled = std::env::var("HA_DISCOVERY")
+            .map(|x| {
+                x.parse()
+                    .expect("Invalid HA_DISCOVERY, expected true or false")
+            })
+            .unwrap_or(false);
+        if !enabled {
+            return None;
+        }
+        let node_id = std::env::var("HA_NODE_ID")
+            .or_else(|_| std::env::var("MQTT_CLIENT_ID"))
+            .unwrap_or_else(|_| "mqtt_sun".to_owned());
+        assert!(
+            !node_id.is_empty()
+                && node_id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'),
+            "Invalid HA_NODE_ID {}, expected letters, digits, _ and -",
+            node_id
+        );
+        Some(Self {
+            prefix: std::env::var("HA_DISCOVERY_PREFIX")
+                .unwrap_or_else(|_| "homeassistant".to_owned()),
+            node_id,
+            name: "Sun".to_owned(),
+        })
+    }
+
+    /// Announces the device for the named `location`, with its own node ID.
+    pub fn with_location(self, location: &str) -> Self {
+        Self {
+            node_id: format!("{}_{}", self.node_id, location),
+            name: format!("Sun ({})", location),
+            ..self
+        }
+    }
+
+    /// Returns the retained configuration messages, as topic and payload,
+    /// referring to the built-in topics as mapped by `topics`.
+    pub fn messages(
+        &self,
+        topics: &Topics,
+        payloads: &Payloads,
+        event_topic: &str,
+        availability_topic: &str,
+    ) -> Vec<(String, String)> {
+        let device = serde_json::json!({
+            "identifiers": [self.node_id],
+            "name": self.name,
+            "manufacturer": "mqtt_sun",
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        });
+        let sensors = [
+            (
+                "phase",
+                serde_json::json!({
+                    "name": "Phase",
+                    "state_topic": topics.topic(event_topic),
+                    "icon": "mdi:weather-sunset",
+                }),
+            ),
+            (
+                "elevation",
+                serde_json::json!({
+                    "name": "Elevation",
+                    "state_topic": topics.topic("sun/info"),
+                    "unit_of_measurement": "°",
+                    "state_class": "measurement",
+                    "suggested_display_precision": 1,
+                    "icon": "mdi:angle-acute",
+                }),
+            ),
+            (
+                "solar_noon",
+                serde_json::json!({
+                    "name": "Solar noon",
+                    "state_topic": topics.topic(SOLAR_NOON_TOPIC),
+                    "device_class": "timestamp",
+                    "icon": "mdi:white-balance-sunny",
+                }),
+            ),
+        ];
+        sensors
+            .iter()
+            .map(|(object_id, sensor)| {
+                let mut config = sensor.clone();
+                let template = match *object_id {
+                    "phase" => Some("{{ value_json.event }}"),
+                    "elevation" => Some("{{ value_json.value }}"),
+                    _ => None,
+                };
+                if let (true, Some(template)) = (payloads.is_json(), template) {
+                    config["value_template"] = template.into();
+                }
+                config["unique_id"] = format!("{}_{}", self.node_id, object_id).into();
+                config["availability_topic"] = topics.topic(availability_topic).into();
+                config["device"] = device.clone();
+                (
+                    format!(
+                        "{}/sensor/{}/{}/config",
+                        self.prefix, self.node_id, object_id
+                    ),
+                    config.to_string(),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Time of today's solar noon in RFC 3339, if the sun culminates today.
+pub fn solar_noon(
+    coords: &astro::coords::GeographPoint,
+    thresholds: &crate::phase::Thresholds,
+) -> Option<String> {
+    use chrono::TimeZone;
+    let today = chrono::Local::today().naive_local();
+    crate::schedule::events_between(
+        crate::schedule::local_midnight(today),
+        crate::schedule::local_midnight(today.succ()),
+        coords,
+        thresholds,
+    )
+    .into_iter()
+    .find(|e| e.position == crate::phase::SunPosition::SolarNoon)
+    .map(|e| chrono::Local.timestamp(e.timestamp, 0).to_rfc3339())
+}
@@ -0,0 +1,72 @@
+//! Distance between the sun and the Earth, published daily in astronomical
+//! units on `sun/distance`, and the perihelion and aphelion, announced on
+//! `sun/apsis` as they happen with the next one retained on
+//! `sun/apsis/next`.
+
+use crate::ephemeris;
+
+pub const TOPIC: &str = "sun/distance";
+pub const APSIS_TOPIC: &str = "sun/apsis";
+pub const NEXT_APSIS_TOPIC: &str = "sun/apsis/next";
+
+const DAY: i64 = 86400;
+
+/// Distance between the centres of the sun and of the Earth at `timestamp`
+/// in AU.
+pub fn au(timestamp: i64) -> f64 {
+    astro::sun::geocent_ecl_pos(ephemeris::julian_day(timestamp)).1
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Apsis {
+    /// `perihelion` or `aphelion`
+    pub name: &'static str,
+    /// Unix timestamp in seconds
+    pub timestamp: i64,
+    /// Distance in AU
+    pub distance: f64,
+}
+
+impl Apsis {
+    pub fn to_json(self) -> serde_json::Value {
+        serde_json::json!({
+            "event": self.name,
+            "timestamp": self.timestamp,
+            "distance": self.distance,
+        })
+    }
+}
+
+/// First perihelion or aphelion after `from`, within the next year.
+pub fn next_apsis(from: i64) -> Option<Apsis> {
+    let mut previous = au(from);
+    let mut current = au(from + DAY);
+    let mut day = from + DAY;
+    while day < from + 366 * DAY {
+        let next = au(day + DAY);
+        let closest = current <= previous && current <= next;
+        if closest || (current >= previous && current >= next) {
+            // Ternary search for the extreme in the surrounding two days
+            let (mut low, mut high) = (day - DAY, day + DAY);
+            while high - low > 2 {
+                let a = low + (high - low) / 3;
+                let b = high - (high - low) / 3;
+                if (au(a) < au(b)) == closest {
+                    high = b;
+                } else {
+                    low = a;
+                }
+            }
+            let timestamp = ((low + high) / 2).max(from + 1);
+            return Some(Apsis {
+                name: if closest { "perihelion" } else { "aphelion" },
+                timestamp,
+                distance: au(timestamp),
+            });
+        }
+        previous = current;
+        current = next;
+        day += DAY;
+    }
+    None
+}
@@ -0,0 +1,141 @@
+//! Higher precision solar ephemeris, based on the VSOP87 theory provided by
+//! the astro crate.
+
+pub fn julian_day(timestamp: i64) -> f64 {
+    timestamp as f64 / 86400.0 + 2440587.5
+}
+
+/// Apparent geocentric ecliptic longitude and latitude of the sun in
+/// radians.
+pub fn sun_ecliptic(jd: f64) -> (f64, f64) {
+    let (ecl, distance) = astro::sun::geocent_ecl_pos(jd);
+    let (nut_in_long, _) = astro::nutation::nutation(jd);
+    let aberration = (-20.4898 / 3600.0f64).to_radians() / distance;
+    (ecl.long + nut_in_long + aberration, ecl.lat)
+}
+
+/// Apparent geocentric right ascension and declination of the sun in
+/// radians.
+pub fn sun_equatorial(jd: f64) -> (f64, f64) {
+    let (long, lat) = sun_ecliptic(jd);
+    let (_, nut_in_oblq) = astro::nutation::nutation(jd);
+    let oblq = astro::ecliptic::mn_oblq_laskar(jd) + nut_in_oblq;
+    (
+        astro::coords::asc_frm_ecl(long, lat, oblq),
+        astro::coords::dec_frm_ecl(long, lat, oblq),
+    )
+}
+
+/// Greenwich apparent sidereal time in radians.
+pub fn apparent_sidereal_time(jd: f64) -> f64 {
+    let (nut_in_long, nut_in_oblq) = astro::nutation::nutation(jd);
+    let oblq = astro::ecliptic::mn_oblq_laskar(jd) + nut_in_oblq;
+    astro::time::apprnt_sidr(astro::time::mn_sidr(jd), nut_in_long, oblq)
+}
+
+/// Local apparent sidereal time at `timestamp` and `longitude` degrees east,
+/// in hours.
+pub fn local_sidereal_time(timestamp: i64, longitude: f64) -> f64 {
+    let gast = apparent_sidereal_time(julian_day(timestamp));
+    (gast.to_degrees() + longitude).rem_euclid(360.0) / 15.0
+}
+
+/// Greenwich hour angle and declination of the sun in radians, the hour
+/// angle being in `[0, 2π)`.
+pub fn sun_gha_dec(jd: f64) -> (f64, f64) {
+    let (ra, dec) = sun_equatorial(jd);
+    (
+        (apparent_sidereal_time(jd) - ra).rem_euclid(std::f64::consts::TAU),
+        dec,
+    )
+}
+
+/// Declination of the sun at `timestamp` in degrees.
+pub fn declination(timestamp: i64) -> f64 {
+    sun_equatorial(julian_day(timestamp)).1.to_degrees()
+}
+
+/// Equation of time at `timestamp` in minutes, positive when the sun
+/// transits before the mean noon.
+pub fn equation_of_time(timestamp: i64) -> f64 {
+    let (gha, _) = sun_gha_dec(julian_day(timestamp));
+    let mean_gha = timestamp.rem_euclid(86400) as f64 / 240.0 + 180.0;
+    ((gha.to_degrees() - mean_gha + 180.0).rem_euclid(360.0) - 180.0) * 4.0
+}
+
+/// Point on the Earth's surface where the sun is at the zenith, as
+/// `(latitude, longitude)` in degrees.
+pub fn subsolar_point(timestamp: i64) -> (f64, f64) {
+    let (gha, dec) = sun_gha_dec(julian_day(timestamp));
+    let long = (-gha.to_degrees() + 540.0).rem_euclid(360.0) - 180.0;
+    (dec.to_degrees(), long)
+}
+
+/// Unix timestamp of the transit of the sun over the meridian of `longitude`
+/// degrees east closest to `near`.
+pub fn solar_transit(near: i64, longitude: f64) -> i64 {
+    let transit =
+        mqtt_sun_core::transit::transit(julian_day(near), longitude, |jd| sun_gha_dec(jd).0);
+    ((transit - 2440587.5) * 86400.0).round() as i64
+}
+
+/// Unix timestamp of the transit of the sun over the meridian of `longitude`
+/// degrees east on `date`.
+pub fn transit_on(date: chrono::NaiveDate, longitude: f64) -> i64 {
+    let midnight = date
+        .signed_duration_since(chrono::NaiveDate::from_ymd_opt(1970, 1, 1).unwrap())
+        .num_seconds();
+    let mean_noon = mqtt_sun_core::transit::mean_noon(longitude);
+    solar_transit(midnight + (mean_noon * 86400.0) as i64, longitude)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> i64 {
+        chrono::NaiveDate::from_ymd_opt(y, m, d)
+            .unwrap()
+            .and_hms_opt(h, min, 0)
+            .unwrap()
+            .timestamp()
+    }
+
+    #[test]
+    fn position() {
+        // Meeus, Astronomical Algorithms, example 25.b: 1992 October 13.0 TD
+        let (ra, dec) = sun_equatorial(2448908.5);
+        assert!((ra.to_degrees().rem_euclid(360.0) - 198.378178).abs() < 0.0005);
+        assert!((dec.to_degrees() - -7.783871).abs() < 0.0005);
+    }
+
+    #[test]
+    fn equation_of_time_on_1992_10_13() {
+        // Meeus, example 28.a: 13m42.7s
+        let minutes = equation_of_time(utc(1992, 10, 13, 0, 0));
+        assert!((minutes - 13.712).abs() < 0.02, "{}", minutes);
+    }
+
+    #[test]
+    fn equation_of_time_extremes() {
+        // About -14m14s in mid February and +16m25s early in November
+        assert!((equation_of_time(utc(2021, 2, 11, 12, 0)) - -14.2).abs() < 0.1);
+        assert!((equation_of_time(utc(2021, 11, 3, 12, 0)) - 16.4).abs() < 0.1);
+    }
+
+    #[test]
+    fn transits() {
+        let transit = |y, m, d, longitude| {
+            transit_on(chrono::NaiveDate::from_ymd_opt(y, m, d).unwrap(), longitude)
+        };
+        // Meridian passages of the Nautical Almanac, to the minute
+        assert!((transit(2021, 2, 11, 0.0) - utc(2021, 2, 11, 12, 14)).abs() <= 60);
+        assert!((transit(2021, 11, 3, 0.0) - utc(2021, 11, 3, 11, 44)).abs() <= 60);
+        // Solar noon in Washington from the NOAA calculator, 13:10 EDT
+        assert!((transit(2021, 6, 21, -77.0369) - utc(2021, 6, 21, 17, 10)).abs() <= 60);
+        // Consistent with the equation of time at Greenwich
+        let noon = transit(1992, 10, 13, 0.0);
+        let expected = utc(1992, 10, 13, 12, 0) as f64 - equation_of_time(noon) * 60.0;
+        assert!((noon as f64 - expected).abs() <= 1.0);
+    }
+}
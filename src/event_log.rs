@@ -0,0 +1,203 @@
+//! Local history of the sun events and of periodic samples of the sun
+//! position in an SQLite database, queried with `mqtt_sun log`.
+
+use chrono::TimeZone;
+use rusqlite::{params, Connection, OpenFlags};
+
+/// Arguments of `mqtt_sun log`. Times are RFC 3339, a local date
+/// (YYYY-MM-DD) or a duration ago such as 12h or 7d.
+#[derive(Debug, clap::Args)]
+pub struct LogArgs {
+    /// Database to query, defaulting to EVENT_LOG_DB
+    #[arg(long)]
+    db: Option<String>,
+    /// Only show entries at or after this time
+    #[arg(long)]
+    since: Option<String>,
+    /// Only show entries before this time
+    #[arg(long)]
+    until: Option<String>,
+    /// Only show these events, such as sunset
+    #[arg(long)]
+    event: Vec<String>,
+    /// Show the samples of the altitude and azimuth instead of the events
+    #[arg(long)]
+    samples: bool,
+}
+
+pub struct Database {
+    connection: Connection,
+}
+
+/// Path of the database, from `EVENT_LOG_DB`.
+pub fn path_from_env() -> Option<String> {
+    std::env::var("EVENT_LOG_DB").ok()
+}
+
+impl Database {
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS events (
+                timestamp INTEGER NOT NULL,
+                name TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS events_timestamp ON events (timestamp);
+            CREATE TABLE IF NOT EXISTS samples (
+                timestamp INTEGER NOT NULL,
+                altitude REAL NOT NULL,
+                azimuth REAL NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS samples_timestamp ON samples (timestamp);",
+        )?;
+        Ok(Self { connection })
+    }
+
+    /// Opens an existing database for querying, without creating it or its
+    /// tables.
+    pub fn open_read_only(path: &str) -> rusqlite::Result<Self> {
+        let connection = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        Ok(Self { connection })
+    }
+
+    pub fn add_event(&self, timestamp: i64, name: &str) -> rusqlite::Result<()> {
+        self.connection
+            .execute(
+                "INSERT INTO events (timestamp, name) VALUES (?1, ?2)",
+                params![timestamp, name],
+            )
+            .map(|_| ())
+    }
+
+    /// Records the sun at `altitude` and `azimuth` degrees.
+    pub fn add_sample(&self, timestamp: i64, altitude: f64, azimuth: f64) -> rusqlite::Result<()> {
+        self.connection
+            .execute(
+                "INSERT INTO samples (timestamp, altitude, azimuth) VALUES (?1, ?2, ?3)",
+                params![timestamp, altitude, azimuth],
+            )
+            .map(|_| ())
+    }
+
+    fn events(&self, since: i64, until: i64) -> rusqlite::Result<Vec<(i64, String)>> {
+        let mut statement = self.connection.prepare(
+            "SELECT timestamp, name FROM events
+             WHERE timestamp >= ?1 AND timestamp < ?2 ORDER BY timestamp",
+        )?;
+        let rows =
+            statement.query_map(params![since, until], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+
+    fn samples(&self, since: i64, until: i64) -> rusqlite::Result<Vec<(i64, f64, f64)>> {
+        let mut statement = self.connection.prepare(
+            "SELECT timestamp, altitude, azimuth FROM samples
+             WHERE timestamp >= ?1 AND timestamp < ?2 ORDER BY timestamp",
+        )?;
+        let rows = statement.query_map(params![since, until], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?;
+        rows.collect()
+    }
+}
+
+/// Parses a time given on the command line into a Unix timestamp.
+fn parse_time(time: &str, now: i64) -> Result<i64, String> {
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(time) {
+        return Ok(time.timestamp());
+    }
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(time, "%Y-%m-%d") {
+        return chrono::Local
+            .from_local_datetime(&date.and_hms(0, 0, 0))
+            .earliest()
+            .map(|x| x.timestamp())
+            .ok_or_else(|| format!("invalid local date `{}`", time));
+    }
+    let unit = match time.chars().last() {
+        Some('m') => 60,
+        Some('h') => 3600,
+        Some('d') => 24 * 3600,
+        _ => return Err(format!("invalid time `{}`", time)),
+    };
+    time[..time.len() - 1]
+        .parse::<i64>()
+        .ok()
+        .and_then(|x| x.checked_mul(unit))
+        .and_then(|x| now.checked_sub(x))
+        .ok_or_else(|| format!("invalid time `{}`", time))
+}
+
+fn format_time(timestamp: i64) -> String {
+    chrono::Local.timestamp(timestamp, 0).to_rfc3339()
+}
+
+fn query(args: &LogArgs) -> Result<(), String> {
+    let now = chrono::Utc::now().timestamp();
+    let since = match &args.since {
+        Some(x) => parse_time(x, now)?,
+        None => 0,
+    };
+    let until = match &args.until {
+        Some(x) => parse_time(x, now)?,
+        None => i64::MAX,
+    };
+    let path = args
+        .db
+        .clone()
+        .or_else(path_from_env)
+        .ok_or("no database given with --db or EVENT_LOG_DB")?;
+    let database = Database::open_read_only(&path).map_err(|e| e.to_string())?;
+    if args.samples {
+        for (timestamp, altitude, azimuth) in
+            database.samples(since, until).map_err(|e| e.to_string())?
+        {
+            println!(
+                "{}\t{:.2}\t{:.2}",
+                format_time(timestamp),
+                altitude,
+                azimuth
+            );
+        }
+        return Ok(());
+    }
+    for (timestamp, name) in database.events(since, until).map_err(|e| e.to_string())? {
+        if args.event.is_empty() || args.event.contains(&name) {
+            println!("{}\t{}", format_time(timestamp), name);
+        }
+    }
+    Ok(())
+}
+
+/// Runs `mqtt_sun log`.
+pub fn run(args: &LogArgs) -> ! {
+    match query(args) {
+        Ok(()) => std::process::exit(0),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(2)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_ago() {
+        assert_eq!(parse_time("12h", 100_000), Ok(100_000 - 12 * 3600));
+        assert!(parse_time("999999999999999d", 100_000).is_err());
+        assert!(parse_time("-9223372036854775807m", 100_000).is_err());
+    }
+
+    #[test]
+    fn querying_does_not_create() {
+        let path = std::env::temp_dir().join(format!("mqtt_sun_log_{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert!(Database::open_read_only(path).is_err());
+        assert!(!std::path::Path::new(path).exists());
+    }
+}
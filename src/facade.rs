@@ -0,0 +1,83 @@
+use std::str::FromStr;
+
+/// A building facade, described by the portion of sky it faces.
+///
+/// The sun shines directly on the facade while its azimuth lies between
+/// `azimuth_from` and `azimuth_to` (clockwise, degrees from north, possibly
+/// wrapping around north) and it is higher than `min_altitude`.
+#[derive(Debug)]
+pub struct Facade {
+    pub name: String,
+    pub azimuth_from: f64,
+    pub azimuth_to: f64,
+    pub min_altitude: f64,
+}
+
+impl Facade {
+    pub fn is_insolated(&self, azimuth: f64, altitude: f64) -> bool {
+        let in_range = if self.azimuth_from <= self.azimuth_to {
+            azimuth >= self.azimuth_from && azimuth <= self.azimuth_to
+        } else {
+            azimuth >= self.azimuth_from || azimuth <= self.azimuth_to
+        };
+        in_range && altitude > self.min_altitude
+    }
+}
+
+/// Parses `name:from-to[:min_altitude]`, e.g. `living_room:135-225:10`.
+impl FromStr for Facade {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split(':');
+        let name = parts
+            .next()
+            .filter(|x| !x.is_empty())
+            .ok_or_else(|| format!("missing name in facade `{}`", s))?;
+        let (from, to) = parts
+            .next()
+            .and_then(|x| x.split_once('-'))
+            .ok_or_else(|| format!("missing azimuth range in facade `{}`", s))?;
+        let parse_angle = |x: &str| {
+            x.trim()
+                .parse::<f64>()
+                .map_err(|_| format!("invalid angle `{}` in facade `{}`", x, s))
+        };
+        let min_altitude = match parts.next() {
+            Some(x) => parse_angle(x)?,
+            None => 0.0,
+        };
+        Ok(Self {
+            name: name.to_owned(),
+            azimuth_from: parse_angle(from)?.rem_euclid(360.0),
+            azimuth_to: parse_angle(to)?.rem_euclid(360.0),
+            min_altitude,
+        })
+    }
+}
+
+/// Reads the facades from the comma separated `FACADES` variable.
+pub fn from_env() -> Vec<Facade> {
+    std::env::var("FACADES")
+        .map(|x| {
+            x.split(',')
+                .filter(|f| !f.trim().is_empty())
+                .map(|f| {
+                    f.parse()
+                        .unwrap_or_else(|e| panic!("Invalid facade: {}", e))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Event published on `sun/facade/<name>` when the facade enters or leaves
+/// direct sunlight, passed to the sinks as `<name>_insolation_start` or
+/// `<name>_insolation_end`.
+pub fn insolation_event(insolated: bool) -> &'static str {
+    if insolated {
+        "insolation_start"
+    } else {
+        "insolation_end"
+    }
+}
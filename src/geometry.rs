@@ -0,0 +1,37 @@
+use serde::Serialize;
+
+/// Vector in the local East-North-Up frame, displayed as its JSON object.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Vector {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl std::fmt::Display for Vector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&serde_json::to_string(self).map_err(|_| std::fmt::Error)?)
+    }
+}
+
+/// Direction of the sun as a unit vector in the local East-North-Up frame,
+/// from azimuth (clockwise from north) and altitude in radians.
+pub fn enu_vector(azimuth: f64, altitude: f64) -> Vector {
+    Vector {
+        x: altitude.cos() * azimuth.sin(),
+        y: altitude.cos() * azimuth.cos(),
+        z: altitude.sin(),
+    }
+}
+
+const COMPASS_POINTS: [&str; 16] = [
+    "N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW", "NW",
+    "NNW",
+];
+
+/// Point of the 16-wind compass rose closest to `azimuth`, clockwise from
+/// north in degrees.
+pub fn compass_point(azimuth: f64) -> &'static str {
+    let sector = (azimuth.rem_euclid(360.0) / 22.5).round() as usize;
+    COMPASS_POINTS[sector % COMPASS_POINTS.len()]
+}
@@ -0,0 +1,61 @@
+//! Grey-line window, when the sun is close to the horizon and HF radio
+//! propagation along the terminator is enhanced.
+
+use crate::band::Band;
+use crate::schedule;
+
+/// How far ahead the end of a window is looked for.
+const LOOKAHEAD: i64 = 24 * 3600;
+
+#[derive(Debug)]
+pub struct GreyLine {
+    band: Band,
+}
+
+pub enum Transition {
+    /// The window opened and is expected to last the given number of
+    /// seconds, if it ends within a day.
+    Start(Option<i64>),
+    End,
+}
+
+impl GreyLine {
+    /// Reads the window bounds from `GREYLINE_BAND`, defaulting to `-6,6`.
+    pub fn from_env() -> Self {
+        Self {
+            band: Band::from_env("GREYLINE_BAND", "-6,6"),
+        }
+    }
+
+    /// Lowest and highest altitude of the window in degrees.
+    pub fn bounds(&self) -> [f64; 2] {
+        self.band.bounds()
+    }
+
+    /// Feeds the sun altitude in degrees at `now`, returning the transition
+    /// if the sun entered or left the window.
+    pub fn update(
+        &mut self,
+        now: i64,
+        altitude: f64,
+        coords: &astro::coords::GeographPoint,
+    ) -> Option<Transition> {
+        if !self.band.update(altitude)? {
+            return Some(Transition::End);
+        }
+        let end = self
+            .band
+            .bounds()
+            .iter()
+            .filter_map(|&bound| schedule::next_crossing(now, now + LOOKAHEAD, bound, coords))
+            .min();
+        Some(Transition::Start(end.map(|end| end - now)))
+    }
+}
+
+pub fn event_name(transition: &Transition) -> &'static str {
+    match transition {
+        Transition::Start(_) => "greylineStart",
+        Transition::End => "greylineEnd",
+    }
+}
@@ -0,0 +1,77 @@
+//! Birth message, published on every connection to the broker, and
+//! periodic heartbeat, so that monitoring can tell a wedged daemon from one
+//! whose connection merely stays open.
+
+use std::time::{Duration, Instant};
+
+pub struct Heartbeat {
+    pub birth_topic: String,
+    pub topic: String,
+    interval: Option<Duration>,
+    started: i64,
+    last: Option<Instant>,
+}
+
+impl Heartbeat {
+    /// Reads the topics from `BIRTH_TOPIC` (default `sun/birth`) and
+    /// `HEARTBEAT_TOPIC` (default `sun/heartbeat`) and the interval in
+    /// seconds from `HEARTBEAT_INTERVAL`, without which no heartbeat is sent.
+    pub fn from_env() -> Self {
+        let interval = std::env::var("HEARTBEAT_INTERVAL")
+            .ok()
+            .map(|x| Duration::from_secs(x.parse().expect("Invalid heartbeat interval")));
+        assert!(
+            interval.map(|x| !x.is_zero()).unwrap_or(true),
+            "The heartbeat interval must be positive"
+        );
+        Self {
+            birth_topic: std::env::var("BIRTH_TOPIC").unwrap_or_else(|_| "sun/birth".to_owned()),
+            topic: std::env::var("HEARTBEAT_TOPIC").unwrap_or_else(|_| "sun/heartbeat".to_owned()),
+            interval,
+            started: chrono::Utc::now().timestamp(),
+            last: None,
+        }
+    }
+
+    pub fn birth(&self, coords: &astro::coords::GeographPoint) -> serde_json::Value {
+        serde_json::json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "started": self.started,
+            "latitude": coords.lat,
+            "longitude": coords.long,
+        })
+    }
+
+    /// When the last heartbeat was sent.
+    pub fn last(&self) -> Option<Instant> {
+        self.last
+    }
+
+    /// Resumes from a heartbeat sent at `last`, such as before a restart.
+    pub fn resume(&mut self, last: Option<Instant>) {
+        self.last = last;
+    }
+
+    /// Time left until the next heartbeat is due, if any is sent.
+    pub fn due_in(&self) -> Option<Duration> {
+        let interval = self.interval?;
+        Some(
+            self.last
+                .map(|x| interval.saturating_sub(x.elapsed()))
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Returns the heartbeat to publish at `now`, if one is due.
+    pub fn beat(&mut self, now: i64) -> Option<serde_json::Value> {
+        let interval = self.interval?;
+        if self.last.map(|x| x.elapsed() < interval).unwrap_or(false) {
+            return None;
+        }
+        self.last = Some(Instant::now());
+        Some(serde_json::json!({
+            "timestamp": now,
+            "uptime": now - self.started,
+        }))
+    }
+}
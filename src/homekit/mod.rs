@@ -0,0 +1,686 @@
+//! HomeKit accessory, implementing the HomeKit Accessory Protocol over IP,
+//! with a light sensor reporting the estimated illuminance and a contact
+//! sensor that is open during the day and closed at night.
+//!
+//! The accessory is advertised over mDNS and paired from the Home app with
+//! the setup code in `HOMEKIT_PIN`. Its identity and the paired controllers
+//! are kept in the state file.
+
+use log::{error, info, warn};
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+mod pairing;
+mod session;
+mod srp;
+mod tlv;
+
+const AID: u64 = 1;
+const IID_IDENTIFY: u64 = 2;
+const IID_LIGHT_LEVEL: u64 = 11;
+const IID_CONTACT_STATE: u64 = 13;
+/// Accessory category advertised to controllers.
+const CATEGORY_SENSOR: &str = "10";
+
+const MIN_LUX: f64 = 0.0001;
+const MAX_LUX: f64 = 100_000.0;
+
+/// Most bytes buffered for a request that is not complete yet.
+const MAX_REQUEST: usize = 64 * 1024;
+/// Most connections served at once.
+const MAX_CONNECTIONS: usize = 16;
+/// Setup codes that the specification forbids as too easy to guess.
+const TRIVIAL_PINS: [&str; 12] = [
+    "000-00-000",
+    "111-11-111",
+    "222-22-222",
+    "333-33-333",
+    "444-44-444",
+    "555-55-555",
+    "666-66-666",
+    "777-77-777",
+    "888-88-888",
+    "999-99-999",
+    "123-45-678",
+    "876-54-321",
+];
+
+/// Checks that `pin` is an acceptable `XXX-XX-XXX` setup code.
+fn check_pin(pin: &str) -> Result<(), &'static str> {
+    let formatted = pin.len() == 10
+        && pin.char_indices().all(|(i, c)| match i {
+            3 | 6 => c == '-',
+            _ => c.is_ascii_digit(),
+        });
+    if !formatted {
+        Err("HOMEKIT_PIN must be formatted as XXX-XX-XXX")
+    } else if TRIVIAL_PINS.contains(&pin) {
+        Err("HOMEKIT_PIN is too easy to guess")
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Values {
+    daylight: bool,
+    lux: f64,
+}
+
+impl Values {
+    fn get(&self, iid: u64) -> Option<serde_json::Value> {
+        match iid {
+            IID_LIGHT_LEVEL => Some(self.lux.clamp(MIN_LUX, MAX_LUX).into()),
+            // 1 means that no contact is detected, i.e. that it is open
+            IID_CONTACT_STATE => Some((self.daylight as u8).into()),
+            _ => None,
+        }
+    }
+}
+
+/// State shared by all the connections.
+pub struct Context {
+    store: Mutex<pairing::Store>,
+    pin: String,
+    name: String,
+    port: u16,
+    values: Arc<Mutex<Values>>,
+    mdns: Option<mdns_sd::ServiceDaemon>,
+    connections: AtomicUsize,
+}
+
+impl Context {
+    /// Advertises the accessory, whose status flag tells whether it can be
+    /// paired.
+    fn announce(&self) {
+        let mdns = match &self.mdns {
+            Some(x) => x,
+            None => return,
+        };
+        let store = self.store.lock().unwrap();
+        let unpaired = if store.is_paired() { "0" } else { "1" };
+        let properties = [
+            ("c#", "1"),
+            ("ff", "0"),
+            ("id", store.device_id.as_str()),
+            ("md", self.name.as_str()),
+            ("pv", "1.1"),
+            ("s#", "1"),
+            ("sf", unpaired),
+            ("ci", CATEGORY_SENSOR),
+        ];
+        let host = format!("mqtt-sun-{}.local.", store.device_id.replace(':', ""));
+        let service = mdns_sd::ServiceInfo::new(
+            "_hap._tcp.local.",
+            &self.name,
+            &host,
+            (),
+            self.port,
+            &properties[..],
+        )
+        .map(|x| x.enable_addr_auto());
+        match service.and_then(|x| mdns.register(x)) {
+            Ok(()) => {}
+            Err(e) => error!("Could not advertise the HomeKit accessory: {}", e),
+        }
+    }
+}
+
+fn characteristic(
+    iid: u64,
+    kind: &str,
+    format: &str,
+    value: Option<serde_json::Value>,
+) -> serde_json::Value {
+    let mut characteristic = serde_json::json!({
+        "iid": iid,
+        "type": kind,
+        "format": format,
+        "perms": ["pr"],
+    });
+    if let Some(value) = value {
+        characteristic["value"] = value;
+    }
+    characteristic
+}
+
+fn accessories(name: &str, serial: &str, values: &Values) -> serde_json::Value {
+    let info = |iid, kind, value: &str| characteristic(iid, kind, "string", Some(value.into()));
+    let mut identify = characteristic(IID_IDENTIFY, "14", "bool", None);
+    identify["perms"] = serde_json::json!(["pw"]);
+    let mut light_level =
+        characteristic(IID_LIGHT_LEVEL, "6B", "float", values.get(IID_LIGHT_LEVEL));
+    light_level["perms"] = serde_json::json!(["pr", "ev"]);
+    light_level["unit"] = "lux".into();
+    light_level["minValue"] = MIN_LUX.into();
+    light_level["maxValue"] = MAX_LUX.into();
+    let mut contact_state = characteristic(
+        IID_CONTACT_STATE,
+        "6A",
+        "uint8",
+        values.get(IID_CONTACT_STATE),
+    );
+    contact_state["perms"] = serde_json::json!(["pr", "ev"]);
+    contact_state["minValue"] = 0.into();
+    contact_state["maxValue"] = 1.into();
+    contact_state["minStep"] = 1.into();
+    serde_json::json!({
+        "accessories": [{
+            "aid": AID,
+            "services": [
+                {
+                    "iid": 1,
+                    "type": "3E",
+                    "characteristics": [
+                        identify,
+                        info(3, "20", "mqtt_sun"),
+                        info(4, "21", "mqtt_sun"),
+                        info(5, "23", name),
+                        info(6, "30", serial),
+                        info(7, "52", env!("CARGO_PKG_VERSION")),
+                    ],
+                },
+                {
+                    "iid": 8,
+                    "type": "A2",
+                    "characteristics": [info(9, "37", "1.1.0")],
+                },
+                {
+                    "iid": 10,
+                    "type": "84",
+                    "primary": true,
+                    "characteristics": [light_level],
+                },
+                {
+                    "iid": 12,
+                    "type": "80",
+                    "characteristics": [contact_state],
+                },
+            ],
+        }],
+    })
+}
+
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+/// Removes the first complete HTTP request from `buffer`, failing on
+/// requests that could never fit in `MAX_REQUEST`.
+fn parse_request(buffer: &mut Vec<u8>) -> Result<Option<Request>, String> {
+    let end = match buffer.windows(4).position(|x| x == b"\r\n\r\n") {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let head = String::from_utf8_lossy(&buffer[..end]).into_owned();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (method, path) = match (request_line.next(), request_line.next()) {
+        (Some(method), Some(path)) if !method.is_empty() => (method.to_owned(), path.to_owned()),
+        _ => return Err("malformed request line".to_owned()),
+    };
+    let length: usize = match lines
+        .filter_map(|x| x.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+    {
+        Some((_, value)) => value
+            .trim()
+            .parse()
+            .map_err(|_| "invalid content length".to_owned())?,
+        None => 0,
+    };
+    if length > MAX_REQUEST {
+        return Err("request too large".to_owned());
+    }
+    let total = end + 4 + length;
+    if buffer.len() < total {
+        return Ok(None);
+    }
+    let body = buffer[end + 4..total].to_vec();
+    buffer.drain(..total);
+    Ok(Some(Request { method, path, body }))
+}
+
+fn response(status: u16, content_type: &str, body: &[u8]) -> Vec<u8> {
+    let reason = match status {
+        200 => "OK",
+        204 => "No Content",
+        207 => "Multi-Status",
+        400 => "Bad Request",
+        404 => "Not Found",
+        470 => "Connection Authorization Required",
+        _ => "Error",
+    };
+    let mut response = format!("HTTP/1.1 {} {}\r\n", status, reason).into_bytes();
+    if status != 204 {
+        response.extend_from_slice(
+            format!(
+                "Content-Type: {}\r\nContent-Length: {}\r\n",
+                content_type,
+                body.len()
+            )
+            .as_bytes(),
+        );
+    }
+    response.extend_from_slice(b"\r\n");
+    response.extend_from_slice(body);
+    response
+}
+
+fn json_response(status: u16, body: &serde_json::Value) -> Vec<u8> {
+    response(status, "application/hap+json", body.to_string().as_bytes())
+}
+
+fn tlv_response(body: &[u8]) -> Vec<u8> {
+    response(200, "application/pairing+tlv8", body)
+}
+
+/// HAP status of a request that needs a verified connection.
+const STATUS_INSUFFICIENT_PRIVILEGES: i64 = -70401;
+const STATUS_READ_ONLY: i64 = -70404;
+const STATUS_NOTIFICATIONS_UNSUPPORTED: i64 = -70406;
+const STATUS_NO_RESOURCE: i64 = -70409;
+
+struct Connection {
+    stream: TcpStream,
+    context: Arc<Context>,
+    received: Vec<u8>,
+    plaintext: Vec<u8>,
+    session: Option<session::Session>,
+    controller: Option<String>,
+    setup: pairing::Setup,
+    verify: Option<pairing::Verify>,
+    subscriptions: HashSet<u64>,
+    notified: Option<Values>,
+}
+
+impl Connection {
+    fn send(&mut self, message: &[u8]) -> std::io::Result<()> {
+        match &mut self.session {
+            Some(session) => self.stream.write_all(&session.encrypt(message)),
+            None => self.stream.write_all(message),
+        }
+    }
+
+    fn run(mut self) -> Result<(), String> {
+        // Wake up periodically to send the events
+        self.stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(1)))
+            .map_err(|e| e.to_string())?;
+        let mut buffer = [0; 4096];
+        loop {
+            // Sessions end with the pairing of their controller
+            if let Some(controller) = &self.controller {
+                if !self.context.store.lock().unwrap().has_pairing(controller) {
+                    return Err(format!("controller {} was removed", controller));
+                }
+            }
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Ok(()),
+                Ok(n) => self.received.extend_from_slice(&buffer[..n]),
+                Err(e)
+                    if e.kind() == std::io::ErrorKind::WouldBlock
+                        || e.kind() == std::io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e.to_string()),
+            }
+            match &mut self.session {
+                Some(session) => {
+                    let plaintext = session.decrypt(&mut self.received)?;
+                    self.plaintext.extend(plaintext);
+                }
+                None => self.plaintext.append(&mut self.received),
+            }
+            if self.received.len() + self.plaintext.len() > MAX_REQUEST {
+                return Err("request too large".to_owned());
+            }
+            while let Some(request) = parse_request(&mut self.plaintext)? {
+                self.handle(request).map_err(|e| e.to_string())?;
+            }
+            self.send_events().map_err(|e| e.to_string())?;
+        }
+    }
+
+    fn handle(&mut self, request: Request) -> std::io::Result<()> {
+        let (path, query) = match request.path.split_once('?') {
+            Some((path, query)) => (path.to_owned(), query.to_owned()),
+            None => (request.path.clone(), String::new()),
+        };
+        match (request.method.as_str(), path.as_str()) {
+            ("POST", "/pair-setup") => {
+                let body = pairing::pair_setup(&request.body, &mut self.setup, &self.context);
+                return self.send(&tlv_response(&body));
+            }
+            ("POST", "/pair-verify") => {
+                let (body, verified) =
+                    pairing::pair_verify(&request.body, &mut self.verify, &self.context);
+                self.send(&tlv_response(&body))?;
+                // Everything after the last pair verify response is encrypted
+                if let Some((session, controller)) = verified {
+                    info!("HomeKit controller {} connected", controller);
+                    self.session = Some(session);
+                    self.controller = Some(controller);
+                }
+                return Ok(());
+            }
+            ("POST", "/identify") if !self.context.store.lock().unwrap().is_paired() => {
+                info!("HomeKit identify requested");
+                return self.send(&response(204, "", &[]));
+            }
+            _ => {}
+        }
+        let controller = match &self.controller {
+            Some(x) => x.clone(),
+            None => {
+                let body = serde_json::json!({ "status": STATUS_INSUFFICIENT_PRIVILEGES });
+                return self.send(&json_response(470, &body));
+            }
+        };
+        if !self.context.store.lock().unwrap().has_pairing(&controller) {
+            return Err(std::io::Error::other(format!(
+                "controller {} was removed",
+                controller
+            )));
+        }
+        let values = *self.context.values.lock().unwrap();
+        let message = match (request.method.as_str(), path.as_str()) {
+            ("GET", "/accessories") => {
+                let store = self.context.store.lock().unwrap();
+                json_response(
+                    200,
+                    &accessories(&self.context.name, &store.device_id, &values),
+                )
+            }
+            ("GET", "/characteristics") => read_characteristics(&query, &values),
+            ("PUT", "/characteristics") => self.write_characteristics(&request.body, values),
+            ("POST", "/pairings") => tlv_response(&pairing::pairings(
+                &request.body,
+                &controller,
+                &self.context,
+            )),
+            _ => response(404, "", &[]),
+        };
+        self.send(&message)
+    }
+
+    fn write_characteristics(&mut self, body: &[u8], values: Values) -> Vec<u8> {
+        let request: serde_json::Value = match serde_json::from_slice(body) {
+            Ok(x) => x,
+            Err(_) => return response(400, "", &[]),
+        };
+        let empty = Vec::new();
+        let mut statuses = Vec::new();
+        for write in request["characteristics"].as_array().unwrap_or(&empty) {
+            let iid = write["iid"].as_u64().unwrap_or(0);
+            let status = if write["aid"].as_u64() != Some(AID) {
+                STATUS_NO_RESOURCE
+            } else if let Some(enable) = write["ev"].as_bool() {
+                if values.get(iid).is_none() {
+                    STATUS_NOTIFICATIONS_UNSUPPORTED
+                } else {
+                    if enable {
+                        self.subscriptions.insert(iid);
+                    } else {
+                        self.subscriptions.remove(&iid);
+                    }
+                    self.notified.get_or_insert(values);
+                    0
+                }
+            } else if iid == IID_IDENTIFY {
+                info!("HomeKit identify requested");
+                0
+            } else {
+                STATUS_READ_ONLY
+            };
+            statuses.push(serde_json::json!({ "aid": AID, "iid": iid, "status": status }));
+        }
+        if statuses.iter().all(|x| x["status"] == 0) {
+            response(204, "", &[])
+        } else {
+            json_response(207, &serde_json::json!({ "characteristics": statuses }))
+        }
+    }
+
+    /// Notifies the subscribed characteristics that changed.
+    fn send_events(&mut self) -> std::io::Result<()> {
+        let notified = match self.notified {
+            Some(x) if self.session.is_some() => x,
+            _ => return Ok(()),
+        };
+        let values = *self.context.values.lock().unwrap();
+        let changed: Vec<_> = self
+            .subscriptions
+            .iter()
+            .filter(|&&iid| values.get(iid) != notified.get(iid))
+            .map(|&iid| serde_json::json!({ "aid": AID, "iid": iid, "value": values.get(iid) }))
+            .collect();
+        self.notified = Some(values);
+        if changed.is_empty() {
+            return Ok(());
+        }
+        let body = serde_json::json!({ "characteristics": changed }).to_string();
+        let event = format!(
+            "EVENT/1.0 200 OK\r\nContent-Type: application/hap+json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        self.send(event.as_bytes())
+    }
+}
+
+/// Answers a read of the characteristics listed in the `id` parameter of
+/// `query`, such as `id=1.11,1.13`.
+fn read_characteristics(query: &str, values: &Values) -> Vec<u8> {
+    let ids = query
+        .split('&')
+        .find_map(|x| x.strip_prefix("id="))
+        .unwrap_or("");
+    let mut complete = true;
+    let characteristics: Vec<_> = ids
+        .split(',')
+        .filter_map(|x| x.split_once('.'))
+        .map(|(aid, iid)| {
+            let aid = aid.parse().unwrap_or(0u64);
+            let iid = iid.parse().unwrap_or(0u64);
+            match values.get(iid).filter(|_| aid == AID) {
+                Some(value) => serde_json::json!({ "aid": aid, "iid": iid, "value": value }),
+                None => {
+                    complete = false;
+                    serde_json::json!({ "aid": aid, "iid": iid, "status": STATUS_NO_RESOURCE })
+                }
+            }
+        })
+        .collect();
+    let status = if complete { 200 } else { 207 };
+    json_response(
+        status,
+        &serde_json::json!({ "characteristics": characteristics }),
+    )
+}
+
+/// Slot in `Context::connections`, released when the connection ends, even
+/// by a panic.
+struct Slot(Arc<Context>);
+
+impl Slot {
+    fn take(context: &Arc<Context>) -> Option<Self> {
+        if context.connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            context.connections.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(Self(context.clone()))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub struct HomeKit {
+    values: Arc<Mutex<Values>>,
+}
+
+impl HomeKit {
+    /// Reads the setup code from `HOMEKIT_PIN` (`XXX-XX-XXX`), with the
+    /// optional `HOMEKIT_NAME` (default `Sun`), `HOMEKIT_PORT` (default
+    /// 51826) and `HOMEKIT_STATE` file (default `homekit.json`).
+    pub fn from_env() -> Option<Self> {
+        let pin = std::env::var("HOMEKIT_PIN").ok()?;
+        check_pin(&pin).unwrap_or_else(|e| panic!("{}", e));
+        let port = std::env::var("HOMEKIT_PORT")
+            .map(|x| x.parse().expect("Invalid HomeKit port"))
+            .unwrap_or(51826);
+        let values = Arc::new(Mutex::new(Values {
+            daylight: false,
+            lux: MIN_LUX,
+        }));
+        let context = Arc::new(Context {
+            store: Mutex::new(pairing::Store::load(
+                &std::env::var("HOMEKIT_STATE").unwrap_or_else(|_| "homekit.json".to_owned()),
+            )),
+            pin,
+            name: std::env::var("HOMEKIT_NAME").unwrap_or_else(|_| "Sun".to_owned()),
+            port,
+            values: values.clone(),
+            mdns: mdns_sd::ServiceDaemon::new()
+                .map_err(|e| {
+                    error!(
+                        "Could not start mDNS, HomeKit will not be advertised: {}",
+                        e
+                    )
+                })
+                .ok(),
+            connections: AtomicUsize::new(0),
+        });
+        let listener = TcpListener::bind(("0.0.0.0", port))
+            .unwrap_or_else(|e| panic!("Could not listen on port {}: {}", port, e));
+        info!("Serving HomeKit accessory on port {}", port);
+        context.announce();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(x) => x,
+                    Err(e) => {
+                        error!("Could not accept HomeKit connection: {}", e);
+                        continue;
+                    }
+                };
+                let slot = match Slot::take(&context) {
+                    Some(x) => x,
+                    None => {
+                        warn!("Refusing HomeKit connection, {} are open", MAX_CONNECTIONS);
+                        continue;
+                    }
+                };
+                let connection = Connection {
+                    stream,
+                    context: context.clone(),
+                    received: Vec::new(),
+                    plaintext: Vec::new(),
+                    session: None,
+                    controller: None,
+                    setup: pairing::Setup::default(),
+                    verify: None,
+                    subscriptions: HashSet::new(),
+                    notified: None,
+                };
+                std::thread::spawn(move || {
+                    let _slot = slot;
+                    connection
+                        .run()
+                        .unwrap_or_else(|e| warn!("HomeKit connection closed: {}", e));
+                });
+            }
+        });
+        Some(Self { values })
+    }
+
+    pub fn update(&self, daylight: bool, lux: f64) {
+        *self.values.lock().unwrap() = Values { daylight, lux };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pins() {
+        assert!(check_pin("031-45-154").is_ok());
+        assert!(check_pin("03145154").is_err());
+        assert!(check_pin("031-45-15a").is_err());
+        assert!(check_pin("111-11-111").is_err());
+        assert!(check_pin("123-45-678").is_err());
+    }
+
+    #[test]
+    fn requests() {
+        let mut buffer = b"POST /pair-setup HTTP/1.1\r\nContent-Length: 3\r\n\r\nabcGET".to_vec();
+        let request = parse_request(&mut buffer).unwrap().unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/pair-setup");
+        assert_eq!(request.body, b"abc");
+        assert_eq!(buffer, b"GET");
+        assert!(parse_request(&mut buffer).unwrap().is_none());
+    }
+
+    #[test]
+    fn pipelined_and_partial_requests() {
+        let mut buffer = b"GET /accessories HTTP/1.1\r\nHost: sun\r\n\r\n\
+            PUT /characteristics HTTP/1.1\r\ncontent-length: 5\r\n\r\n{\"a\""
+            .to_vec();
+        let request = parse_request(&mut buffer).unwrap().unwrap();
+        assert_eq!(
+            (request.method.as_str(), request.path.as_str()),
+            ("GET", "/accessories")
+        );
+        assert!(request.body.is_empty());
+        // The body of the second one is still incomplete
+        assert!(parse_request(&mut buffer).unwrap().is_none());
+        buffer.extend_from_slice(b":1}");
+        let request = parse_request(&mut buffer).unwrap().unwrap();
+        assert_eq!(request.body, b"{\"a\":");
+        assert_eq!(buffer, b"1}");
+    }
+
+    #[test]
+    fn oversized_requests() {
+        for length in [usize::MAX.to_string(), (MAX_REQUEST + 1).to_string()] {
+            let mut buffer = format!(
+                "POST /pair-setup HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+                length
+            )
+            .into_bytes();
+            assert!(parse_request(&mut buffer).is_err(), "{}", length);
+        }
+        let mut buffer =
+            b"POST / HTTP/1.1\r\nContent-Length: 99999999999999999999999\r\n\r\n".to_vec();
+        assert!(parse_request(&mut buffer).is_err());
+        let mut buffer = b"POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n".to_vec();
+        assert!(parse_request(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn slots_are_released() {
+        let context = Arc::new(pairing::tests::context("slots"));
+        let slots: Vec<_> = (0..MAX_CONNECTIONS)
+            .map(|_| Slot::take(&context).unwrap())
+            .collect();
+        assert!(Slot::take(&context).is_none());
+        let slot = slots.into_iter().next().unwrap();
+        // A connection thread that panics still gives its slot back
+        let _ = std::thread::spawn(move || {
+            let _slot = slot;
+            panic!("connection failed");
+        })
+        .join();
+        assert!(Slot::take(&context).is_some());
+        pairing::tests::remove_state(&context);
+    }
+}
@@ -0,0 +1,763 @@
+//! Pair setup, pair verify and pairing management, with the controllers
+//! persisted in a state file.
+
+use super::session::{derive_key, nonce, open, seal, Session};
+use super::{srp, tlv, Context};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+
+/// Most controllers the accessory can be paired with.
+const MAX_PAIRINGS: usize = 16;
+/// Wrong setup codes after which pair setup is refused for good, so that
+/// the code cannot be guessed.
+const MAX_TRIES: u32 = 100;
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn random<const L: usize>() -> [u8; L] {
+    let mut bytes = [0; L];
+    getrandom::getrandom(&mut bytes).expect("No random numbers available");
+    bytes
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Pairing {
+    id: String,
+    public_key: String,
+    admin: bool,
+}
+
+/// Identity of the accessory and the controllers paired with it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Store {
+    #[serde(skip)]
+    path: String,
+    pub device_id: String,
+    secret_key: String,
+    pairings: Vec<Pairing>,
+    /// Pair setups that failed on a wrong setup code
+    #[serde(default)]
+    failed_attempts: u32,
+}
+
+impl Store {
+    /// Loads the state from `path`, creating a new identity if it does not
+    /// exist yet.
+    pub fn load(path: &str) -> Self {
+        let mut store = match std::fs::read_to_string(path) {
+            Ok(state) => serde_json::from_str::<Store>(&state)
+                .unwrap_or_else(|e| panic!("Invalid HomeKit state in {}: {}", path, e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let id = random::<6>();
+                let store = Store {
+                    path: path.to_owned(),
+                    device_id: id
+                        .iter()
+                        .map(|x| format!("{:02X}", x))
+                        .collect::<Vec<_>>()
+                        .join(":"),
+                    secret_key: to_hex(&random::<32>()),
+                    pairings: Vec::new(),
+                    failed_attempts: 0,
+                };
+                store.save();
+                store
+            }
+            Err(e) => panic!("Could not read HomeKit state from {}: {}", path, e),
+        };
+        store.path = path.to_owned();
+        store
+    }
+
+    /// Saves the state, readable by the owner only as it holds the secret
+    /// key of the accessory.
+    fn save(&self) {
+        let state = serde_json::to_string_pretty(self).unwrap();
+        let temporary = format!("{}.tmp", self.path);
+        let written = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&temporary)
+            .and_then(|mut file| file.write_all(state.as_bytes()))
+            .and_then(|_| std::fs::rename(&temporary, &self.path));
+        if let Err(e) = written {
+            log::error!("Could not save HomeKit state to {}: {}", self.path, e);
+        }
+    }
+
+    pub fn is_paired(&self) -> bool {
+        !self.pairings.is_empty()
+    }
+
+    fn signing_key(&self) -> SigningKey {
+        let bytes = from_hex(&self.secret_key).expect("Invalid HomeKit secret key");
+        SigningKey::from_bytes(
+            bytes
+                .as_slice()
+                .try_into()
+                .expect("Invalid HomeKit secret key"),
+        )
+    }
+
+    /// Whether the controller `id` is still paired.
+    pub fn has_pairing(&self, id: &str) -> bool {
+        self.public_key(id).is_some()
+    }
+
+    fn public_key(&self, id: &str) -> Option<VerifyingKey> {
+        let pairing = self.pairings.iter().find(|x| x.id == id)?;
+        let bytes = from_hex(&pairing.public_key)?;
+        VerifyingKey::from_bytes(bytes.as_slice().try_into().ok()?).ok()
+    }
+
+    fn is_admin(&self, id: &str) -> bool {
+        self.pairings.iter().any(|x| x.id == id && x.admin)
+    }
+}
+
+/// State of a pair setup in progress on a connection.
+#[derive(Default)]
+pub struct Setup {
+    srp: Option<srp::Server>,
+    key: Option<Vec<u8>>,
+}
+
+/// State of a pair verify in progress on a connection.
+pub struct Verify {
+    shared_secret: [u8; 32],
+    accessory_key: [u8; 32],
+    controller_key: [u8; 32],
+}
+
+fn verify_signature(key: &VerifyingKey, message: &[u8], signature: &[u8]) -> bool {
+    Signature::from_slice(signature)
+        .map(|signature| key.verify(message, &signature).is_ok())
+        .unwrap_or(false)
+}
+
+fn field(items: &HashMap<u8, Vec<u8>>, kind: u8) -> Result<&[u8], String> {
+    items
+        .get(&kind)
+        .map(|x| x.as_slice())
+        .ok_or_else(|| format!("missing TLV item {}", kind))
+}
+
+/// Handles a pair setup request, returning the response body.
+pub fn pair_setup(body: &[u8], setup: &mut Setup, context: &Context) -> Vec<u8> {
+    let request = match tlv::decode(body) {
+        Ok(x) => x,
+        Err(_) => return tlv::error(2, tlv::ERROR_UNKNOWN),
+    };
+    match request.get(&tlv::STATE).and_then(|x| x.first()) {
+        Some(1) => {
+            let store = context.store.lock().unwrap();
+            if store.is_paired() {
+                return tlv::error(2, tlv::ERROR_UNAVAILABLE);
+            }
+            if store.failed_attempts >= MAX_TRIES {
+                return tlv::error(2, tlv::ERROR_MAX_TRIES);
+            }
+            drop(store);
+            let server = srp::Server::new(&context.pin);
+            let response = tlv::encode(&[
+                (tlv::STATE, &[2]),
+                (tlv::SALT, server.salt()),
+                (tlv::PUBLIC_KEY, &server.public_key()),
+            ]);
+            *setup = Setup {
+                srp: Some(server),
+                key: None,
+            };
+            response
+        }
+        Some(3) => {
+            let verified = setup.srp.as_ref().and_then(|server| {
+                server.verify(
+                    field(&request, tlv::PUBLIC_KEY).ok()?,
+                    field(&request, tlv::PROOF).ok()?,
+                )
+            });
+            match verified {
+                Some((proof, key)) => {
+                    setup.key = Some(key);
+                    tlv::encode(&[(tlv::STATE, &[4]), (tlv::PROOF, &proof)])
+                }
+                None => {
+                    if setup.srp.is_some() {
+                        let mut store = context.store.lock().unwrap();
+                        store.failed_attempts += 1;
+                        store.save();
+                        log::warn!(
+                            "Wrong HomeKit setup code, {} of {} attempts",
+                            store.failed_attempts,
+                            MAX_TRIES
+                        );
+                    }
+                    *setup = Setup::default();
+                    tlv::error(4, tlv::ERROR_AUTHENTICATION)
+                }
+            }
+        }
+        Some(5) => match exchange(&request, setup, context) {
+            Ok(response) => response,
+            Err(e) => {
+                log::warn!("HomeKit pair setup failed: {}", e);
+                tlv::error(6, tlv::ERROR_AUTHENTICATION)
+            }
+        },
+        _ => tlv::error(2, tlv::ERROR_UNKNOWN),
+    }
+}
+
+/// Last step of pair setup, exchanging the long term public keys.
+fn exchange(
+    request: &HashMap<u8, Vec<u8>>,
+    setup: &mut Setup,
+    context: &Context,
+) -> Result<Vec<u8>, String> {
+    let key = setup.key.take().ok_or("pair setup was not verified")?;
+    *setup = Setup::default();
+    let encryption_key = derive_key(&key, "Pair-Setup-Encrypt-Salt", "Pair-Setup-Encrypt-Info");
+    let data = open(
+        &encryption_key,
+        &nonce(b"PS-Msg05"),
+        field(request, tlv::ENCRYPTED_DATA)?,
+        &[],
+    )
+    .ok_or("could not decrypt the controller's keys")?;
+    let items = tlv::decode(&data)?;
+    let id = std::str::from_utf8(field(&items, tlv::IDENTIFIER)?)
+        .map_err(|_| "invalid controller identifier")?;
+    let public_key = field(&items, tlv::PUBLIC_KEY)?;
+    let verifying_key = public_key
+        .try_into()
+        .ok()
+        .and_then(|x| VerifyingKey::from_bytes(x).ok())
+        .ok_or("invalid controller public key")?;
+    let mut info = derive_key(
+        &key,
+        "Pair-Setup-Controller-Sign-Salt",
+        "Pair-Setup-Controller-Sign-Info",
+    )
+    .to_vec();
+    info.extend_from_slice(id.as_bytes());
+    info.extend_from_slice(public_key);
+    if !verify_signature(&verifying_key, &info, field(&items, tlv::SIGNATURE)?) {
+        return Err("invalid controller signature".to_owned());
+    }
+
+    let mut store = context.store.lock().unwrap();
+    if store.is_paired() {
+        return Err("already paired".to_owned());
+    }
+    store.pairings.push(Pairing {
+        id: id.to_owned(),
+        public_key: to_hex(public_key),
+        admin: true,
+    });
+    store.failed_attempts = 0;
+    store.save();
+    info!("Paired with HomeKit controller {}", id);
+
+    let signing_key = store.signing_key();
+    let accessory_key = signing_key.verifying_key().to_bytes();
+    let mut info = derive_key(
+        &key,
+        "Pair-Setup-Accessory-Sign-Salt",
+        "Pair-Setup-Accessory-Sign-Info",
+    )
+    .to_vec();
+    info.extend_from_slice(store.device_id.as_bytes());
+    info.extend_from_slice(&accessory_key);
+    let signature = signing_key.sign(&info).to_bytes();
+    let data = tlv::encode(&[
+        (tlv::IDENTIFIER, store.device_id.as_bytes()),
+        (tlv::PUBLIC_KEY, &accessory_key),
+        (tlv::SIGNATURE, &signature),
+    ]);
+    drop(store);
+    context.announce();
+    Ok(tlv::encode(&[
+        (tlv::STATE, &[6]),
+        (
+            tlv::ENCRYPTED_DATA,
+            &seal(&encryption_key, &nonce(b"PS-Msg06"), &data, &[]),
+        ),
+    ]))
+}
+
+/// Handles a pair verify request, returning the response body and, once
+/// verification succeeds, the session and the controller identifier.
+pub fn pair_verify(
+    body: &[u8],
+    verify: &mut Option<Verify>,
+    context: &Context,
+) -> (Vec<u8>, Option<(Session, String)>) {
+    let request = match tlv::decode(body) {
+        Ok(x) => x,
+        Err(_) => return (tlv::error(2, tlv::ERROR_UNKNOWN), None),
+    };
+    match request.get(&tlv::STATE).and_then(|x| x.first()) {
+        Some(1) => {
+            let controller_key: [u8; 32] = match field(&request, tlv::PUBLIC_KEY)
+                .ok()
+                .and_then(|x| x.try_into().ok())
+            {
+                Some(x) => x,
+                None => return (tlv::error(2, tlv::ERROR_UNKNOWN), None),
+            };
+            let secret = x25519_dalek::StaticSecret::from(random::<32>());
+            let accessory_key = x25519_dalek::PublicKey::from(&secret).to_bytes();
+            let shared_secret = secret
+                .diffie_hellman(&x25519_dalek::PublicKey::from(controller_key))
+                .to_bytes();
+            let store = context.store.lock().unwrap();
+            let mut info = accessory_key.to_vec();
+            info.extend_from_slice(store.device_id.as_bytes());
+            info.extend_from_slice(&controller_key);
+            let signature = store.signing_key().sign(&info).to_bytes();
+            let data = tlv::encode(&[
+                (tlv::IDENTIFIER, store.device_id.as_bytes()),
+                (tlv::SIGNATURE, &signature),
+            ]);
+            let encryption_key = derive_key(
+                &shared_secret,
+                "Pair-Verify-Encrypt-Salt",
+                "Pair-Verify-Encrypt-Info",
+            );
+            *verify = Some(Verify {
+                shared_secret,
+                accessory_key,
+                controller_key,
+            });
+            let response = tlv::encode(&[
+                (tlv::STATE, &[2]),
+                (tlv::PUBLIC_KEY, &accessory_key),
+                (
+                    tlv::ENCRYPTED_DATA,
+                    &seal(&encryption_key, &nonce(b"PV-Msg02"), &data, &[]),
+                ),
+            ]);
+            (response, None)
+        }
+        Some(3) => match verify
+            .take()
+            .ok_or_else(|| "pair verify was not started".to_owned())
+        {
+            Ok(state) => match finish_verify(&request, &state, context) {
+                Ok(id) => (
+                    tlv::encode(&[(tlv::STATE, &[4])]),
+                    Some((Session::new(&state.shared_secret), id)),
+                ),
+                Err(e) => {
+                    log::warn!("HomeKit pair verify failed: {}", e);
+                    (tlv::error(4, tlv::ERROR_AUTHENTICATION), None)
+                }
+            },
+            Err(_) => (tlv::error(4, tlv::ERROR_AUTHENTICATION), None),
+        },
+        _ => (tlv::error(2, tlv::ERROR_UNKNOWN), None),
+    }
+}
+
+fn finish_verify(
+    request: &HashMap<u8, Vec<u8>>,
+    state: &Verify,
+    context: &Context,
+) -> Result<String, String> {
+    let encryption_key = derive_key(
+        &state.shared_secret,
+        "Pair-Verify-Encrypt-Salt",
+        "Pair-Verify-Encrypt-Info",
+    );
+    let data = open(
+        &encryption_key,
+        &nonce(b"PV-Msg03"),
+        field(request, tlv::ENCRYPTED_DATA)?,
+        &[],
+    )
+    .ok_or("could not decrypt the controller's proof")?;
+    let items = tlv::decode(&data)?;
+    let id = std::str::from_utf8(field(&items, tlv::IDENTIFIER)?)
+        .map_err(|_| "invalid controller identifier")?;
+    let public_key = context
+        .store
+        .lock()
+        .unwrap()
+        .public_key(id)
+        .ok_or_else(|| format!("unknown controller {}", id))?;
+    let mut info = state.controller_key.to_vec();
+    info.extend_from_slice(id.as_bytes());
+    info.extend_from_slice(&state.accessory_key);
+    if !verify_signature(&public_key, &info, field(&items, tlv::SIGNATURE)?) {
+        return Err("invalid controller signature".to_owned());
+    }
+    Ok(id.to_owned())
+}
+
+/// Handles a request to add, remove or list pairings from the admin
+/// controller `controller`.
+pub fn pairings(body: &[u8], controller: &str, context: &Context) -> Vec<u8> {
+    let request = match tlv::decode(body) {
+        Ok(x) => x,
+        Err(_) => return tlv::error(2, tlv::ERROR_UNKNOWN),
+    };
+    let mut store = context.store.lock().unwrap();
+    if !store.is_admin(controller) {
+        return tlv::error(2, tlv::ERROR_AUTHENTICATION);
+    }
+    let id = request
+        .get(&tlv::IDENTIFIER)
+        .and_then(|x| std::str::from_utf8(x).ok())
+        .map(|x| x.to_owned());
+    match request.get(&tlv::METHOD).and_then(|x| x.first()) {
+        // Add pairing
+        Some(3) => {
+            let (id, public_key) = match (id, request.get(&tlv::PUBLIC_KEY)) {
+                (Some(id), Some(key)) if key.len() == 32 => (id, to_hex(key)),
+                _ => return tlv::error(2, tlv::ERROR_UNKNOWN),
+            };
+            let admin = request
+                .get(&tlv::PERMISSIONS)
+                .and_then(|x| x.first())
+                .map(|x| x & 1 == 1)
+                .unwrap_or(false);
+            let count = store.pairings.len();
+            match store.pairings.iter_mut().find(|x| x.id == id) {
+                Some(existing) if existing.public_key != public_key => {
+                    return tlv::error(2, tlv::ERROR_UNKNOWN)
+                }
+                Some(existing) => existing.admin = admin,
+                None if count >= MAX_PAIRINGS => return tlv::error(2, tlv::ERROR_MAX_PEERS),
+                None => store.pairings.push(Pairing {
+                    id,
+                    public_key,
+                    admin,
+                }),
+            }
+            store.save();
+            tlv::encode(&[(tlv::STATE, &[2])])
+        }
+        // Remove pairing
+        Some(4) => {
+            let id = match id {
+                Some(id) => id,
+                None => return tlv::error(2, tlv::ERROR_UNKNOWN),
+            };
+            store.pairings.retain(|x| x.id != id);
+            // Without admins nobody could manage the accessory any more
+            if !store.pairings.iter().any(|x| x.admin) {
+                store.pairings.clear();
+            }
+            store.save();
+            info!("Removed HomeKit pairing {}", id);
+            drop(store);
+            context.announce();
+            tlv::encode(&[(tlv::STATE, &[2])])
+        }
+        // List pairings
+        Some(5) => {
+            let keys: Vec<_> = store
+                .pairings
+                .iter()
+                .map(|x| from_hex(&x.public_key).unwrap_or_default())
+                .collect();
+            let mut items: Vec<(u8, &[u8])> = vec![(tlv::STATE, &[2])];
+            for (i, (pairing, key)) in store.pairings.iter().zip(&keys).enumerate() {
+                if i > 0 {
+                    items.push((tlv::SEPARATOR, &[]));
+                }
+                items.push((tlv::IDENTIFIER, pairing.id.as_bytes()));
+                items.push((tlv::PUBLIC_KEY, key));
+                items.push((tlv::PERMISSIONS, if pairing.admin { &[1] } else { &[0] }));
+            }
+            tlv::encode(&items)
+        }
+        _ => tlv::error(2, tlv::ERROR_UNKNOWN),
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    pub(in crate::homekit) fn context(name: &str) -> Context {
+        let path =
+            std::env::temp_dir().join(format!("homekit-{}-{}.json", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        Context {
+            store: std::sync::Mutex::new(Store::load(path.to_str().unwrap())),
+            pin: "031-45-154".to_owned(),
+            name: "Sun".to_owned(),
+            port: 0,
+            values: std::sync::Arc::new(std::sync::Mutex::new(crate::homekit::Values {
+                daylight: false,
+                lux: 0.0,
+            })),
+            mdns: None,
+            connections: Default::default(),
+        }
+    }
+
+    pub(in crate::homekit) fn remove_state(context: &Context) {
+        let path = context.store.lock().unwrap().path.clone();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn private_state() {
+        let context = context("private");
+        let path = context.store.lock().unwrap().path.clone();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn max_tries() {
+        let context = context("tries");
+        context.store.lock().unwrap().failed_attempts = MAX_TRIES - 1;
+        let mut setup = Setup::default();
+        let start = tlv::encode(&[(tlv::STATE, &[1])]);
+        let response = tlv::decode(&pair_setup(&start, &mut setup, &context)).unwrap();
+        assert_eq!(response[&tlv::STATE], [2]);
+        assert!(response.contains_key(&tlv::SALT));
+
+        let guess = tlv::encode(&[
+            (tlv::STATE, &[3]),
+            (tlv::PUBLIC_KEY, &[2; 384]),
+            (tlv::PROOF, &[0; 64]),
+        ]);
+        let response = tlv::decode(&pair_setup(&guess, &mut setup, &context)).unwrap();
+        assert_eq!(response[&tlv::ERROR], [tlv::ERROR_AUTHENTICATION]);
+
+        let response = tlv::decode(&pair_setup(&start, &mut setup, &context)).unwrap();
+        assert_eq!(response[&tlv::ERROR], [tlv::ERROR_MAX_TRIES]);
+        let path = context.store.lock().unwrap().path.clone();
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod verify_tests {
+    use super::tests::{context, remove_state};
+    use super::*;
+
+    const CONTROLLER: &str = "7B3D1E0C-controller";
+
+    /// Controller side of a pair verify, up to the session keys.
+    struct Controller {
+        signing_key: SigningKey,
+        secret: x25519_dalek::StaticSecret,
+    }
+
+    impl Controller {
+        fn new() -> Self {
+            Self {
+                signing_key: SigningKey::from_bytes(&[7; 32]),
+                secret: x25519_dalek::StaticSecret::from([9; 32]),
+            }
+        }
+
+        fn public_key(&self) -> [u8; 32] {
+            x25519_dalek::PublicKey::from(&self.secret).to_bytes()
+        }
+
+        fn pair(&self, context: &Context) {
+            context.store.lock().unwrap().pairings.push(Pairing {
+                id: CONTROLLER.to_owned(),
+                public_key: to_hex(&self.signing_key.verifying_key().to_bytes()),
+                admin: true,
+            });
+        }
+
+        /// Checks the accessory's M2 and answers with M3, returning it with
+        /// the shared secret.
+        fn m3(&self, m2: &HashMap<u8, Vec<u8>>, context: &Context) -> (Vec<u8>, [u8; 32]) {
+            assert_eq!(m2[&tlv::STATE], [2]);
+            let accessory_key: [u8; 32] = m2[&tlv::PUBLIC_KEY].as_slice().try_into().unwrap();
+            let shared_secret = self
+                .secret
+                .diffie_hellman(&x25519_dalek::PublicKey::from(accessory_key))
+                .to_bytes();
+            let key = derive_key(
+                &shared_secret,
+                "Pair-Verify-Encrypt-Salt",
+                "Pair-Verify-Encrypt-Info",
+            );
+            let data = open(&key, &nonce(b"PV-Msg02"), &m2[&tlv::ENCRYPTED_DATA], &[]).unwrap();
+            let items = tlv::decode(&data).unwrap();
+            let store = context.store.lock().unwrap();
+            assert_eq!(items[&tlv::IDENTIFIER], store.device_id.as_bytes());
+            let mut info = accessory_key.to_vec();
+            info.extend_from_slice(store.device_id.as_bytes());
+            info.extend_from_slice(&self.public_key());
+            assert!(verify_signature(
+                &store.signing_key().verifying_key(),
+                &info,
+                &items[&tlv::SIGNATURE]
+            ));
+
+            let mut info = self.public_key().to_vec();
+            info.extend_from_slice(CONTROLLER.as_bytes());
+            info.extend_from_slice(&accessory_key);
+            let signature = self.signing_key.sign(&info).to_bytes();
+            let data = tlv::encode(&[
+                (tlv::IDENTIFIER, CONTROLLER.as_bytes()),
+                (tlv::SIGNATURE, &signature),
+            ]);
+            let m3 = tlv::encode(&[
+                (tlv::STATE, &[3]),
+                (
+                    tlv::ENCRYPTED_DATA,
+                    &seal(&key, &nonce(b"PV-Msg03"), &data, &[]),
+                ),
+            ]);
+            (m3, shared_secret)
+        }
+    }
+
+    fn m1(controller: &Controller) -> Vec<u8> {
+        tlv::encode(&[
+            (tlv::STATE, &[1]),
+            (tlv::PUBLIC_KEY, &controller.public_key()),
+        ])
+    }
+
+    #[test]
+    fn pair_verify_flow() {
+        let context = context("verify");
+        let controller = Controller::new();
+        controller.pair(&context);
+        let mut verify = None;
+        let (m2, verified) = pair_verify(&m1(&controller), &mut verify, &context);
+        assert!(verified.is_none());
+        let (m3, shared_secret) = controller.m3(&tlv::decode(&m2).unwrap(), &context);
+        let (m4, verified) = pair_verify(&m3, &mut verify, &context);
+        assert_eq!(tlv::decode(&m4).unwrap()[&tlv::STATE], [4]);
+        let (mut session, id) = verified.unwrap();
+        assert_eq!(id, CONTROLLER);
+
+        // The frames of the controller decrypt, and those of the accessory
+        // are sealed with the other key
+        let write_key = derive_key(
+            &shared_secret,
+            "Control-Salt",
+            "Control-Write-Encryption-Key",
+        );
+        let read_key = derive_key(
+            &shared_secret,
+            "Control-Salt",
+            "Control-Read-Encryption-Key",
+        );
+        let request = b"GET /accessories HTTP/1.1\r\n\r\n";
+        let length = (request.len() as u16).to_le_bytes();
+        let mut frame = length.to_vec();
+        frame.extend(seal(
+            &write_key,
+            &nonce(&0u64.to_le_bytes()),
+            request,
+            &length,
+        ));
+        // Half a frame waits for the rest
+        let mut buffer = frame[..10].to_vec();
+        assert!(session.decrypt(&mut buffer).unwrap().is_empty());
+        buffer.extend_from_slice(&frame[10..]);
+        assert_eq!(session.decrypt(&mut buffer).unwrap(), request);
+        assert!(buffer.is_empty());
+        let response = session.encrypt(b"HTTP/1.1 204 No Content\r\n\r\n");
+        assert_eq!(
+            open(
+                &read_key,
+                &nonce(&0u64.to_le_bytes()),
+                &response[2..],
+                &response[..2]
+            )
+            .unwrap(),
+            b"HTTP/1.1 204 No Content\r\n\r\n"
+        );
+        // A replayed frame has the wrong nonce
+        assert!(session.decrypt(&mut frame).is_err());
+        remove_state(&context);
+    }
+
+    #[test]
+    fn pair_verify_rejects_unknown_controllers() {
+        let context = context("unpaired");
+        let controller = Controller::new();
+        let mut verify = None;
+        let (m2, _) = pair_verify(&m1(&controller), &mut verify, &context);
+        let (m3, _) = controller.m3(&tlv::decode(&m2).unwrap(), &context);
+        let (m4, verified) = pair_verify(&m3, &mut verify, &context);
+        assert_eq!(
+            tlv::decode(&m4).unwrap()[&tlv::ERROR],
+            [tlv::ERROR_AUTHENTICATION]
+        );
+        assert!(verified.is_none());
+        remove_state(&context);
+    }
+
+    #[test]
+    fn pair_verify_rejects_forged_signatures() {
+        let context = context("forged");
+        Controller::new().pair(&context);
+        let impostor = Controller {
+            signing_key: SigningKey::from_bytes(&[8; 32]),
+            secret: x25519_dalek::StaticSecret::from([9; 32]),
+        };
+        let mut verify = None;
+        let (m2, _) = pair_verify(&m1(&impostor), &mut verify, &context);
+        let (m3, _) = impostor.m3(&tlv::decode(&m2).unwrap(), &context);
+        let (m4, verified) = pair_verify(&m3, &mut verify, &context);
+        assert_eq!(
+            tlv::decode(&m4).unwrap()[&tlv::ERROR],
+            [tlv::ERROR_AUTHENTICATION]
+        );
+        assert!(verified.is_none());
+        // Nor can M3 be sent without M1
+        let (m4, verified) = pair_verify(&m3, &mut verify, &context);
+        assert_eq!(
+            tlv::decode(&m4).unwrap()[&tlv::ERROR],
+            [tlv::ERROR_AUTHENTICATION]
+        );
+        assert!(verified.is_none());
+        remove_state(&context);
+    }
+
+    #[test]
+    fn removed_controllers_lose_their_pairing() {
+        let context = context("removed");
+        let controller = Controller::new();
+        controller.pair(&context);
+        assert!(context.store.lock().unwrap().has_pairing(CONTROLLER));
+        let remove = tlv::encode(&[
+            (tlv::STATE, &[1]),
+            (tlv::METHOD, &[4]),
+            (tlv::IDENTIFIER, CONTROLLER.as_bytes()),
+        ]);
+        let response = tlv::decode(&pairings(&remove, CONTROLLER, &context)).unwrap();
+        assert_eq!(response[&tlv::STATE], [2]);
+        assert!(!context.store.lock().unwrap().has_pairing(CONTROLLER));
+        remove_state(&context);
+    }
+}
@@ -0,0 +1,108 @@
+//! Encrypted framing of the connection after pair verify.
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha512;
+
+/// Longest plaintext carried by a single frame.
+const MAX_FRAME: usize = 1024;
+const TAG_LENGTH: usize = 16;
+
+/// Derives a 32 byte key from `secret` with HKDF-SHA512.
+pub fn derive_key(secret: &[u8], salt: &str, info: &str) -> [u8; 32] {
+    let mut key = [0; 32];
+    Hkdf::<Sha512>::new(Some(salt.as_bytes()), secret)
+        .expand(info.as_bytes(), &mut key)
+        .expect("32 bytes is a valid HKDF-SHA512 output length");
+    key
+}
+
+/// Nonce made of a counter or of a label such as `PS-Msg05`, right aligned.
+pub fn nonce(label: &[u8]) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[12 - label.len()..].copy_from_slice(label);
+    *Nonce::from_slice(&nonce)
+}
+
+pub fn seal(key: &[u8; 32], nonce: &Nonce, plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt(
+            nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .expect("ChaCha20-Poly1305 encryption cannot fail for payloads this size")
+}
+
+pub fn open(key: &[u8; 32], nonce: &Nonce, ciphertext: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(
+            nonce,
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .ok()
+}
+
+pub struct Session {
+    /// Key of the frames sent by the accessory
+    read_key: [u8; 32],
+    /// Key of the frames sent by the controller
+    write_key: [u8; 32],
+    read_counter: u64,
+    write_counter: u64,
+}
+
+impl Session {
+    pub fn new(shared_secret: &[u8]) -> Self {
+        Self {
+            read_key: derive_key(shared_secret, "Control-Salt", "Control-Read-Encryption-Key"),
+            write_key: derive_key(
+                shared_secret,
+                "Control-Salt",
+                "Control-Write-Encryption-Key",
+            ),
+            read_counter: 0,
+            write_counter: 0,
+        }
+    }
+
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let mut frames = Vec::new();
+        for chunk in plaintext.chunks(MAX_FRAME) {
+            let length = (chunk.len() as u16).to_le_bytes();
+            let nonce = nonce(&self.read_counter.to_le_bytes());
+            self.read_counter += 1;
+            frames.extend_from_slice(&length);
+            frames.extend_from_slice(&seal(&self.read_key, &nonce, chunk, &length));
+        }
+        frames
+    }
+
+    /// Decrypts the complete frames at the start of `buffer`, removing them.
+    pub fn decrypt(&mut self, buffer: &mut Vec<u8>) -> Result<Vec<u8>, String> {
+        let mut plaintext = Vec::new();
+        while buffer.len() >= 2 {
+            let length = u16::from_le_bytes([buffer[0], buffer[1]]) as usize;
+            if length > MAX_FRAME {
+                return Err("oversized frame".to_owned());
+            }
+            if buffer.len() < 2 + length + TAG_LENGTH {
+                break;
+            }
+            let nonce = nonce(&self.write_counter.to_le_bytes());
+            self.write_counter += 1;
+            let frame: Vec<u8> = buffer.drain(..2 + length + TAG_LENGTH).collect();
+            plaintext.extend(
+                open(&self.write_key, &nonce, &frame[2..], &frame[..2])
+                    .ok_or_else(|| "could not decrypt frame".to_owned())?,
+            );
+        }
+        Ok(plaintext)
+    }
+}
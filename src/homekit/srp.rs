@@ -0,0 +1,221 @@
+//! Server side of SRP-6a with the 3072 bit group of RFC 5054 and SHA-512,
+//! as used by pair setup.
+//!
+//! The modular exponentiations of `num_bigint` are not constant time. They
+//! only see the ephemeral secret of a single pair setup, pair setup is
+//! refused for good after `pairing::MAX_TRIES` wrong codes, and the proof is
+//! compared in constant time.
+
+use num_bigint::BigUint;
+use sha2::{Digest, Sha512};
+
+const N: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B139B22514A08798E3404DD\
+    EF9519B3CD3A431B302B0A6DF25F14374FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+    EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF0598DA48361C55D39A69163FA8FD24CF5F\
+    83655D23DCA3AD961C62F356208552BB9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B\
+    E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF6955817183995497CEA956AE515D2261898FA0510\
+    15728E5A8AAAC42DAD33170D04507A33A85521ABDF1CBA64ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7\
+    ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA06D98A0864D87602733EC86A64521F2B18177B200C\
+    BBE117577A615D6C770988C0BAD946E208E24FA074E5AB3143DB5BFCE0FD108E4B82D120A93AD2CAFFFFFFFFFFFFFFFF";
+const G: u32 = 5;
+/// Length in bytes of the group elements.
+const LENGTH: usize = 384;
+const USERNAME: &[u8] = b"Pair-Setup";
+
+fn hash(parts: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().to_vec()
+}
+
+fn pad(x: &BigUint) -> Vec<u8> {
+    let bytes = x.to_bytes_be();
+    let mut padded = vec![0; LENGTH.saturating_sub(bytes.len())];
+    padded.extend_from_slice(&bytes);
+    padded
+}
+
+fn random<const L: usize>() -> [u8; L] {
+    let mut bytes = [0; L];
+    getrandom::getrandom(&mut bytes).expect("No random numbers available");
+    bytes
+}
+
+pub struct Server {
+    username: &'static [u8],
+    n: BigUint,
+    g: BigUint,
+    salt: [u8; 16],
+    verifier: BigUint,
+    secret: BigUint,
+    public_key: BigUint,
+}
+
+impl Server {
+    /// Starts an exchange authenticating the setup code `password`.
+    pub fn new(password: &str) -> Self {
+        Self::with(USERNAME, password, random::<16>(), &random::<32>())
+    }
+
+    fn with(username: &'static [u8], password: &str, salt: [u8; 16], secret: &[u8]) -> Self {
+        let n = BigUint::parse_bytes(N.as_bytes(), 16).unwrap();
+        let g = BigUint::from(G);
+        let x = BigUint::from_bytes_be(&hash(&[
+            &salt,
+            &hash(&[username, b":", password.as_bytes()]),
+        ]));
+        let verifier = g.modpow(&x, &n);
+        let k = BigUint::from_bytes_be(&hash(&[&n.to_bytes_be(), &pad(&g)]));
+        let secret = BigUint::from_bytes_be(secret);
+        let public_key = (&k * &verifier + g.modpow(&secret, &n)) % &n;
+        Self {
+            username,
+            n,
+            g,
+            salt,
+            verifier,
+            secret,
+            public_key,
+        }
+    }
+
+    pub fn salt(&self) -> &[u8] {
+        &self.salt
+    }
+
+    pub fn public_key(&self) -> Vec<u8> {
+        self.public_key.to_bytes_be()
+    }
+
+    /// Checks the client's public key and proof, returning the server proof
+    /// and the shared session key if they are valid.
+    pub fn verify(&self, client_public_key: &[u8], proof: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+        let a = BigUint::from_bytes_be(client_public_key);
+        if (&a % &self.n) == BigUint::from(0u32) {
+            return None;
+        }
+        let u = BigUint::from_bytes_be(&hash(&[&pad(&a), &pad(&self.public_key)]));
+        let shared = (&a * self.verifier.modpow(&u, &self.n)).modpow(&self.secret, &self.n);
+        let key = hash(&[&shared.to_bytes_be()]);
+        let group_hash: Vec<u8> = hash(&[&self.n.to_bytes_be()])
+            .iter()
+            .zip(hash(&[&self.g.to_bytes_be()]))
+            .map(|(n, g)| n ^ g)
+            .collect();
+        let expected = hash(&[
+            &group_hash,
+            &hash(&[self.username]),
+            &self.salt,
+            &a.to_bytes_be(),
+            &self.public_key.to_bytes_be(),
+            &key,
+        ]);
+        // Compared in constant time, not to leak how much of the proof matched
+        let difference = expected
+            .iter()
+            .zip(proof)
+            .fold(0, |difference, (a, b)| difference | (a ^ b));
+        if proof.len() != expected.len() || difference != 0 {
+            return None;
+        }
+        Some((hash(&[&a.to_bytes_be(), proof, &key]), key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    // Test vectors of the HomeKit Accessory Protocol specification
+    const SALT: &str = "BEB25379D1A8581EB5A727673A2441EE";
+    const SECRET: &str = "E487CB59D31AC550471E81F00F6928E01DDA08E974A004F49E61F5D105284D20";
+    const VERIFIER: &str = "\
+        9B5E061701EA7AEB39CF6E3519655A853CF94C75CAF2555EF1FAF759BB79CB477014E04A88D68FFC05323891D4C205B8\
+        DE81C2F203D8FAD1B24D2C109737F1BEBBD71F912447C4A03C26B9FAD8EDB3E780778E302529ED1EE138CCFC36D4BA31\
+        3CC48B14EA8C22A0186B222E655F2DF5603FD75DF76B3B08FF8950069ADD03A754EE4AE88587CCE1BFDE36794DBAE459\
+        2B7B904F442B041CB17AEBAD1E3AEBE3CBE99DE65F4BB1FA00B0E7AF06863DB53B02254EC66E781E3B62A8212C86BEB0\
+        D50B5BA6D0B478D8C4E9BBCEC21765326FBD14058D2BBDE2C33045F03873E53948D78B794F0790E48C36AED6E880F557\
+        427B2FC06DB5E1E2E1D7E661AC482D18E528D7295EF7437295FF1A72D402771713F16876DD050AE5B7AD53CCB90855C9\
+        3956648358ADFD966422F52498732D68D1D7FBEF10D78034AB8DCB6F0FCF885CC2B2EA2C3E6AC86609EA058A9DA8CC63\
+        531DC915414DF568B09482DDAC1954DEC7EB714F6FF7D44CD5B86F6BD115810930637C01D0F6013BC9740FA2C633BA89";
+    const CLIENT_PUBLIC_KEY: &str = "\
+        FAB6F5D2615D1E323512E7991CC37443F487DA604CA8C9230FCB04E541DCE6280B27CA4680B0374F179DC3BDC7553FE6\
+        2459798C701AD864A91390A28C93B644ADBF9C00745B942B79F9012A21B9B78782319D83A1F8362866FBD6F46BFC0DDB\
+        2E1AB6E4B45A9906B82E37F05D6F97F6A3EB6E182079759C4F6847837B62321AC1B4FA68641FCB4BB98DD697A0C73641\
+        385F4BAB25B793584CC39FC8D48D4BD867A9A3C10F8EA12170268E34FE3BBE6FF89998D60DA2F3E4283CBEC1393D52AF\
+        724A57230C604E9FBCE583D7613E6BFFD67596AD121A8707EEC46944957033686A155F644D5C5863B48F61BDBF19A53E\
+        AB6DAD0A186B8C152E5F5D8CAD4B0EF8AA4EA5008834C3CD342E5E0F167AD04592CD8BD279639398EF9E114DFAAAB919\
+        E14E850989224DDD98576D79385D2210902E9F9B1F2D86CFA47EE244635465F71058421A0184BE51DD10CC9D079E6F16\
+        04E7AA9B7CF7883C7D4CE12B06EBE16081E23F27A231D18432D7D1BB55C28AE21FFCF005F57528D15A88881BB3BBB7FE";
+    const PUBLIC_KEY: &str = "\
+        40F57088A482D4C7733384FE0D301FDDCA9080AD7D4F6FDF09A01006C3CB6D562E41639AE8FA21DE3B5DBA7585B27558\
+        9BDB279863C562807B2B99083CD1429CDBE89E25BFBD7E3CAD3173B2E3C5A0B174DA6D5391E6A06E465F037A40062548\
+        39A56BF76DA84B1C94E0AE208576156FE5C140A4BA4FFC9E38C3B07B88845FC6F7DDDA93381FE0CA6084C4CD2D336E54\
+        51C464CCB6EC65E7D16E548A273E826284AF2559B6264274215960FFF47BDD63D3AFF064D6137AF769661C9D4FEE4738\
+        2603C88EAA0980581D07758461B777E4356DDA5835198B51FEEA308D70F75450B71675C08C7D8302FD7539DD1FF2A11C\
+        B4258AA70D234436AA42B6A0615F3F915D55CC3B966B2716B36E4D1A06CE5E5D2EA3BEE5A1270E8751DA45B60B997B0F\
+        FDB0F9962FEE4F03BEE780BA0A845B1D9271421783AE6601A61EA2E342E4F2E8BC935A409EAD19F221BD1B74E2964DD1\
+        9FC845F60EFC09338B60B6B256D8CAC889CCA306CC370A0B18C8B886E95DA0AF5235FEF4393020D2B7F3056904759042";
+    const KEY: &str = "\
+        5CBC219DB052138EE1148C71CD4498963D682549CE91CA24F098468F06015BEB6AF245C2093F98C3651BCA83AB8CAB2B\
+        580BBF02184FEFDF26142F73DF95AC50";
+    const CLIENT_PROOF: &str = "\
+        5F7C14AB57ED0E94FD1D78C6B4DD09ED7E340B7E05D419A9FD760F6B35E523D1310777A1AE1D2826F596F3A85116CC45\
+        7C7C964D4F44DED5559DA818C88B617F";
+    const PROOF: &str = "\
+        2FA0E81F5CB73B88FA0964270F321DD641F2227A5D805C40F1BFE96AAF6A19FFCE8E23287965A39EAB9D5A02215F89E1\
+        28177ED2C4F103E655A045531BCBF7AD";
+
+    fn bytes(hex: &str) -> Vec<u8> {
+        BigUint::parse_bytes(hex.as_bytes(), 16)
+            .unwrap()
+            .to_bytes_be()
+    }
+
+    fn server() -> Server {
+        Server::with(
+            b"alice",
+            "password123",
+            bytes(SALT).try_into().unwrap(),
+            &bytes(SECRET),
+        )
+    }
+
+    #[test]
+    fn verifier_and_public_key() {
+        let server = server();
+        assert_eq!(server.verifier.to_bytes_be(), bytes(VERIFIER));
+        assert_eq!(server.public_key(), bytes(PUBLIC_KEY));
+    }
+
+    #[test]
+    fn proofs_and_key() {
+        let (proof, key) = server()
+            .verify(&bytes(CLIENT_PUBLIC_KEY), &bytes(CLIENT_PROOF))
+            .unwrap();
+        assert_eq!(proof, bytes(PROOF));
+        assert_eq!(key, bytes(KEY));
+    }
+
+    #[test]
+    fn wrong_proof() {
+        let mut proof = bytes(CLIENT_PROOF);
+        proof[0] ^= 1;
+        assert!(server().verify(&bytes(CLIENT_PUBLIC_KEY), &proof).is_none());
+        assert!(server()
+            .verify(&bytes(CLIENT_PUBLIC_KEY), &proof[..32])
+            .is_none());
+    }
+
+    #[test]
+    fn zero_public_key() {
+        let n = BigUint::parse_bytes(N.as_bytes(), 16).unwrap();
+        assert!(server()
+            .verify(&n.to_bytes_be(), &bytes(CLIENT_PROOF))
+            .is_none());
+    }
+}
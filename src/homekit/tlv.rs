@@ -0,0 +1,63 @@
+//! TLV8 encoding used by the pairing endpoints.
+
+use std::collections::HashMap;
+
+pub const METHOD: u8 = 0x00;
+pub const IDENTIFIER: u8 = 0x01;
+pub const SALT: u8 = 0x02;
+pub const PUBLIC_KEY: u8 = 0x03;
+pub const PROOF: u8 = 0x04;
+pub const ENCRYPTED_DATA: u8 = 0x05;
+pub const STATE: u8 = 0x06;
+pub const ERROR: u8 = 0x07;
+pub const SIGNATURE: u8 = 0x0A;
+pub const PERMISSIONS: u8 = 0x0B;
+pub const SEPARATOR: u8 = 0xFF;
+
+pub const ERROR_UNKNOWN: u8 = 0x01;
+pub const ERROR_AUTHENTICATION: u8 = 0x02;
+pub const ERROR_MAX_PEERS: u8 = 0x04;
+pub const ERROR_MAX_TRIES: u8 = 0x05;
+pub const ERROR_UNAVAILABLE: u8 = 0x06;
+
+/// Encodes the items, splitting values longer than 255 bytes in fragments.
+pub fn encode(items: &[(u8, &[u8])]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (kind, value) in items {
+        if value.is_empty() {
+            data.extend_from_slice(&[*kind, 0]);
+        }
+        for chunk in value.chunks(255) {
+            data.push(*kind);
+            data.push(chunk.len() as u8);
+            data.extend_from_slice(chunk);
+        }
+    }
+    data
+}
+
+/// Decodes the items, joining consecutive fragments of the same type.
+pub fn decode(data: &[u8]) -> Result<HashMap<u8, Vec<u8>>, String> {
+    let mut items: HashMap<u8, Vec<u8>> = HashMap::new();
+    let mut previous = None;
+    let mut rest = data;
+    while !rest.is_empty() {
+        if rest.len() < 2 || rest.len() < 2 + rest[1] as usize {
+            return Err("truncated TLV item".to_owned());
+        }
+        let (kind, value) = (rest[0], &rest[2..2 + rest[1] as usize]);
+        if previous == Some(kind) {
+            items.get_mut(&kind).unwrap().extend_from_slice(value);
+        } else {
+            items.insert(kind, value.to_vec());
+        }
+        previous = Some(kind);
+        rest = &rest[2 + value.len()..];
+    }
+    Ok(items)
+}
+
+/// A response reporting `error` at pairing step `state`.
+pub fn error(state: u8, error: u8) -> Vec<u8> {
+    encode(&[(STATE, &[state]), (ERROR, &[error])])
+}
@@ -0,0 +1,114 @@
+//! Layout of the topics following the Homie 4.0 convention, alongside the
+//! plain one, so that controllers such as openHAB discover the device.
+//!
+//! The device has a single `position` node with the `phase`, `altitude` and
+//! `azimuth` properties, under `homie/<HOMIE_DEVICE_ID>`.
+//!
+//! `$state` is published explicitly: `init` and `ready` on every connection
+//! and `disconnected` on shutdown. The connection's only last will is kept
+//! for `sun/availability`, so a crash does not set `$state` to `lost`, and
+//! controllers that must notice it should also watch the availability topic.
+
+use crate::phase::SunPosition;
+
+pub struct Homie {
+    base: String,
+    phase: Option<SunPosition>,
+}
+
+impl Homie {
+    /// Enabled by `HOMIE`, for the device `HOMIE_DEVICE_ID` (default `sun`).
+    pub fn from_env() -> Option<Self> {
+        let enabled = std::env::var("HOMIE")
+            .map(|x| x.parse().expect("Invalid HOMIE, expected true or false"))
+            .unwrap_or(false);
+        if !enabled {
+            return None;
+        }
+        let device_id = std::env::var("HOMIE_DEVICE_ID").unwrap_or_else(|_| "sun".to_owned());
+        // Homie IDs are lowercase letters, digits and hyphens, not starting
+        // with a hyphen
+        assert!(
+            !device_id.is_empty()
+                && !device_id.starts_with('-')
+                && device_id
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-'),
+            "Invalid HOMIE_DEVICE_ID {}, expected lowercase letters, digits and -",
+            device_id
+        );
+        Some(Self {
+            base: format!("homie/{}", device_id),
+            phase: None,
+        })
+    }
+
+    /// Publishes the device for the named `location` as `<HOMIE_DEVICE_ID>-<location>`.
+    pub fn with_location(self, location: &str) -> Self {
+        Self {
+            base: format!("{}-{}", self.base, location),
+            ..self
+        }
+    }
+
+    /// Topic of the `$state` attribute.
+    pub fn state_topic(&self) -> String {
+        format!("{}/$state", self.base)
+    }
+
+    /// Attributes of the device, node and properties, and the last phase, to
+    /// publish retained between the `init` and `ready` states.
+    pub fn attributes(&self) -> Vec<(String, String)> {
+        let phases: Vec<&'static str> = SunPosition::ALL.iter().map(|x| x.into()).collect();
+        let attributes = [
+            ("$homie", "4.0".to_owned()),
+            ("$name", "Sun".to_owned()),
+            ("$nodes", "position".to_owned()),
+            ("position/$name", "Position".to_owned()),
+            ("position/$type", "sun".to_owned()),
+            ("position/$properties", "phase,altitude,azimuth".to_owned()),
+            ("position/phase/$name", "Phase".to_owned()),
+            ("position/phase/$datatype", "enum".to_owned()),
+            ("position/phase/$format", phases.join(",")),
+            ("position/altitude/$name", "Altitude".to_owned()),
+            ("position/altitude/$datatype", "float".to_owned()),
+            ("position/altitude/$unit", "°".to_owned()),
+            ("position/altitude/$format", "-90:90".to_owned()),
+            ("position/azimuth/$name", "Azimuth".to_owned()),
+            ("position/azimuth/$datatype", "float".to_owned()),
+            ("position/azimuth/$unit", "°".to_owned()),
+            ("position/azimuth/$format", "0:360".to_owned()),
+        ];
+        let mut messages: Vec<_> = attributes
+            .iter()
+            .map(|(topic, value)| (format!("{}/{}", self.base, topic), value.clone()))
+            .collect();
+        messages.extend(self.phase.map(|x| self.phase_message(&x)));
+        messages
+    }
+
+    fn phase_message(&self, phase: &SunPosition) -> (String, String) {
+        let name: &'static str = phase.into();
+        (format!("{}/position/phase", self.base), name.to_owned())
+    }
+
+    /// Records the phase the sun entered, returning the value to publish.
+    pub fn set_phase(&mut self, phase: &SunPosition) -> (String, String) {
+        self.phase = Some(*phase);
+        self.phase_message(phase)
+    }
+
+    /// Values of the position properties, with angles in degrees.
+    pub fn position(&self, altitude: f64, azimuth: f64) -> Vec<(String, String)> {
+        vec![
+            (
+                format!("{}/position/altitude", self.base),
+                format!("{:.2}", altitude),
+            ),
+            (
+                format!("{}/position/azimuth", self.base),
+                format!("{:.2}", azimuth),
+            ),
+        ]
+    }
+}
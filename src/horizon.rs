@@ -0,0 +1,171 @@
+//! Local horizon hiding the sun behind hills, trees or buildings, so that
+//! the apparent sunrise and sunset, when the sun actually clears it or goes
+//! behind it, are announced on `sun/apparent`.
+//!
+//! The profile is a list of `azimuth:elevation` points in degrees, given in
+//! `HORIZON_PROFILE` as `0:5,90:12,180:3,270:8` or in the CSV file named by
+//! `HORIZON_PROFILE_FILE`, one `azimuth,elevation` point per line. The
+//! elevation is interpolated linearly between the points, around north too.
+
+pub const TOPIC: &str = "sun/apparent";
+
+/// Seconds between the samples looking for the sun crossing the profile.
+const SAMPLE_STEP: i64 = 5 * 60;
+
+#[derive(Debug)]
+pub struct Horizon {
+    /// Azimuth and elevation in degrees, sorted by azimuth
+    points: Vec<(f64, f64)>,
+    visible: Option<bool>,
+}
+
+/// Parses one `azimuth` and `elevation` pair, in degrees.
+fn point(azimuth: &str, elevation: &str) -> Result<(f64, f64), String> {
+    let parse = |x: &str| {
+        x.trim()
+            .parse::<f64>()
+            .map_err(|_| format!("invalid angle `{}`", x.trim()))
+    };
+    let elevation = parse(elevation)?;
+    if !(-90.0..=90.0).contains(&elevation) {
+        return Err(format!("elevation {} is not between -90 and 90", elevation));
+    }
+    Ok((parse(azimuth)?.rem_euclid(360.0), elevation))
+}
+
+impl Horizon {
+    fn new(mut points: Vec<(f64, f64)>) -> Result<Self, String> {
+        if points.is_empty() {
+            return Err("no points".to_owned());
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(Self {
+            points,
+            visible: None,
+        })
+    }
+
+    /// Parses the comma separated `azimuth:elevation` points.
+    pub fn parse(profile: &str) -> Result<Self, String> {
+        let points = profile
+            .split(',')
+            .filter(|x| !x.trim().is_empty())
+            .map(|x| {
+                let (azimuth, elevation) = x
+                    .split_once(':')
+                    .ok_or_else(|| format!("{} is not azimuth:elevation", x.trim()))?;
+                point(azimuth, elevation)
+            })
+            .collect::<Result<_, _>>()?;
+        Self::new(points)
+    }
+
+    /// Parses a CSV file of `azimuth,elevation` lines, skipping empty lines,
+    /// `#` comments and a header.
+    pub fn parse_csv(csv: &str) -> Result<Self, String> {
+        let mut points = Vec::new();
+        for (number, line) in csv.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (azimuth, elevation) = line
+                .split_once(',')
+                .ok_or_else(|| format!("line {} is not azimuth,elevation", number + 1))?;
+            match point(azimuth, elevation) {
+                Ok(x) => points.push(x),
+                Err(_) if points.is_empty() && azimuth.trim().parse::<f64>().is_err() => {}
+                Err(e) => return Err(format!("line {}: {}", number + 1, e)),
+            }
+        }
+        Self::new(points)
+    }
+
+    /// Reads the profile from `HORIZON_PROFILE` or `HORIZON_PROFILE_FILE`.
+    pub fn from_env() -> Option<Self> {
+        let horizon = match (
+            std::env::var("HORIZON_PROFILE"),
+            std::env::var("HORIZON_PROFILE_FILE"),
+        ) {
+            (Ok(_), Ok(_)) => {
+                panic!("Only one of HORIZON_PROFILE and HORIZON_PROFILE_FILE can be set")
+            }
+            (Ok(profile), Err(_)) => {
+                Self::parse(&profile).unwrap_or_else(|e| panic!("Invalid HORIZON_PROFILE: {}", e))
+            }
+            (Err(_), Ok(path)) => {
+                let csv = std::fs::read_to_string(&path)
+                    .unwrap_or_else(|e| panic!("Could not read {}: {}", path, e));
+                Self::parse_csv(&csv).unwrap_or_else(|e| panic!("Invalid {}: {}", path, e))
+            }
+            (Err(_), Err(_)) => return None,
+        };
+        Some(horizon)
+    }
+
+    /// Elevation of the horizon in degrees towards `azimuth`.
+    pub fn elevation(&self, azimuth: f64) -> f64 {
+        let azimuth = azimuth.rem_euclid(360.0);
+        let after = self.points.iter().position(|x| x.0 >= azimuth).unwrap_or(0);
+        let before = (after + self.points.len() - 1) % self.points.len();
+        let (from, to) = (self.points[before], self.points[after]);
+        let span = (to.0 - from.0).rem_euclid(360.0);
+        if span == 0.0 {
+            return from.1;
+        }
+        from.1 + (to.1 - from.1) * (azimuth - from.0).rem_euclid(360.0) / span
+    }
+
+    /// Degrees the sun is above the horizon at `timestamp`, negative when
+    /// it is hidden.
+    fn clearance(&self, timestamp: i64, coords: &astro::coords::GeographPoint) -> f64 {
+        let position = crate::solar::pos(timestamp * 1000, coords.lat, coords.long);
+        position.altitude.to_degrees() - self.elevation(position.azimuth.to_degrees())
+    }
+
+    /// Feeds the position of the sun in degrees, returning `apparentSunrise`
+    /// or `apparentSunset` if it cleared or went behind the horizon. The
+    /// first sample only announces a sun already visible.
+    pub fn update(&mut self, azimuth: f64, altitude: f64) -> Option<&'static str> {
+        let visible = altitude >= self.elevation(azimuth);
+        if self.visible == Some(visible) {
+            return None;
+        }
+        let first = self.visible.is_none();
+        self.visible = Some(visible);
+        match (visible, first) {
+            (true, _) => Some("apparentSunrise"),
+            (false, false) => Some("apparentSunset"),
+            (false, true) => None,
+        }
+    }
+
+    /// Forgets the last sample, after the observer moved.
+    pub fn reset(&mut self) {
+        self.visible = None;
+    }
+
+    /// First instant in `[from, to)` at which the sun clears or goes behind
+    /// the horizon, to the second.
+    pub fn next(&self, from: i64, to: i64, coords: &astro::coords::GeographPoint) -> Option<i64> {
+        let mut current = (from, self.clearance(from, coords) >= 0.0);
+        while current.0 < to {
+            let next_time = (current.0 + SAMPLE_STEP).min(to);
+            let next = (next_time, self.clearance(next_time, coords) >= 0.0);
+            if next.1 != current.1 {
+                let (mut from, mut to) = (current.0, next.0);
+                while to - from > 1 {
+                    let mid = (from + to) / 2;
+                    if (self.clearance(mid, coords) >= 0.0) == current.1 {
+                        from = mid;
+                    } else {
+                        to = mid;
+                    }
+                }
+                return Some(to);
+            }
+            current = next;
+        }
+        None
+    }
+}
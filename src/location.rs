@@ -0,0 +1,112 @@
+//! Observer location, given either as `LAT`/`LON` or as a Maidenhead grid
+//! locator in `GRID`, or several named ones in `LOCATIONS`.
+
+/// Converts a Maidenhead locator of 2 to 8 characters, such as `JN54qk`, to
+/// the centre of the square it designates.
+pub fn from_maidenhead(locator: &str) -> Result<astro::coords::GeographPoint, String> {
+    let chars: Vec<char> = locator.trim().to_ascii_uppercase().chars().collect();
+    if chars.is_empty() || chars.len() > 8 || !chars.len().is_multiple_of(2) {
+        return Err(format!("invalid grid locator `{}`", locator));
+    }
+    // Alternating letter and digit pairs, each dividing the previous cell
+    let divisions = [18u32, 10, 24, 10];
+    let (mut long, mut lat) = (-180.0, -90.0);
+    let (mut long_size, mut lat_size) = (360.0, 180.0);
+    for (pair, &division) in chars.chunks(2).zip(divisions.iter()) {
+        let base = if division == 10 { '0' } else { 'A' };
+        let index = |c: char| {
+            (c as u32)
+                .checked_sub(base as u32)
+                .filter(|&x| x < division)
+                .ok_or_else(|| format!("invalid grid locator `{}`", locator))
+        };
+        long_size /= division as f64;
+        lat_size /= division as f64;
+        long += index(pair[0])? as f64 * long_size;
+        lat += index(pair[1])? as f64 * lat_size;
+    }
+    Ok(astro::coords::GeographPoint {
+        long: long + long_size / 2.0,
+        lat: lat + lat_size / 2.0,
+    })
+}
+
+pub fn from_env() -> astro::coords::GeographPoint {
+    if let Ok(grid) = std::env::var("GRID") {
+        assert!(
+            std::env::var_os("LAT").is_none() && std::env::var_os("LON").is_none(),
+            "Only one of GRID and LAT/LON can be set"
+        );
+        return from_maidenhead(&grid).expect("Invalid grid locator");
+    }
+    astro::coords::GeographPoint {
+        long: std::env::var("LON")
+            .expect("Missing longitude")
+            .parse()
+            .expect("Invalid longitude"),
+        lat: std::env::var("LAT")
+            .expect("Missing latitude")
+            .parse()
+            .expect("Invalid latitude"),
+    }
+}
+
+/// Parses a location given as `lat,lon` or as a grid locator.
+fn parse(location: &str) -> Result<astro::coords::GeographPoint, String> {
+    let (lat, lon) = match location.split_once(',') {
+        Some(x) => x,
+        None => return from_maidenhead(location),
+    };
+    let lat: f64 = lat
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid latitude `{}`", lat))?;
+    let lon: f64 = lon
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid longitude `{}`", lon))?;
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return Err(format!("`{}` is out of range", location));
+    }
+    Ok(astro::coords::GeographPoint { long: lon, lat })
+}
+
+/// Parses named locations such as `home=44.5,11.3;cabin=JN56ab`. Names are
+/// made of lowercase letters, digits and hyphens, so that they fit in every
+/// topic and ID derived from them.
+pub fn parse_named(locations: &str) -> Result<Vec<(String, astro::coords::GeographPoint)>, String> {
+    let mut named: Vec<(String, astro::coords::GeographPoint)> = Vec::new();
+    for entry in locations
+        .split(';')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+    {
+        let (name, location) = entry
+            .split_once('=')
+            .ok_or_else(|| format!("`{}` is not name=location", entry))?;
+        let name = name.trim();
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        {
+            return Err(format!(
+                "invalid name `{}`, expected lowercase letters, digits and -",
+                name
+            ));
+        }
+        if named.iter().any(|(x, _)| x == name) {
+            return Err(format!("duplicate location `{}`", name));
+        }
+        let coords = parse(location).map_err(|e| format!("{}: {}", name, e))?;
+        named.push((name.to_owned(), coords));
+    }
+    Ok(named)
+}
+
+/// Named locations from `LOCATIONS`, empty if it is not set.
+pub fn named_from_env() -> Vec<(String, astro::coords::GeographPoint)> {
+    std::env::var("LOCATIONS")
+        .map(|x| parse_named(&x).unwrap_or_else(|e| panic!("Invalid LOCATIONS: {}", e)))
+        .unwrap_or_default()
+}
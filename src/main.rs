@@ -1,82 +1,84 @@
-use chrono::{Datelike, Timelike};
-use log::{info, LevelFilter};
-use rumqttc::{Client, MqttOptions, QoS};
+use log::{info, warn, LevelFilter};
+use phase::SunPosition;
+use publisher::Publisher;
 use simple_logger::SimpleLogger;
 use syslog::{BasicLogger, Facility, Formatter3164};
 
-#[derive(Debug, PartialEq)]
-enum SunPosition {
-    Night,
-    AstronomicalDawn,
-    NauticalDawn,
-    CivilDawn,
-    Sunrise,
-    Sunset,
-    CivilDusk,
-    NauticalDusk,
-    AstronomicalDusk,
-    SolarNoon,
-}
-
-impl From<&SunPosition> for &'static str {
-    fn from(s: &SunPosition) -> Self {
-        match s {
-            SunPosition::Night => "night",
-            SunPosition::AstronomicalDawn => "astronomicalDawn",
-            SunPosition::NauticalDawn => "nauticalDawn",
-            SunPosition::CivilDawn => "civilDawn",
-            SunPosition::Sunrise => "sunrise",
-            SunPosition::Sunset => "sunset",
-            SunPosition::CivilDusk => "civilDusk",
-            SunPosition::NauticalDusk => "nauticalDusk",
-            SunPosition::AstronomicalDusk => "astronomicalDusk",
-            SunPosition::SolarNoon => "solarNoon",
-        }
-    }
-}
-
-impl From<(f64, bool)> for SunPosition {
-    fn from(angle: (f64, bool)) -> Self {
-        let is_morning = angle.1;
-        let angle = angle.0.to_degrees() as i8;
-        if is_morning {
-            match angle {
-                -18..=-13 => Self::AstronomicalDawn,
-                -12..=-7 => Self::NauticalDawn,
-                -6..=-1 => Self::CivilDawn,
-                0..=90 => Self::Sunrise,
-                _ => Self::Night,
-            }
-        } else {
-            match angle {
-                -18..=-13 => Self::AstronomicalDusk,
-                -12..=-7 => Self::NauticalDusk,
-                -6..=-1 => Self::CivilDusk,
-                0..=90 => Self::Sunset,
-                _ => Self::Night,
-            }
-        }
-    }
-}
-
-fn get_mqtt_conn(server: &str) -> Client {
-    let mut mqttoptions = MqttOptions::new(
-        "rust_mqtt_sun",
-        server,
-        std::env::var("MQTT_PORT")
-            .map(|x| x.parse().unwrap_or(1883))
-            .unwrap_or(1883),
-    );
-    mqttoptions.set_keep_alive(5);
-
-    let (client, mut connection) = Client::new(mqttoptions, 10);
-    std::thread::spawn(move || for _ in connection.iter() {});
-    client
-}
+mod almanac;
+mod ambient;
+mod band;
+mod bearing;
+mod broker;
+mod circadian;
+mod clear_sky;
+mod cli;
+mod clock;
+mod command;
+mod config;
+mod countdown;
+mod curve;
+mod dark_window;
+mod day_cycle;
+mod day_length;
+mod discovery;
+mod distance;
+mod encryption;
+mod ephemeris;
+mod event_log;
+mod facade;
+mod geometry;
+mod greyline;
+mod heartbeat;
+mod homekit;
+mod homie;
+mod horizon;
+mod location;
+mod missed;
+mod modbus;
+mod moon;
+mod mqtt;
+mod offsets;
+mod owntracks;
+mod payload;
+mod phase;
+mod phase_topics;
+mod photography;
+mod polar;
+mod prayer;
+mod publisher;
+mod pv;
+mod query;
+mod sampling;
+mod schedule;
+mod shadow;
+mod signing;
+mod sinks;
+mod solar;
+mod solar_terms;
+mod state;
+mod summary;
+mod sunburn;
+mod switch;
+mod terminator;
+mod timezone;
+mod topics;
+mod tracker;
+mod watch;
 
+/// Logs at `LOG_LEVEL` (default info) to syslog, or to the terminal if
+/// `LOG_SYSLOG` is false or this is a debug build.
 fn init_logger() {
-    if cfg!(debug_assertions) {
-        SimpleLogger::new().init().unwrap();
+    let level = std::env::var("LOG_LEVEL")
+        .map(|x| x.parse().expect("Invalid LOG_LEVEL"))
+        .unwrap_or(LevelFilter::Info);
+    let syslog = std::env::var("LOG_SYSLOG")
+        .map(|x| {
+            x.parse()
+                .expect("Invalid LOG_SYSLOG, expected true or false")
+        })
+        .unwrap_or(!cfg!(debug_assertions));
+    if !syslog {
+        SimpleLogger::new().with_level(level).init().unwrap();
     } else {
         let formatter = Formatter3164 {
             facility: Facility::LOG_SYSLOG,
@@ -87,70 +89,73 @@ fn init_logger() {
 
         let logger = syslog::unix(formatter).expect("could not connect to syslog");
         log::set_boxed_logger(Box::new(BasicLogger::new(logger)))
-            .map(|()| log::set_max_level(LevelFilter::Info))
+            .map(|()| log::set_max_level(level))
             .expect("Couldn't setup logger!");
     }
 }
 
-fn publish_event(conn: &mut Client, event: &SunPosition, topic: &'static str) {
+fn publish_event(
+    conn: &mut Publisher,
+    sinks: &sinks::Sinks,
+    homie: Option<&mut homie::Homie>,
+    payloads: &payload::Payloads,
+    coords: &astro::coords::GeographPoint,
+    event: &SunPosition,
+    topic: &str,
+) {
     let camel_case_sun_pos: &'static str = (event).into();
-    conn.publish(
-        topic,
-        QoS::ExactlyOnce,
-        false,
-        camel_case_sun_pos.as_bytes(),
-    )
-    .unwrap_or_else(|_| log::error!("Could not publish event to MQTT server"));
+    let now = chrono::Utc::now().timestamp();
+    conn.publish_state(topic, &payloads.phase(event, now, coords));
+    if let Some(code) = payloads.code(event) {
+        conn.publish_state("sun/code", &code);
+    }
+    if let Some(homie) = homie {
+        let (topic, value) = homie.set_phase(event);
+        conn.publish_plain_retained(&topic, &value);
+    }
+    sinks.notify(sinks::Event {
+        name: camel_case_sun_pos.to_owned(),
+        timestamp: now,
+    });
 }
 
-fn date_to_julian(date: &chrono::Date<chrono::Local>) -> f64 {
-    let today_greg = astro::time::Date {
-        year: date.year() as i16,
-        month: date.month() as u8,
-        decimal_day: date.day() as f64,
-        cal_type: astro::time::CalType::Gregorian,
-    };
-    astro::time::julian_day(&today_greg)
+/// Publishes the next `count` events on `sun/upcoming` and the very next one,
+/// with the seconds left until it, on `sun/next`.
+fn publish_upcoming(
+    conn: &mut Publisher,
+    coords: &astro::coords::GeographPoint,
+    thresholds: &phase::Thresholds,
+    count: usize,
+) {
+    let now = chrono::Utc::now().timestamp();
+    let events = schedule::upcoming(now, count.max(1), coords, thresholds);
+    let upcoming: Vec<_> = events.iter().take(count).map(|e| e.to_json()).collect();
+    conn.publish_retained(
+        "sun/upcoming",
+        &serde_json::Value::from(upcoming).to_string(),
+    );
+    if let Some(next) = events.first() {
+        let mut payload = next.to_json();
+        payload["seconds"] = (next.timestamp - now).into();
+        conn.publish_retained("sun/next", &payload.to_string());
+    }
 }
 
-fn today_solar_noon(over: &astro::coords::GeographPoint) -> i64 {
-    let today = chrono::Local::today();
-    let today_jul = date_to_julian(&today);
-    let today_jul_century = astro::time::julian_cent(today_jul);
-    let sun_long =
-        (280.46646 + today_jul_century * (36000.76983 + today_jul_century * 0.0003032)) % 360.0;
-    let sun_anom = 357.52911 + today_jul_century * (35999.05029 - 0.0001537 * today_jul_century);
-    let eccent_eart_orbit =
-        0.016708634 - today_jul_century * (0.000042037 + 0.0000001267 * today_jul_century);
-    let mean_obliq_ecliptic_corr = 23.0
-        + (26.0
-            + (21.448
-                - today_jul_century
-                    * (46.815 + today_jul_century * (0.00059 - today_jul_century * 0.001813)))
-                / 60.0)
-            / 60.0
-        + 0.00256 * (125.04 - 1934.136 * today_jul_century).to_radians().cos();
-
-    let var_y = (mean_obliq_ecliptic_corr / 2.0).to_radians().tan().powi(2);
-    let eq_of_time = 4.0
-        * (var_y * (2.0 * sun_long.to_radians()).sin()
-            - 2.0 * eccent_eart_orbit * sun_anom.to_radians().sin()
-            + 4.0
-                * eccent_eart_orbit
-                * var_y
-                * sun_anom.to_radians().sin()
-                * (2.0 * sun_long.to_radians()).cos()
-            - 0.5 * var_y.powi(2) * (4.0 * sun_long.to_radians())
-            - 1.25 * eccent_eart_orbit.powi(2) * (2.0 * sun_anom.to_radians()).sin())
-        .to_degrees();
-
-    let solar_noon_after = (720.0 - 4.0 * over.long - eq_of_time) / 1440.0;
+fn publish_dark_window(
+    conn: &mut Publisher,
+    coords: &astro::coords::GeographPoint,
+    thresholds: &phase::Thresholds,
+) {
+    let now = chrono::Utc::now().timestamp();
+    let window = dark_window::tonight(now, coords, thresholds);
+    if let Some((start, end)) = window {
+        info!("Tonight's dark window spans {} to {}", start, end);
+    }
+    conn.publish_retained("sun/dark_window", &dark_window::to_json(window).to_string());
+}
 
-    today
-        .naive_local()
-        .signed_duration_since(chrono::NaiveDate::from_ymd_opt(1970, 1, 1).unwrap())
-        .num_seconds()
-        + (solar_noon_after * 24.0 * 3600.0) as i64
+fn today_solar_noon(over: &astro::coords::GeographPoint) -> i64 {
+    ephemeris::transit_on(chrono::Local::today().naive_local(), over.long)
 }
 
 fn main() -> ! {
@@ -168,50 +173,1114 @@ fn main() -> ! {
                 .to_degrees()
         )
     }*/
+    let cli = cli::Cli::from_args();
+    timezone::apply_from_env();
+    match cli.command {
+        Some(cli::Command::Log(args)) => event_log::run(&args),
+        Some(cli::Command::Watch) => watch::run(),
+        None => {}
+    }
     init_logger();
-    let my_coords = astro::coords::GeographPoint {
-        long: std::env::var("LON")
-            .expect("Missing longitude")
-            .parse()
-            .expect("Invalid longitude"),
-        lat: std::env::var("LAT")
-            .expect("Missing latitude")
-            .parse()
-            .expect("Invalid latitude"),
+    let broker = broker::resolve();
+    let locations = location::named_from_env();
+    if locations.is_empty() {
+        run(location::from_env(), None, true, &broker);
+    } else {
+        let threads: Vec<_> = locations
+            .into_iter()
+            .enumerate()
+            .map(|(i, (name, coords))| {
+                let broker = broker.clone();
+                std::thread::Builder::new()
+                    .name(name.clone())
+                    .spawn(move || run(coords, Some(&name), i == 0, &broker))
+                    .expect("Could not start the location thread")
+            })
+            .collect();
+        for thread in threads {
+            // A panic has already been reported
+            let _ = thread.join();
+        }
+    }
+    std::process::exit(0)
+}
+
+/// Publishes the sun as seen from `my_coords` until a signal is received.
+///
+/// Each named `location` gets its own connection and topics. The sinks, the
+/// local servers and OwnTracks only serve the `primary` one, as they are
+/// shared by the whole process.
+fn run(
+    mut my_coords: astro::coords::GeographPoint,
+    location: Option<&str>,
+    primary: bool,
+    (broker_host, broker_port): &(String, u16),
+) {
+    let mut topics = topics::Topics::from_env();
+    let mut payloads = payload::Payloads::from_env();
+    let mut client_id = mqtt::client_id_from_env();
+    if let Some(name) = location {
+        info!(
+            "Observing {} at {}, {}",
+            name, my_coords.lat, my_coords.long
+        );
+        topics = topics.with_location(name);
+        payloads = payloads.with_location(name);
+        client_id = format!("{}_{}", client_id, name);
+    }
+    let availability_topic = mqtt::availability_topic_from_env();
+    let mut homie = homie::Homie::from_env().map(|x| match location {
+        Some(name) => x.with_location(name),
+        None => x,
+    });
+    let homie_state_topic = homie.as_ref().map(|x| x.state_topic());
+    let mut ambient_light = ambient::AmbientLight::from_env();
+    let owntracks = owntracks::OwnTracks::from_env().filter(|_| primary);
+    let query_topic = topics.topic(query::QUERY_TOPIC);
+    let curve_request_topic = topics.topic(curve::REQUEST_TOPIC);
+    let command_topic = topics.topic(command::TOPIC);
+    let event_topic = std::env::var("EVENT_TOPIC").unwrap_or_else(|_| "sun".to_owned());
+    // The phase retained on the broker, not announced again if still current
+    let readback_topic = std::env::var("READ_RETAINED_STATE")
+        .map(|x| {
+            x.parse()
+                .expect("Invalid READ_RETAINED_STATE, expected true or false")
+        })
+        .unwrap_or(false)
+        .then(|| topics.topic(&event_topic));
+    let mut subscriptions = vec![
+        query_topic.as_str(),
+        curve_request_topic.as_str(),
+        command_topic.as_str(),
+    ];
+    if let Some(ambient_light) = &ambient_light {
+        subscriptions.push(&ambient_light.topic);
+    }
+    if let Some(owntracks) = &owntracks {
+        subscriptions.push(&owntracks.topic);
+    }
+    if let Some(topic) = &readback_topic {
+        subscriptions.push(topic);
+    }
+    let (client, conn_state, incoming) = mqtt::get_mqtt_conn(
+        &client_id,
+        broker_host,
+        *broker_port,
+        &subscriptions,
+        Some(&topics.topic(&availability_topic)),
+    );
+    let retain_state = std::env::var("RETAIN_STATE")
+        .map(|x| {
+            x.parse()
+                .expect("Invalid RETAIN_STATE, expected true or false")
+        })
+        .unwrap_or(false);
+    assert!(
+        retain_state || readback_topic.is_none(),
+        "READ_RETAINED_STATE needs RETAIN_STATE"
+    );
+    // Seconds to wait for the retained phase before announcing the current one
+    let readback_timeout = std::time::Duration::from_secs(
+        std::env::var("READ_RETAINED_TIMEOUT")
+            .map(|x| x.parse().expect("Invalid READ_RETAINED_TIMEOUT"))
+            .unwrap_or(5),
+    );
+    let mut readback_deadline = readback_topic
+        .as_ref()
+        .map(|_| std::time::Instant::now() + readback_timeout);
+    let mut conn = Publisher::new(
+        client,
+        conn_state.clone(),
+        topics.clone(),
+        signing::Signer::from_env(),
+        encryption::Encrypter::from_env(),
+        publisher::Qos::from_env(),
+        retain_state,
+    );
+    let mut state = state::Store::from_env(location);
+    let started = chrono::Utc::now().timestamp();
+    // Periodic topics go on from where they were instead of all at once
+    let restore = |name| state.as_ref().and_then(|x| x.last_published(name, started));
+    let refresh_interval = std::env::var("REFRESH_INTERVAL")
+        .ok()
+        .map(|x| std::time::Duration::from_secs(x.parse().expect("Invalid refresh interval")));
+    let mut last_refresh = std::time::Instant::now();
+    let terminator_interval = std::time::Duration::from_secs(
+        std::env::var("TERMINATOR_INTERVAL")
+            .map(|x| x.parse().expect("Invalid terminator interval"))
+            .unwrap_or(600),
+    );
+    let mut last_terminator = restore("terminator");
+    let almanac_interval = std::env::var("ALMANAC_INTERVAL")
+        .ok()
+        .map(|x| std::time::Duration::from_secs(x.parse().expect("Invalid almanac interval")));
+    let mut last_almanac = restore("almanac");
+    let info_interval = std::env::var("INFO_INTERVAL").ok().map(|x| {
+        let interval = std::time::Duration::from_secs(x.parse().expect("Invalid info interval"));
+        assert!(!interval.is_zero(), "The info interval must be positive");
+        interval
+    });
+    let mut last_info = restore("info");
+    let sidereal_interval = std::env::var("SIDEREAL_INTERVAL").ok().map(|x| {
+        let interval =
+            std::time::Duration::from_secs(x.parse().expect("Invalid sidereal interval"));
+        assert!(
+            !interval.is_zero(),
+            "The sidereal interval must be positive"
+        );
+        interval
+    });
+    let mut last_sidereal = restore("sidereal");
+    let solar_tracker = tracker::Tracker::from_env();
+    let circadian_lighting = circadian::Circadian::from_env();
+    let object_shadow = shadow::Shadow::from_env();
+    let mut last_tracker = restore("tracker");
+    // Degrees the altitude must move by before sun/info is published again
+    let info_min_change: f64 = std::env::var("INFO_MIN_CHANGE")
+        .map(|x| x.parse().expect("Invalid INFO_MIN_CHANGE"))
+        .unwrap_or(0.0);
+    assert!(
+        info_min_change >= 0.0,
+        "INFO_MIN_CHANGE must not be negative"
+    );
+    let mut last_info_altitude: Option<f64> = None;
+    let mut almanac_date = None;
+    let mut curve_date = None;
+    let summary = summary::Summary::from_env();
+    let mut last_summary = restore("summary");
+    let mut reconnections = 0;
+    let mut heartbeat = heartbeat::Heartbeat::from_env();
+    heartbeat.resume(restore("heartbeat"));
+    let mut birth_connection = None;
+    let discovery = discovery::Discovery::from_env().map(|x| match location {
+        Some(name) => x.with_location(name),
+        None => x,
+    });
+    let mut solar_noon_date = None;
+    let mut schedule_date = None;
+    let mut polar_state = None;
+    let facades = facade::from_env();
+    let azimuth_crossings = bearing::from_env();
+    let mut last_azimuth = None;
+    let mut facades_insolated = vec![None; facades.len()];
+    let elevation_bands = band::ElevationBands::from_env();
+    let mut old_elevation_band = None;
+    let mut grey_line = greyline::GreyLine::from_env();
+    let mut photography_windows = photography::from_env();
+    let mut local_horizon = horizon::Horizon::from_env();
+    let solar_terms_enabled = solar_terms::enabled_from_env();
+    let mut next_solar_term: Option<solar_terms::Term> = None;
+    let mut next_apsis: Option<distance::Apsis> = None;
+    let prayers = prayer::Prayers::from_env();
+    let mut next_prayer = None;
+    let pv_array = pv::PvArray::from_env();
+    let mut pv_energy = pv::EnergyMeter::default();
+    let mut pv_day_energy = None;
+    let sunburn = sunburn::Sunburn::from_env();
+    let mut countdown = countdown::Countdown::from_env();
+    let mut sampling = sampling::Sampling::from_env();
+    // The other events that follow the altitude or the azimuth of the sun
+    sampling.watch(
+        elevation_bands
+            .edges()
+            .iter()
+            .copied()
+            .chain(grey_line.bounds())
+            .chain(photography_windows.iter().flat_map(|x| x.bounds()))
+            .chain(facades.iter().map(|x| x.min_altitude))
+            .chain(azimuth_crossings.iter().filter_map(|x| x.min_altitude)),
+        facades
+            .iter()
+            .flat_map(|x| [x.azimuth_from, x.azimuth_to])
+            .chain(azimuth_crossings.iter().map(|x| x.bearing)),
+    );
+    let mut modbus = if primary {
+        modbus::ModbusServer::from_env()
+    } else {
+        None
+    };
+    let homekit = if primary {
+        homekit::HomeKit::from_env()
+    } else {
+        None
     };
-    let mut conn =
-        get_mqtt_conn(&std::env::var("MQTT_BROKER").expect("Please provide a MQTT broker"));
-    let mut old_sun_pos = None;
-    let mut time_of_noon = None;
+    let upcoming_events = std::env::var("UPCOMING_EVENTS")
+        .map(|x| x.parse().expect("Invalid number of upcoming events"))
+        .unwrap_or(5);
+    let mut phase_tracker = phase::PhaseTracker::from_env();
+    let is_day = switch::Switch::from_env("IS_DAY_PAYLOADS", "true,false");
+    let mut was_day = None;
+    let mut phase_topics = phase_topics::PhaseTopics::from_env();
+    let sinks = if primary {
+        sinks::Sinks::from_env(&my_coords, &phase_tracker.thresholds)
+    } else {
+        sinks::Sinks::default()
+    };
+    let offset_events = offsets::from_env();
+    let mut last_offsets_check = None;
+    let mut dark_window_published = false;
+    // Not announced again if still current
+    let mut restored_phase = state.as_ref().and_then(|x| x.phase(started));
+    let mut last_event = restored_phase;
+    let mut time_of_noon = state.as_ref().and_then(|x| x.solar_noon(started));
+    if let Some((mode, since)) =
+        missed::Mode::from_env().zip(state.as_ref().and_then(|x| x.published_at()))
+    {
+        let events = missed::between(since, started, &my_coords, &phase_tracker.thresholds);
+        if !events.is_empty() {
+            info!("Missed {} events while down", events.len());
+            conn.publish(
+                missed::SUMMARY_TOPIC,
+                &missed::summary(since, started, &events).to_string(),
+            );
+            if mode == missed::Mode::Late {
+                for event in &events {
+                    conn.publish(
+                        missed::LATE_TOPIC,
+                        &payloads.phase(&event.position, event.timestamp, &my_coords),
+                    );
+                }
+            }
+        }
+    }
+    let mut clock_jumps = clock::JumpDetector::from_env();
+    let clear_retained_on_exit = std::env::var("CLEAR_RETAINED_ON_EXIT")
+        .map(|x| {
+            x.parse()
+                .expect("Invalid CLEAR_RETAINED_ON_EXIT, expected true or false")
+        })
+        .unwrap_or(false);
+    let shutdown = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    for signal in &[signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+        signal_hook::flag::register(*signal, shutdown.clone()).expect("Could not handle signals");
+    }
     loop {
+        if shutdown.load(std::sync::atomic::Ordering::Relaxed) {
+            info!("Shutting down");
+            if clear_retained_on_exit {
+                conn.clear_retained();
+            }
+            // A clean disconnection does not trigger the last will
+            conn.publish_plain_retained(&availability_topic, mqtt::OFFLINE);
+            if let Some(topic) = &homie_state_topic {
+                conn.publish_plain_retained(topic, "disconnected");
+            }
+            conn.disconnect();
+            // Give the event loop some time to flush the queued requests
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+            while conn_state.is_connected() && std::time::Instant::now() < deadline {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+            return;
+        }
         if let Ok(t) = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+            if let Some(jump) = clock_jumps.check(t) {
+                warn!("The system clock jumped by {:.0} s, recomputing", jump);
+                let now = t.as_secs() as i64;
+                // Announce the phase again and recompute all the daily data
+                phase_tracker.reset();
+                time_of_noon = time_of_noon
+                    .map(|_| today_solar_noon(&my_coords))
+                    .filter(|x| *x > now);
+                almanac_date = None;
+                curve_date = None;
+                solar_noon_date = None;
+                schedule_date = None;
+                // Events skipped over did not happen at their time
+                last_offsets_check = None;
+                last_azimuth = None;
+                if let Some(horizon) = &mut local_horizon {
+                    horizon.reset();
+                }
+                next_solar_term = None;
+                next_apsis = None;
+            }
+            // Retained documents may have been lost if the broker restarted
+            let refresh_due = refresh_interval
+                .map(|x| last_refresh.elapsed() >= x)
+                .unwrap_or(false);
+            if conn_state.reconnections() != reconnections || refresh_due {
+                reconnections = conn_state.reconnections();
+                last_refresh = std::time::Instant::now();
+                conn.republish_retained();
+            }
             // Check for noon
             if let Some(time) = time_of_noon {
                 let now = t.as_secs();
                 if now > time as u64 {
-                    publish_event(&mut conn, &SunPosition::SolarNoon, "sun");
+                    publish_event(
+                        &mut conn,
+                        &sinks,
+                        homie.as_mut(),
+                        &payloads,
+                        &my_coords,
+                        &SunPosition::SolarNoon,
+                        &event_topic,
+                    );
+                    publish_upcoming(
+                        &mut conn,
+                        &my_coords,
+                        &phase_tracker.thresholds,
+                        upcoming_events,
+                    );
+                    last_event = Some(SunPosition::SolarNoon);
+                    for (topic, payload) in phase_topics
+                        .as_mut()
+                        .map(|x| x.update(SunPosition::SolarNoon))
+                        .unwrap_or_default()
+                    {
+                        conn.publish_retained(&topic, &payload);
+                    }
                     time_of_noon = None;
                 }
             }
             // Check for next event
-            let is_morning = chrono::Local::now().hour() <= 12;
-            let sun_info = sun::pos(t.as_millis() as i64, my_coords.lat, my_coords.long);
-            conn.publish(
-                "sun/info",
-                QoS::ExactlyOnce,
-                false,
-                format!("{}", sun_info.altitude.to_degrees()).as_bytes(),
-            )
-            .unwrap_or_else(|_| log::error!("Could not publish event to MQTT server"));
-            let sun_pos = SunPosition::from((sun_info.altitude, is_morning));
-            if let Some(o_p) = &old_sun_pos {
-                if o_p == &sun_pos {
-                    std::thread::sleep(std::time::Duration::from_secs(60));
-                    continue;
+            let sun_info = solar::pos(t.as_millis() as i64, my_coords.lat, my_coords.long);
+            let is_morning = schedule::is_rising(t.as_secs() as i64, &my_coords);
+            // Fire the events derived from the schedule that came due since the
+            // previous iteration
+            let now = t.as_secs() as i64;
+            if let Some(from) = last_offsets_check {
+                for event in offsets::due(
+                    &offset_events,
+                    from,
+                    now,
+                    &my_coords,
+                    &phase_tracker.thresholds,
+                ) {
+                    info!("Reached {}", event.payload);
+                    conn.publish(
+                        &event.topic,
+                        &payloads.event(&event.payload, now, &my_coords),
+                    );
+                    sinks.notify(sinks::Event {
+                        name: event.payload.clone(),
+                        timestamp: now,
+                    });
+                }
+            }
+            last_offsets_check = Some(now);
+            if let Some(term) = next_solar_term.filter(|x| x.timestamp <= now) {
+                info!("Reached {}", term.name);
+                conn.publish(
+                    solar_terms::TOPIC,
+                    &payloads.event(term.name, now, &my_coords),
+                );
+                sinks.notify(sinks::Event {
+                    name: term.name.to_owned(),
+                    timestamp: now,
+                });
+                next_solar_term = None;
+            }
+            if let Some(apsis) = next_apsis.filter(|x| x.timestamp <= now) {
+                info!("Reached {}", apsis.name);
+                conn.publish(
+                    distance::APSIS_TOPIC,
+                    &payloads.event(apsis.name, now, &my_coords),
+                );
+                sinks.notify(sinks::Event {
+                    name: apsis.name.to_owned(),
+                    timestamp: now,
+                });
+                next_apsis = None;
+            }
+            if next_apsis.is_none() {
+                next_apsis = distance::next_apsis(now);
+                if let Some(apsis) = next_apsis {
+                    conn.publish_retained(distance::NEXT_APSIS_TOPIC, &apsis.to_json().to_string());
+                }
+            }
+            if let Some((name, _)) = next_prayer.filter(|(_, at)| *at <= now) {
+                info!("Reached {}", name);
+                conn.publish(prayer::TOPIC, &payloads.event(name, now, &my_coords));
+                sinks.notify(sinks::Event {
+                    name: name.to_owned(),
+                    timestamp: now,
+                });
+                next_prayer = prayers
+                    .as_ref()
+                    .and_then(|x| x.next(now, chrono::Local::today().naive_local(), &my_coords));
+            }
+            if solar_terms_enabled && next_solar_term.is_none() {
+                let term = solar_terms::next(now);
+                conn.publish_retained(solar_terms::NEXT_TOPIC, &term.to_json().to_string());
+                next_solar_term = Some(term);
+            }
+            // Telemetry is not queued while the broker is unreachable, so that
+            // the request queue does not fill up and block event detection
+            let online = conn_state.is_connected();
+            if online && birth_connection != Some(conn_state.reconnections()) {
+                conn.publish(
+                    &heartbeat.birth_topic,
+                    &heartbeat.birth(&my_coords).to_string(),
+                );
+                birth_connection = Some(conn_state.reconnections());
+                if let Some(discovery) = &discovery {
+                    for (topic, payload) in
+                        discovery.messages(&topics, &payloads, &event_topic, &availability_topic)
+                    {
+                        conn.publish_plain_retained(&topic, &payload);
+                    }
+                }
+                if let (Some(homie), Some(state_topic)) = (&homie, &homie_state_topic) {
+                    conn.publish_plain_retained(state_topic, "init");
+                    for (topic, payload) in homie.attributes() {
+                        conn.publish_plain_retained(&topic, &payload);
+                    }
+                    conn.publish_plain_retained(state_topic, "ready");
+                }
+            }
+            if let Some(beat) = heartbeat.beat(now).filter(|_| online) {
+                conn.publish_telemetry(&heartbeat.topic, &beat.to_string());
+            }
+            if online
+                && last_info
+                    .zip(info_interval)
+                    .map(|(x, interval)| x.elapsed() >= interval)
+                    .unwrap_or(true)
+            {
+                let altitude = sun_info.altitude.to_degrees();
+                if last_info_altitude
+                    .map(|x| (altitude - x).abs() >= info_min_change)
+                    .unwrap_or(true)
+                {
+                    conn.publish_telemetry("sun/info", &payloads.value(altitude, now));
+                    last_info_altitude = Some(altitude);
+                }
+                last_info = Some(std::time::Instant::now());
+            }
+            if let Some(solar_tracker) = solar_tracker.as_ref().filter(|_| online) {
+                if last_tracker
+                    .map(|x| x.elapsed() >= solar_tracker.interval)
+                    .unwrap_or(true)
+                {
+                    let (azimuth, elevation) = solar_tracker.setpoint(
+                        sun_info.azimuth.to_degrees(),
+                        sun_info.altitude.to_degrees(),
+                    );
+                    conn.publish_retained(
+                        tracker::AZIMUTH_TOPIC,
+                        &payloads.value((azimuth * 100.0).round() / 100.0, now),
+                    );
+                    conn.publish_retained(
+                        tracker::ELEVATION_TOPIC,
+                        &payloads.value((elevation * 100.0).round() / 100.0, now),
+                    );
+                    last_tracker = Some(std::time::Instant::now());
+                }
+            }
+            if let Some(sidereal_interval) = sidereal_interval.filter(|_| online) {
+                if last_sidereal
+                    .map(|x| x.elapsed() >= sidereal_interval)
+                    .unwrap_or(true)
+                {
+                    let hours = ephemeris::local_sidereal_time(now, my_coords.long);
+                    conn.publish_telemetry(
+                        "sun/sidereal",
+                        &payloads.value((hours * 1e5).round() / 1e5, now),
+                    );
+                    last_sidereal = Some(std::time::Instant::now());
+                }
+            }
+            if online {
+                if let Some(homie) = &homie {
+                    for (topic, value) in homie.position(
+                        sun_info.altitude.to_degrees(),
+                        sun_info.azimuth.to_degrees(),
+                    ) {
+                        conn.publish_plain_retained(&topic, &value);
+                    }
+                }
+                conn.publish_telemetry(
+                    "sun/azimuth/compass",
+                    &payloads.value(geometry::compass_point(sun_info.azimuth.to_degrees()), now),
+                );
+                conn.publish_telemetry(
+                    "sun/shadow_azimuth",
+                    &payloads.value((sun_info.azimuth.to_degrees() + 180.0) % 360.0, now),
+                );
+                conn.publish_telemetry(
+                    "sun/vector",
+                    &payloads.value(
+                        geometry::enu_vector(sun_info.azimuth, sun_info.altitude),
+                        now,
+                    ),
+                );
+                conn.publish_telemetry(
+                    "sun/lux",
+                    &payloads.value(
+                        (clear_sky::illuminance(sun_info.altitude.to_degrees()) * 1000.0).round()
+                            / 1000.0,
+                        now,
+                    ),
+                );
+                if let Some(object_shadow) = &object_shadow {
+                    conn.publish_telemetry(
+                        shadow::TOPIC,
+                        &object_shadow
+                            .message(
+                                sun_info.azimuth.to_degrees(),
+                                sun_info.altitude.to_degrees(),
+                            )
+                            .to_string(),
+                    );
+                }
+                if let Some(circadian_lighting) = &circadian_lighting {
+                    conn.publish_telemetry(
+                        circadian::TOPIC,
+                        &circadian_lighting
+                            .message(sun_info.altitude.to_degrees())
+                            .to_string(),
+                    );
+                }
+                let day_cycle = day_cycle::value(now, &my_coords, &phase_tracker.thresholds);
+                conn.publish_telemetry("sun/day_cycle", &payloads.value(day_cycle, now));
+                conn.publish_telemetry(
+                    "sun/day_cycle/ticks",
+                    &payloads.value((day_cycle * day_cycle::TICKS_PER_DAY) as u32, now),
+                );
+                let moon = moon::Moon::at(t.as_secs() as i64, &my_coords);
+                conn.publish_telemetry(
+                    "moon/illumination",
+                    &payloads.value((moon.illumination() * 10.0).round() / 10.0, now),
+                );
+                conn.publish_telemetry(
+                    "moon/distance",
+                    &payloads.value(moon.distance.round(), now),
+                );
+                if let Some(air_mass) = clear_sky::air_mass(sun_info.altitude.to_degrees()) {
+                    conn.publish_telemetry("sun/air_mass", &payloads.value(air_mass, now));
+                    let altitude = sun_info.altitude.to_degrees();
+                    conn.publish_telemetry(
+                        "sun/uv_index",
+                        &payloads.value(sunburn.uv_index(altitude), now),
+                    );
+                    if let Some(minutes) = sunburn.minutes(altitude) {
+                        conn.publish_telemetry(
+                            "sun/sunburn_minutes",
+                            &payloads.value(minutes, now),
+                        );
+                    }
+                } else {
+                    conn.publish_telemetry("moon/lux", &payloads.value(moon.illuminance(), now));
+                }
+            }
+            if online
+                && last_terminator
+                    .map(|x| x.elapsed() >= terminator_interval)
+                    .unwrap_or(true)
+            {
+                conn.publish_retained(
+                    "sun/terminator",
+                    &terminator::geojson(t.as_secs() as i64).to_string(),
+                );
+                last_terminator = Some(std::time::Instant::now());
+            }
+            if let Some(almanac_interval) = almanac_interval.filter(|_| online) {
+                if last_almanac
+                    .map(|x| x.elapsed() >= almanac_interval)
+                    .unwrap_or(true)
+                {
+                    conn.publish_telemetry("sun/almanac", &almanac::position(now).to_string());
+                    last_almanac = Some(std::time::Instant::now());
+                }
+                let today = chrono::Utc::today().naive_utc();
+                if almanac_date != Some(today) {
+                    conn.publish_retained(
+                        "sun/almanac/twilight",
+                        &almanac::twilight(today, &my_coords, &phase_tracker.thresholds)
+                            .to_string(),
+                    );
+                    almanac_date = Some(today);
+                }
+            }
+            let today = chrono::Utc::today().naive_utc();
+            if online && curve_date != Some(today) {
+                conn.publish_retained(curve::CURVE_TOPIC, &curve::today(&my_coords).to_string());
+                curve_date = Some(today);
+            }
+            let local_today = chrono::Local::today().naive_local();
+            if online && solar_noon_date != Some(local_today) {
+                if let Some(noon) = discovery::solar_noon(&my_coords, &phase_tracker.thresholds) {
+                    conn.publish_retained(discovery::SOLAR_NOON_TOPIC, &noon);
                 }
+                solar_noon_date = Some(local_today);
             }
+            if schedule_date != Some(local_today) {
+                conn.publish_retained(
+                    "sun/schedule",
+                    &schedule::day(local_today, &my_coords, &phase_tracker.thresholds).to_string(),
+                );
+                // For planning ahead before midnight
+                conn.publish_retained(
+                    "sun/tomorrow",
+                    &schedule::day(local_today.succ(), &my_coords, &phase_tracker.thresholds)
+                        .to_string(),
+                );
+                conn.publish_retained(
+                    day_length::TOPIC,
+                    &day_length::message(local_today, &my_coords, &phase_tracker.thresholds)
+                        .to_string(),
+                );
+                // At solar noon, as sundials read them
+                let noon = ephemeris::transit_on(local_today, my_coords.long);
+                conn.publish_retained(
+                    "sun/declination",
+                    &payloads.value((ephemeris::declination(noon) * 1e4).round() / 1e4, noon),
+                );
+                conn.publish_retained(
+                    "sun/equation_of_time",
+                    &payloads.value(
+                        (ephemeris::equation_of_time(noon) * 100.0).round() / 100.0,
+                        noon,
+                    ),
+                );
+                conn.publish_retained(
+                    distance::TOPIC,
+                    &payloads.value((distance::au(now) * 1e6).round() / 1e6, now),
+                );
+                conn.publish_retained(
+                    moon::FULL_MOON_TOPIC,
+                    &moon::full_moon(now, &my_coords).to_string(),
+                );
+                if let Some(prayers) = &prayers {
+                    conn.publish_retained(
+                        prayer::TIMETABLE_TOPIC,
+                        &prayers.timetable(local_today, &my_coords).to_string(),
+                    );
+                    next_prayer = prayers.next(now, local_today, &my_coords);
+                }
+                let polar = polar::on(local_today, &my_coords, &phase_tracker.thresholds);
+                if polar_state != Some(polar) {
+                    let name = polar.map(|x| x.name()).unwrap_or("none");
+                    info!("Polar state: {}", name);
+                    conn.publish_state(polar::TOPIC, &payloads.event(name, now, &my_coords));
+                    if polar.is_some() {
+                        sinks.notify(sinks::Event {
+                            name: name.to_owned(),
+                            timestamp: now,
+                        });
+                    }
+                    polar_state = Some(polar);
+                }
+                // Without a sunrise solar noon is never armed otherwise
+                if polar == Some(polar::Polar::Day) {
+                    let noon = today_solar_noon(&my_coords);
+                    if noon > now {
+                        time_of_noon = Some(noon);
+                    }
+                }
+                schedule_date = Some(local_today);
+            }
+            if online
+                && last_summary
+                    .map(|x| x.elapsed() >= std::time::Duration::from_secs(60))
+                    .unwrap_or(true)
+            {
+                conn.publish_retained(
+                    summary::TOPIC,
+                    &summary.text(
+                        now,
+                        sun_info.altitude.to_degrees(),
+                        &my_coords,
+                        &phase_tracker.thresholds,
+                    ),
+                );
+                last_summary = Some(std::time::Instant::now());
+            }
+            if let Some(pv_array) = &pv_array {
+                let power = pv_array.power(
+                    sun_info.azimuth.to_degrees(),
+                    sun_info.altitude.to_degrees(),
+                );
+                let energy = pv_energy.add(t.as_secs() as i64, power);
+                let local_today = chrono::Local::today().naive_local();
+                let day_energy = match pv_day_energy {
+                    Some((date, day_energy)) if date == local_today => day_energy,
+                    _ => {
+                        let day_energy = pv_array.day_energy(local_today, &my_coords);
+                        pv_day_energy = Some((local_today, day_energy));
+                        day_energy
+                    }
+                };
+                if online {
+                    conn.publish_telemetry("sun/pv/power", &payloads.value(power, now));
+                    conn.publish_telemetry("sun/pv/energy", &payloads.value(energy, now));
+                    conn.publish_telemetry(
+                        pv::ESTIMATE_TOPIC,
+                        &pv::estimate(power, energy, day_energy).to_string(),
+                    );
+                }
+            }
+            if let Some(countdown) = countdown.as_mut().filter(|_| online) {
+                for (topic, minutes) in countdown.update(now, &my_coords, &phase_tracker.thresholds)
+                {
+                    conn.publish_telemetry(&topic, &payloads.value(minutes, now));
+                }
+            }
+            let elevation_band = elevation_bands.label(sun_info.altitude.to_degrees());
+            if old_elevation_band.as_ref() != Some(&elevation_band) {
+                conn.publish_state(
+                    "sun/elevation_band",
+                    &payloads.event(&elevation_band, now, &my_coords),
+                );
+                old_elevation_band = Some(elevation_band);
+            }
+            // Check for facades entering or leaving direct sunlight
+            if let Some(transition) =
+                grey_line.update(now, sun_info.altitude.to_degrees(), &my_coords)
+            {
+                let name = greyline::event_name(&transition);
+                info!("Reached {}", name);
+                conn.publish_state("sun/greyline", &payloads.event(name, now, &my_coords));
+                if let greyline::Transition::Start(Some(duration)) = transition {
+                    conn.publish("sun/greyline/duration", &payloads.value(duration, now));
+                }
+            }
+            for name in
+                photography::update(&mut photography_windows, sun_info.altitude.to_degrees())
+            {
+                info!("Reached {}", name);
+                conn.publish_state("sun/photography", &payloads.event(&name, now, &my_coords));
+                sinks.notify(sinks::Event {
+                    name,
+                    timestamp: now,
+                });
+            }
+            let azimuth = sun_info.azimuth.to_degrees();
+            if let Some(name) = local_horizon
+                .as_mut()
+                .and_then(|x| x.update(azimuth, sun_info.altitude.to_degrees()))
+            {
+                info!("Reached {}", name);
+                conn.publish_state(horizon::TOPIC, &payloads.event(name, now, &my_coords));
+                sinks.notify(sinks::Event {
+                    name: name.to_owned(),
+                    timestamp: now,
+                });
+            }
+            if let Some(previous) = last_azimuth {
+                for crossing in azimuth_crossings
+                    .iter()
+                    .filter(|x| x.is_crossed(previous, azimuth, sun_info.altitude.to_degrees()))
+                {
+                    info!("Reached {}", crossing.name);
+                    conn.publish(
+                        "sun/bearing",
+                        &payloads.event(&crossing.name, now, &my_coords),
+                    );
+                    sinks.notify(sinks::Event {
+                        name: crossing.name.clone(),
+                        timestamp: now,
+                    });
+                }
+            }
+            last_azimuth = Some(azimuth);
+            for (facade, was_insolated) in facades.iter().zip(facades_insolated.iter_mut()) {
+                let insolated = facade.is_insolated(
+                    sun_info.azimuth.to_degrees(),
+                    sun_info.altitude.to_degrees(),
+                );
+                // The first sample only seeds the state, as nothing changed yet
+                if was_insolated.is_some_and(|x| x != insolated) {
+                    info!("Facade {} insolated: {}", facade.name, insolated);
+                    let event = facade::insolation_event(insolated);
+                    conn.publish_state(
+                        &format!("sun/facade/{}", facade.name),
+                        &payloads.event(event, now, &my_coords),
+                    );
+                    sinks.notify(sinks::Event {
+                        name: format!("{}_{}", facade.name, event),
+                        timestamp: now,
+                    });
+                }
+                *was_insolated = Some(insolated);
+            }
+            if let Some(ambient_light) = &mut ambient_light {
+                if let Some(event) = ambient_light.update(sun_info.altitude.to_degrees()) {
+                    info!("Reached {}", event);
+                    conn.publish_state("sun/effective", &payloads.event(event, now, &my_coords));
+                }
+            }
+            readback_deadline = readback_deadline.filter(|x| std::time::Instant::now() < *x);
+            let transition = if readback_deadline.is_some() {
+                None
+            } else {
+                phase_tracker.update(sun_info.altitude, is_morning)
+            };
+            if let Some(homekit) = &homekit {
+                let altitude = sun_info.altitude.to_degrees();
+                homekit.update(
+                    altitude >= phase_tracker.thresholds.horizon,
+                    clear_sky::illuminance(altitude),
+                );
+            }
+            if let Some(modbus) = &mut modbus {
+                modbus.update(
+                    now,
+                    sun_info.altitude.to_degrees(),
+                    sun_info.azimuth.to_degrees(),
+                    phase_tracker.current(),
+                    &my_coords,
+                    &phase_tracker.thresholds,
+                );
+            }
+            if let Some(state) = &mut state {
+                state.sync(
+                    now,
+                    last_event,
+                    time_of_noon,
+                    &[
+                        ("info", last_info),
+                        ("tracker", last_tracker),
+                        ("sidereal", last_sidereal),
+                        ("terminator", last_terminator),
+                        ("almanac", last_almanac),
+                        ("summary", last_summary),
+                        ("heartbeat", heartbeat.last()),
+                    ],
+                );
+            }
+            let sun_pos = match transition {
+                Some(sun_pos) => sun_pos,
+                None => {
+                    let mut interval = std::time::Duration::MAX;
+                    if let Some(countdown) = &countdown {
+                        if countdown.running() {
+                            interval = interval.min(std::time::Duration::from_secs(60));
+                        } else if let Some(at) =
+                            countdown.next_start(now, &my_coords, &phase_tracker.thresholds)
+                        {
+                            interval = interval
+                                .min(std::time::Duration::from_secs((at - now).max(0) as u64 + 1));
+                        }
+                    }
+                    if let Some((last, info_interval)) = last_info.zip(info_interval) {
+                        interval = interval.min(info_interval.saturating_sub(last.elapsed()));
+                    }
+                    if let Some((last, solar_tracker)) = last_tracker.zip(solar_tracker.as_ref()) {
+                        interval =
+                            interval.min(solar_tracker.interval.saturating_sub(last.elapsed()));
+                    }
+                    if let Some((last, sidereal_interval)) = last_sidereal.zip(sidereal_interval) {
+                        interval = interval.min(sidereal_interval.saturating_sub(last.elapsed()));
+                    }
+                    if let Some(due_in) = heartbeat.due_in() {
+                        interval = interval.min(due_in);
+                    }
+                    if let Some(last) = last_terminator {
+                        interval = interval.min(terminator_interval.saturating_sub(last.elapsed()));
+                    }
+                    if let Some((last, almanac_interval)) = last_almanac.zip(almanac_interval) {
+                        interval = interval.min(almanac_interval.saturating_sub(last.elapsed()));
+                    }
+                    if let Some(refresh_interval) = refresh_interval {
+                        interval =
+                            interval.min(refresh_interval.saturating_sub(last_refresh.elapsed()));
+                    }
+                    // The daily documents are due at midnight, both local and UTC
+                    let midnight =
+                        schedule::local_midnight(chrono::Local::today().naive_local().succ())
+                            .min(chrono::Utc::today().succ().and_hms(0, 0, 0).timestamp());
+                    interval =
+                        interval.min(std::time::Duration::from_secs((midnight - now + 1) as u64));
+                    // Solar noon is no threshold crossing, so wake up for it too
+                    if let Some(noon) = time_of_noon.filter(|x| *x >= now) {
+                        interval =
+                            interval.min(std::time::Duration::from_secs((noon - now + 1) as u64));
+                    }
+                    if let Some((_, at)) = next_prayer {
+                        interval =
+                            interval.min(std::time::Duration::from_secs((at - now + 1) as u64));
+                    }
+                    if let Some(apsis) = next_apsis {
+                        interval = interval.min(std::time::Duration::from_secs(
+                            (apsis.timestamp - now + 1) as u64,
+                        ));
+                    }
+                    if let Some(term) = next_solar_term {
+                        interval = interval.min(std::time::Duration::from_secs(
+                            (term.timestamp - now + 1) as u64,
+                        ));
+                    }
+                    if let Some(readback_deadline) = readback_deadline {
+                        interval = interval.min(
+                            readback_deadline.saturating_duration_since(std::time::Instant::now()),
+                        );
+                    }
+                    // Then look for a threshold crossing until the earliest of those
+                    interval =
+                        sampling.interval(now, interval, &my_coords, &phase_tracker.thresholds);
+                    // Offset events are no threshold crossings either
+                    if let Some(at) = offsets::next(
+                        &offset_events,
+                        now,
+                        now + interval.as_secs() as i64,
+                        &my_coords,
+                        &phase_tracker.thresholds,
+                    ) {
+                        interval =
+                            interval.min(std::time::Duration::from_secs((at - now + 1) as u64));
+                    }
+                    // And for the sun clearing or going behind the local horizon
+                    if let Some(at) = local_horizon
+                        .as_ref()
+                        .and_then(|x| x.next(now, now + interval.as_secs() as i64, &my_coords))
+                    {
+                        interval =
+                            interval.min(std::time::Duration::from_secs((at - now + 1) as u64));
+                    }
+                    // Handle incoming messages until the next iteration is due
+                    let deadline = std::time::Instant::now() + interval;
+                    while let Some(remaining) =
+                        deadline.checked_duration_since(std::time::Instant::now())
+                    {
+                        if shutdown.load(std::sync::atomic::Ordering::Relaxed) {
+                            break;
+                        }
+                        // Replay what was missed as soon as the broker is back
+                        conn.flush();
+                        // Wake up every second to notice shutdown requests
+                        let timeout = remaining.min(std::time::Duration::from_secs(1));
+                        let message = match incoming.recv_timeout(timeout) {
+                            Ok(message) => message,
+                            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => continue,
+                            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                                std::thread::sleep(timeout);
+                                continue;
+                            }
+                        };
+                        if Some(&message.topic) == readback_topic.as_ref() {
+                            // Later ones are our own
+                            if readback_deadline.take().is_some() {
+                                let retained = std::str::from_utf8(&message.payload)
+                                    .ok()
+                                    .and_then(|x| conn.decrypt(&message.topic, x))
+                                    .and_then(|x| payloads.parse_phase(&x));
+                                info!("Retained phase: {:?}", retained);
+                                if retained.is_some() {
+                                    restored_phase = retained;
+                                }
+                                break;
+                            }
+                        } else if message.topic == query_topic {
+                            let (topic, reply) = query::handle(
+                                &message.payload,
+                                &my_coords,
+                                &phase_tracker.thresholds,
+                            );
+                            conn.publish(&topic, &reply);
+                        } else if message.topic == curve_request_topic {
+                            let (topic, reply) = curve::handle(&message.payload, &my_coords);
+                            conn.publish(&topic, &reply);
+                        } else if message.topic == command_topic {
+                            match command::Command::parse(&message.payload) {
+                                Ok(command::Command::Refresh) => {
+                                    info!("Publishing the current state on request");
+                                    let now = chrono::Utc::now().timestamp();
+                                    if let Some(event) = &last_event {
+                                        conn.publish_state(
+                                            &event_topic,
+                                            &payloads.phase(event, now, &my_coords),
+                                        );
+                                        if let Some(code) = payloads.code(event) {
+                                            conn.publish_state("sun/code", &code);
+                                        }
+                                    }
+                                    let altitude =
+                                        solar::pos(now * 1000, my_coords.lat, my_coords.long)
+                                            .altitude;
+                                    conn.publish_telemetry(
+                                        "sun/info",
+                                        &payloads.value(altitude.to_degrees(), now),
+                                    );
+                                    publish_upcoming(
+                                        &mut conn,
+                                        &my_coords,
+                                        &phase_tracker.thresholds,
+                                        upcoming_events,
+                                    );
+                                }
+                                Err(e) => warn!("Ignoring command: {}", e),
+                            }
+                        } else if let Some(location) = owntracks
+                            .as_ref()
+                            .filter(|x| x.topic == message.topic)
+                            .and_then(|x| x.location(&message.payload, &my_coords))
+                        {
+                            info!("Moved to {}, {}", location.lat, location.long);
+                            sinks.relocate(&location);
+                            my_coords = location;
+                            // Everything computed for the day is stale
+                            if time_of_noon.is_some() {
+                                time_of_noon = Some(today_solar_noon(&my_coords));
+                            }
+                            almanac_date = None;
+                            curve_date = None;
+                            solar_noon_date = None;
+                            schedule_date = None;
+                            // Moving is no azimuth crossing
+                            last_azimuth = None;
+                            if let Some(horizon) = &mut local_horizon {
+                                horizon.reset();
+                            }
+                            if let Some(modbus) = &mut modbus {
+                                modbus.relocate();
+                            }
+                            publish_upcoming(
+                                &mut conn,
+                                &my_coords,
+                                &phase_tracker.thresholds,
+                                upcoming_events,
+                            );
+                            publish_dark_window(&mut conn, &my_coords, &phase_tracker.thresholds);
+                        } else if let Some(ambient_light) =
+                            ambient_light.as_mut().filter(|x| x.topic == message.topic)
+                        {
+                            ambient_light.record(&message.payload);
+                            // The reading may cross the threshold on its own
+                            break;
+                        }
+                    }
+                    continue;
+                }
+            };
             info!("Reached {:?}", sun_pos);
-            publish_event(&mut conn, &sun_pos, "sun");
+            if restored_phase.take() == Some(sun_pos) {
+                info!("{:?} was already announced before the restart", sun_pos);
+            } else {
+                publish_event(
+                    &mut conn,
+                    &sinks,
+                    homie.as_mut(),
+                    &payloads,
+                    &my_coords,
+                    &sun_pos,
+                    &event_topic,
+                );
+            }
+            last_event = Some(sun_pos);
+            if was_day != Some(sun_pos.is_day()) {
+                conn.publish_retained("sun/is_day", is_day.payload(sun_pos.is_day()));
+                was_day = Some(sun_pos.is_day());
+            }
+            for (topic, payload) in phase_topics
+                .as_mut()
+                .map(|x| x.update(sun_pos))
+                .unwrap_or_default()
+            {
+                conn.publish_retained(&topic, &payload);
+            }
+            publish_upcoming(
+                &mut conn,
+                &my_coords,
+                &phase_tracker.thresholds,
+                upcoming_events,
+            );
+            if sun_pos == SunPosition::CivilDusk || !dark_window_published {
+                publish_dark_window(&mut conn, &my_coords, &phase_tracker.thresholds);
+                dark_window_published = true;
+            }
             // Check if we should calculate noon time
             if sun_pos == SunPosition::Sunrise {
                 time_of_noon = Some(today_solar_noon(&my_coords));
@@ -221,7 +1290,6 @@ fn main() -> ! {
 
                 info!("Today solar noon will occour at {}", utc);
             }
-            old_sun_pos = Some(sun_pos);
         }
     }
 }
@@ -0,0 +1,57 @@
+//! Events that happened while the daemon was down, from the last phase
+//! published before the restart, as saved in the `STATE_FILE`, to the start.
+//!
+//! `MISSED_EVENTS=summary` publishes them all at once on `sun/missed`, as
+//! `{"since": ..., "until": ..., "events": [...]}` with Unix timestamps, so
+//! that automations can reconcile. `MISSED_EVENTS=late` also publishes each
+//! of them on `sun/late`, with the payload it would have had on time.
+
+use crate::phase::Thresholds;
+use crate::schedule::{self, Event};
+
+pub const SUMMARY_TOPIC: &str = "sun/missed";
+pub const LATE_TOPIC: &str = "sun/late";
+
+/// Seconds of downtime looked back at most.
+const MAX_LOOKBACK: i64 = 2 * 24 * 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Summary,
+    Late,
+}
+
+impl Mode {
+    pub fn from_env() -> Option<Self> {
+        let mode = match std::env::var("MISSED_EVENTS").as_deref() {
+            Ok("summary") => Self::Summary,
+            Ok("late") => Self::Late,
+            Ok("off") | Err(_) => return None,
+            Ok(x) => panic!("Invalid MISSED_EVENTS {}, expected summary, late or off", x),
+        };
+        assert!(
+            std::env::var("STATE_FILE").is_ok(),
+            "MISSED_EVENTS needs STATE_FILE"
+        );
+        Some(mode)
+    }
+}
+
+/// Events after `since` and before `until`.
+pub fn between(
+    since: i64,
+    until: i64,
+    coords: &astro::coords::GeographPoint,
+    thresholds: &Thresholds,
+) -> Vec<Event> {
+    let since = since.max(until - MAX_LOOKBACK);
+    schedule::phases_between(since + 1, until, coords, thresholds)
+}
+
+pub fn summary(since: i64, until: i64, events: &[Event]) -> serde_json::Value {
+    serde_json::json!({
+        "since": since,
+        "until": until,
+        "events": events.iter().map(|e| e.to_json()).collect::<Vec<_>>(),
+    })
+}
@@ -0,0 +1,180 @@
+//! Modbus TCP server exposing the sun position and schedule to PLCs.
+//!
+//! The same registers are readable as holding (function 3) and input
+//! (function 4) registers; 32 bit values are split big-endian over two
+//! registers:
+//!
+//! | Address | Content                                        |
+//! |---------|------------------------------------------------|
+//! | 0       | Altitude in hundredths of a degree (signed)    |
+//! | 1       | Azimuth in hundredths of a degree              |
+//! | 2       | Current phase code                             |
+//! | 3-4     | Timestamp of the next event                    |
+//! | 5       | Phase code of the next event                   |
+//! | 6-7     | Timestamp of the next sunrise                  |
+//! | 8-9     | Timestamp of the next sunset                   |
+//!
+//! Codes are those of [`SunPosition::code`], 0xFFFF when unknown. Missing
+//! timestamps are 0.
+
+use crate::phase::{SunPosition, Thresholds};
+use crate::schedule;
+use log::{error, info, warn};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+const REGISTERS: usize = 10;
+const UNKNOWN: u16 = 0xFFFF;
+/// Most registers a single request may read, as per the specification.
+const MAX_QUANTITY: u16 = 125;
+
+const ILLEGAL_FUNCTION: u8 = 1;
+const ILLEGAL_DATA_ADDRESS: u8 = 2;
+const ILLEGAL_DATA_VALUE: u8 = 3;
+
+type Registers = Arc<Mutex<[u16; REGISTERS]>>;
+
+pub struct ModbusServer {
+    registers: Registers,
+    /// Upcoming phases, as named on the `sun` topic.
+    upcoming: Vec<schedule::Event>,
+    /// Upcoming raw threshold crossings, for the sunrise and sunset.
+    crossings: Vec<schedule::Event>,
+}
+
+/// Builds the response PDU to the request PDU `request`.
+fn respond(request: &[u8], registers: &Registers) -> Vec<u8> {
+    let function = match request.first() {
+        Some(&x) => x,
+        None => return vec![0x80, ILLEGAL_FUNCTION],
+    };
+    let exception = |code| vec![function | 0x80, code];
+    if function != 3 && function != 4 {
+        return exception(ILLEGAL_FUNCTION);
+    }
+    if request.len() != 5 {
+        return exception(ILLEGAL_DATA_VALUE);
+    }
+    let start = u16::from_be_bytes([request[1], request[2]]) as usize;
+    let quantity = u16::from_be_bytes([request[3], request[4]]);
+    if quantity == 0 || quantity > MAX_QUANTITY {
+        return exception(ILLEGAL_DATA_VALUE);
+    }
+    let end = start + quantity as usize;
+    if end > REGISTERS {
+        return exception(ILLEGAL_DATA_ADDRESS);
+    }
+    let registers = registers.lock().unwrap();
+    let mut response = vec![function, 2 * quantity as u8];
+    for register in &registers[start..end] {
+        response.extend_from_slice(&register.to_be_bytes());
+    }
+    response
+}
+
+fn serve(mut stream: TcpStream, registers: Registers) -> std::io::Result<()> {
+    loop {
+        let mut header = [0; 7];
+        match stream.read_exact(&mut header) {
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            x => x?,
+        }
+        let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+        if length < 1 {
+            return Ok(());
+        }
+        // The length counts the unit identifier, already read with the header
+        let mut request = vec![0; length - 1];
+        stream.read_exact(&mut request)?;
+        let response = respond(&request, &registers);
+        let mut frame = header[..4].to_vec();
+        frame.extend_from_slice(&(response.len() as u16 + 1).to_be_bytes());
+        frame.push(header[6]);
+        frame.extend_from_slice(&response);
+        stream.write_all(&frame)?;
+    }
+}
+
+fn split(value: i64) -> [u16; 2] {
+    let value = value.clamp(0, u32::MAX as i64) as u32;
+    [(value >> 16) as u16, value as u16]
+}
+
+impl ModbusServer {
+    /// Listens on the address in `MODBUS_BIND`, such as `0.0.0.0:502`.
+    pub fn from_env() -> Option<Self> {
+        let address = std::env::var("MODBUS_BIND").ok()?;
+        let listener = TcpListener::bind(&address)
+            .unwrap_or_else(|e| panic!("Could not listen on {}: {}", address, e));
+        info!("Serving Modbus TCP on {}", address);
+        let registers = Arc::new(Mutex::new([UNKNOWN; REGISTERS]));
+        let shared = registers.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let registers = shared.clone();
+                        std::thread::spawn(move || {
+                            serve(stream, registers)
+                                .unwrap_or_else(|e| warn!("Modbus client error: {}", e))
+                        });
+                    }
+                    Err(e) => error!("Could not accept Modbus connection: {}", e),
+                }
+            }
+        });
+        Some(Self {
+            registers,
+            upcoming: Vec::new(),
+            crossings: Vec::new(),
+        })
+    }
+
+    /// Forgets the upcoming events, computed for the previous location.
+    pub fn relocate(&mut self) {
+        self.upcoming.clear();
+        self.crossings.clear();
+    }
+
+    /// Updates the registers with the sun at `altitude` and `azimuth`
+    /// degrees.
+    pub fn update(
+        &mut self,
+        now: i64,
+        altitude: f64,
+        azimuth: f64,
+        phase: Option<SunPosition>,
+        coords: &astro::coords::GeographPoint,
+        thresholds: &Thresholds,
+    ) {
+        let stale =
+            |events: &[schedule::Event]| events.first().map(|e| e.timestamp <= now).unwrap_or(true);
+        if stale(&self.upcoming) || stale(&self.crossings) {
+            let end = now + 2 * 24 * 3600;
+            self.upcoming = schedule::phases_between(now, end, coords, thresholds);
+            self.crossings = schedule::events_between(now, end, coords, thresholds);
+        }
+        let next = |position: SunPosition| {
+            self.crossings
+                .iter()
+                .find(|e| e.position == position)
+                .map(|e| e.timestamp)
+                .unwrap_or(0)
+        };
+        let mut registers = [UNKNOWN; REGISTERS];
+        registers[0] = ((altitude * 100.0).round() as i16) as u16;
+        registers[1] = (azimuth * 100.0).round() as u16;
+        if let Some(phase) = phase {
+            registers[2] = phase.code() as u16;
+        }
+        let first = self.upcoming.first();
+        registers[3..5].copy_from_slice(&split(first.map(|e| e.timestamp).unwrap_or(0)));
+        if let Some(first) = first {
+            registers[5] = first.position.code() as u16;
+        }
+        registers[6..8].copy_from_slice(&split(next(SunPosition::Sunrise)));
+        registers[8..10].copy_from_slice(&split(next(SunPosition::Sunset)));
+        *self.registers.lock().unwrap() = registers;
+    }
+}
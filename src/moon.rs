@@ -0,0 +1,132 @@
+//! Position and brightness of the moon, from the lunar theory of the astro
+//! crate, and the next full moon on `moon/full_moon`, a supermoon when closer
+//! than 360000 km and a micromoon when farther than 405000 km.
+
+use crate::{clear_sky, ephemeris};
+
+const KM_PER_AU: f64 = 149_597_870.7;
+const EARTH_RADIUS_KM: f64 = 6378.14;
+const MEAN_DISTANCE_KM: f64 = 384_400.0;
+/// Atmospheric extinction in magnitudes per air mass.
+const EXTINCTION: f64 = 0.2;
+const SUPERMOON_KM: f64 = 360_000.0;
+const MICROMOON_KM: f64 = 405_000.0;
+/// Seconds between the samples looking for the full moon.
+const FULL_MOON_STEP: i64 = 6 * 3600;
+
+pub const FULL_MOON_TOPIC: &str = "moon/full_moon";
+
+#[derive(Debug, Clone, Copy)]
+pub struct Moon {
+    /// Topocentric altitude in degrees
+    pub altitude: f64,
+    /// Distance from the centre of the Earth in km
+    pub distance: f64,
+    /// Sun-Moon-Earth angle in degrees, 0 at full moon
+    pub phase_angle: f64,
+}
+
+impl Moon {
+    pub fn at(timestamp: i64, coords: &astro::coords::GeographPoint) -> Self {
+        let jd = ephemeris::julian_day(timestamp);
+        let (ecl, distance) = astro::lunar::geocent_ecl_pos(jd);
+        let (nut_in_long, nut_in_oblq) = astro::nutation::nutation(jd);
+        let oblq = astro::ecliptic::mn_oblq_laskar(jd) + nut_in_oblq;
+        let long = ecl.long + nut_in_long;
+        let ra = astro::coords::asc_frm_ecl(long, ecl.lat, oblq);
+        let dec = astro::coords::dec_frm_ecl(long, ecl.lat, oblq);
+        let hour_angle = ephemeris::apparent_sidereal_time(jd) + coords.long.to_radians() - ra;
+        let geocentric_altitude =
+            astro::coords::alt_frm_eq(hour_angle, dec, coords.lat.to_radians());
+        let parallax = (EARTH_RADIUS_KM / distance).asin() * geocentric_altitude.cos();
+
+        let (sun_ra, sun_dec) = ephemeris::sun_equatorial(jd);
+        let (_, sun_distance) = astro::sun::geocent_ecl_pos(jd);
+        let sun_distance = sun_distance * KM_PER_AU;
+        let elongation =
+            (sun_dec.sin() * dec.sin() + sun_dec.cos() * dec.cos() * (sun_ra - ra).cos()).acos();
+        let phase_angle =
+            (sun_distance * elongation.sin()).atan2(distance - sun_distance * elongation.cos());
+        Self {
+            altitude: (geocentric_altitude - parallax).to_degrees(),
+            distance,
+            phase_angle: phase_angle.to_degrees(),
+        }
+    }
+
+    /// Percentage of the disc lit by the sun, 100 at full moon.
+    pub fn illumination(&self) -> f64 {
+        50.0 * (1.0 + self.phase_angle.to_radians().cos())
+    }
+
+    /// Estimated illuminance from moonlight on a horizontal surface in lux,
+    /// using the Krisciunas-Schaefer magnitude of the moon.
+    pub fn illuminance(&self) -> f64 {
+        let air_mass = match clear_sky::air_mass(self.altitude) {
+            Some(x) => x,
+            None => return 0.0,
+        };
+        let alpha = self.phase_angle.abs();
+        let magnitude = -12.73 + 0.026 * alpha + 4e-9 * alpha.powi(4);
+        let top_of_atmosphere =
+            10f64.powf(-0.4 * (magnitude + 14.18)) * (MEAN_DISTANCE_KM / self.distance).powi(2);
+        top_of_atmosphere
+            * 10f64.powf(-0.4 * EXTINCTION * air_mass)
+            * self.altitude.to_radians().sin()
+    }
+}
+
+/// Timestamp and distance in km of the first full moon after `from`.
+pub fn next_full_moon(from: i64, coords: &astro::coords::GeographPoint) -> (i64, f64) {
+    let phase_angle = |timestamp| Moon::at(timestamp, coords).phase_angle;
+    // A lunation is shorter than 30 days
+    let mut previous = (from, phase_angle(from));
+    let mut current = (from + FULL_MOON_STEP, phase_angle(from + FULL_MOON_STEP));
+    while current.0 < from + 30 * 24 * 3600 {
+        let next = (
+            current.0 + FULL_MOON_STEP,
+            phase_angle(current.0 + FULL_MOON_STEP),
+        );
+        if current.1 <= previous.1 && current.1 <= next.1 {
+            // Ternary search for the minimum of the phase angle
+            let (mut low, mut high) = (previous.0, next.0);
+            while high - low > 2 {
+                let a = low + (high - low) / 3;
+                let b = high - (high - low) / 3;
+                if phase_angle(a) < phase_angle(b) {
+                    high = b;
+                } else {
+                    low = a;
+                }
+            }
+            let timestamp = ((low + high) / 2).max(from);
+            return (timestamp, Moon::at(timestamp, coords).distance);
+        }
+        previous = current;
+        current = next;
+    }
+    let moon = Moon::at(from, coords);
+    (from, moon.distance)
+}
+
+/// Whether a full moon at `distance` km is a `supermoon`, a `micromoon` or
+/// just `regular`.
+pub fn full_moon_kind(distance: f64) -> &'static str {
+    if distance < SUPERMOON_KM {
+        "supermoon"
+    } else if distance > MICROMOON_KM {
+        "micromoon"
+    } else {
+        "regular"
+    }
+}
+
+/// Message of `moon/full_moon` for the first full moon after `from`.
+pub fn full_moon(from: i64, coords: &astro::coords::GeographPoint) -> serde_json::Value {
+    let (timestamp, distance) = next_full_moon(from, coords);
+    serde_json::json!({
+        "timestamp": timestamp,
+        "distance": distance.round(),
+        "kind": full_moon_kind(distance),
+    })
+}
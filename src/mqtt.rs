@@ -0,0 +1,419 @@
+use log::{error, info, warn};
+use rumqttc::{
+    Client, Connection, Event, Key, LastWill, MqttOptions, Outgoing, Packet, Publish, QoS,
+    Transport,
+};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Connections lasting less than this count as failures when deciding
+/// whether to switch to the next broker.
+const STABLE_CONNECTION: Duration = Duration::from_secs(60);
+pub const ONLINE: &str = "online";
+pub const OFFLINE: &str = "offline";
+const SYSTEM_CA_BUNDLE: &str = "/etc/ssl/certs/ca-certificates.crt";
+
+/// State of the broker connection, as observed by the event loop supervisor.
+#[derive(Debug, Default)]
+pub struct ConnectionState {
+    connected: AtomicBool,
+    reconnections: AtomicUsize,
+}
+
+impl ConnectionState {
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Number of times the connection was re-established after being lost.
+    pub fn reconnections(&self) -> usize {
+        self.reconnections.load(Ordering::Relaxed)
+    }
+}
+
+/// Client ID from `MQTT_CLIENT_ID` (default `rust_mqtt_sun`), followed by
+/// a random suffix if `MQTT_CLIENT_ID_SUFFIX` is true, so that several
+/// instances can share a broker.
+pub fn client_id_from_env() -> String {
+    let mut client_id =
+        std::env::var("MQTT_CLIENT_ID").unwrap_or_else(|_| "rust_mqtt_sun".to_owned());
+    let suffix = std::env::var("MQTT_CLIENT_ID_SUFFIX")
+        .map(|x| {
+            x.parse()
+                .expect("Invalid MQTT_CLIENT_ID_SUFFIX, expected true or false")
+        })
+        .unwrap_or(false);
+    if suffix {
+        let mut random = [0u8; 4];
+        getrandom::getrandom(&mut random).expect("Could not generate the client ID suffix");
+        client_id.push('_');
+        for byte in random.iter() {
+            client_id += &format!("{:02x}", byte);
+        }
+    }
+    assert!(!client_id.is_empty(), "The MQTT client ID is empty");
+    client_id
+}
+
+/// Topic on which the daemon announces whether it is online, from
+/// `AVAILABILITY_TOPIC` (default `sun/availability`).
+pub fn availability_topic_from_env() -> String {
+    std::env::var("AVAILABILITY_TOPIC").unwrap_or_else(|_| "sun/availability".to_owned())
+}
+
+/// Reads the credentials from `MQTT_USERNAME` and `MQTT_PASSWORD`, or from
+/// the file named by `MQTT_PASSWORD_FILE` (ignoring surrounding whitespace).
+fn credentials_from_env() -> Option<(String, String)> {
+    let username = std::env::var("MQTT_USERNAME").ok()?;
+    let password = match std::env::var("MQTT_PASSWORD") {
+        Ok(password) => password,
+        Err(_) => match std::env::var("MQTT_PASSWORD_FILE") {
+            Ok(path) => std::fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("Could not read MQTT password from {}: {}", path, e))
+                .trim()
+                .to_owned(),
+            Err(_) => String::new(),
+        },
+    };
+    Some((username, password))
+}
+
+/// Whether `MQTT_TLS` asks for connecting to the broker over TLS.
+pub fn tls_from_env() -> bool {
+    std::env::var("MQTT_TLS")
+        .map(|x| x.parse().expect("Invalid MQTT_TLS, expected true or false"))
+        .unwrap_or(false)
+}
+
+/// Checks that `key` belongs to the first certificate in `chain`, by
+/// signing a message with it and verifying the signature. Certificates that
+/// cannot be parsed, such as X.509 v1 ones, are left to the broker to check.
+fn key_matches(chain: &[rustls::Certificate], key: &rustls::PrivateKey) -> Result<(), String> {
+    let signing_key =
+        rustls::sign::any_supported_type(key).map_err(|_| "unsupported key type".to_owned())?;
+    let signer = signing_key
+        .choose_scheme(&[
+            rustls::SignatureScheme::ED25519,
+            rustls::SignatureScheme::ECDSA_NISTP256_SHA256,
+            rustls::SignatureScheme::ECDSA_NISTP384_SHA384,
+            rustls::SignatureScheme::RSA_PKCS1_SHA256,
+        ])
+        .ok_or_else(|| "unsupported key type".to_owned())?;
+    let algorithm = match signer.get_scheme() {
+        rustls::SignatureScheme::ED25519 => &webpki::ED25519,
+        rustls::SignatureScheme::ECDSA_NISTP256_SHA256 => &webpki::ECDSA_P256_SHA256,
+        rustls::SignatureScheme::ECDSA_NISTP384_SHA384 => &webpki::ECDSA_P384_SHA384,
+        _ => &webpki::RSA_PKCS1_2048_8192_SHA256,
+    };
+    let message = b"mqtt_sun client key check";
+    let signature = signer.sign(message).map_err(|e| e.to_string())?;
+    let certificate = match webpki::EndEntityCert::from(&chain[0].0) {
+        Ok(x) => x,
+        Err(e) => {
+            warn!(
+                "Could not check the client key against its certificate: {:?}",
+                e
+            );
+            return Ok(());
+        }
+    };
+    certificate
+        .verify_signature(algorithm, message, &signature)
+        .map_err(|_| "the key does not match the certificate".to_owned())
+}
+
+/// Reads the client certificate and private key from `MQTT_CLIENT_CERT` and
+/// `MQTT_CLIENT_KEY`, both PEM. The key may be PKCS#1 RSA or PKCS#8.
+fn client_auth_from_env() -> Option<(Vec<u8>, Key)> {
+    let cert_path = std::env::var("MQTT_CLIENT_CERT").ok()?;
+    let key_path =
+        std::env::var("MQTT_CLIENT_KEY").expect("MQTT_CLIENT_CERT requires MQTT_CLIENT_KEY");
+    let read = |path: &str| {
+        std::fs::read(path).unwrap_or_else(|e| panic!("Could not read {}: {}", path, e))
+    };
+    let cert = read(&cert_path);
+    let key = read(&key_path);
+    let chain = rumqttc::certs(&mut &cert[..])
+        .ok()
+        .filter(|x| !x.is_empty())
+        .unwrap_or_else(|| panic!("No PEM certificates in {}", cert_path));
+    let (parsed, key) = match rumqttc::rsa_private_keys(&mut &key[..]) {
+        Ok(mut keys) if !keys.is_empty() => (keys.remove(0), Key::RSA(key)),
+        _ => match rumqttc::pkcs8_private_keys(&mut &key[..]) {
+            Ok(mut keys) if !keys.is_empty() => (keys.remove(0), Key::ECC(key)),
+            _ => panic!(
+                "No PKCS#1 RSA or PKCS#8 private key in {}, EC keys must be converted to PKCS#8",
+                key_path
+            ),
+        },
+    };
+    if let Err(e) = key_matches(&chain, &parsed) {
+        panic!(
+            "Invalid client key {} for certificate {}: {}",
+            key_path, cert_path, e
+        );
+    }
+    Some((cert, key))
+}
+
+/// Whether `MQTT_TRANSPORT` is `websocket` rather than the default `tcp`.
+pub fn websocket_from_env() -> bool {
+    match std::env::var("MQTT_TRANSPORT").as_deref() {
+        Ok("websocket") => true,
+        Ok("tcp") | Err(_) => false,
+        Ok(x) => panic!("Invalid MQTT_TRANSPORT {}, expected tcp or websocket", x),
+    }
+}
+
+/// Port of the broker for the configured transport.
+pub fn default_port() -> u16 {
+    match (websocket_from_env(), tls_from_env()) {
+        (false, false) => 1883,
+        (false, true) => 8883,
+        (true, false) => 80,
+        (true, true) => 443,
+    }
+}
+
+/// Reads the certificate authorities trusted to sign the broker certificate
+/// from `MQTT_CA_CERT` or, by default, those of the system, and the client
+/// certificate if one is configured.
+///
+/// The broker certificate is checked against the host name it is reached
+/// at, so that must not be an IP address.
+fn tls_from_files() -> (Vec<u8>, Option<(Vec<u8>, Key)>) {
+    let path = std::env::var("MQTT_CA_CERT").unwrap_or_else(|_| SYSTEM_CA_BUNDLE.to_owned());
+    let ca = std::fs::read(&path)
+        .unwrap_or_else(|e| panic!("Could not read CA certificates from {}: {}", path, e));
+    if rumqttc::certs(&mut &ca[..])
+        .map(|x| x.is_empty())
+        .unwrap_or(true)
+    {
+        panic!("No PEM certificates in {}", path);
+    }
+    (ca, client_auth_from_env())
+}
+
+/// Fallback brokers from the comma separated `MQTT_FALLBACK_BROKERS`, each
+/// given as `host` or `host:port` and reached with the same transport and
+/// credentials as the primary one, on `port` if none is given.
+fn fallback_brokers_from_env(port: u16) -> Vec<(String, u16)> {
+    std::env::var("MQTT_FALLBACK_BROKERS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| match x.rsplit_once(':') {
+            Some((host, port)) => (
+                host.to_owned(),
+                port.parse()
+                    .unwrap_or_else(|_| panic!("Invalid port in MQTT_FALLBACK_BROKERS {}", x)),
+            ),
+            None => (x.to_owned(), port),
+        })
+        .collect()
+}
+
+/// Options for connecting to `server`:`port` with the configured transport
+/// and credentials.
+fn options(client_id: &str, server: &str, port: u16, will: Option<&LastWill>) -> MqttOptions {
+    let websocket = websocket_from_env();
+    let tls = tls_from_env();
+    // The WebSocket transport connects to the URL in place of the host name
+    let address = if websocket {
+        format!(
+            "{}://{}:{}{}",
+            if tls { "wss" } else { "ws" },
+            server,
+            port,
+            std::env::var("MQTT_WS_PATH").unwrap_or_else(|_| "/mqtt".to_owned())
+        )
+    } else {
+        server.to_owned()
+    };
+    let mut mqttoptions = MqttOptions::new(client_id, address, port);
+    mqttoptions.set_keep_alive(5);
+    match (websocket, tls) {
+        (false, false) => {}
+        (false, true) => {
+            let (ca, client_auth) = tls_from_files();
+            mqttoptions.set_transport(Transport::tls(ca, client_auth, None));
+        }
+        (true, false) => {
+            mqttoptions.set_transport(Transport::ws());
+        }
+        (true, true) => {
+            let (ca, client_auth) = tls_from_files();
+            mqttoptions.set_transport(Transport::wss(ca, client_auth, None));
+        }
+    }
+    if let Some(will) = will {
+        mqttoptions.set_last_will(will.clone());
+    }
+    if let Some((username, password)) = credentials_from_env() {
+        mqttoptions.set_credentials(username, password);
+    }
+    mqttoptions
+}
+
+/// Connects to `server`:`port` as `client_id`, subscribing to `subscriptions` every time the
+/// connection is established. Messages received on those topics are
+/// forwarded to the returned receiver.
+///
+/// If `availability` is given, [`ONLINE`] is published retained on it on
+/// every connection and the broker is asked to publish [`OFFLINE`] if the
+/// connection is lost, as the only last will a connection can have.
+///
+/// After `MQTT_FAILOVER_ATTEMPTS` (default 3) failed attempts in a row,
+/// counting connections that drop within a minute, the next of the brokers
+/// in `MQTT_FALLBACK_BROKERS` is tried, going back to the first after the
+/// last.
+pub fn get_mqtt_conn(
+    client_id: &str,
+    server: &str,
+    port: u16,
+    subscriptions: &[&str],
+    availability: Option<&str>,
+) -> (Client, Arc<ConnectionState>, Receiver<Publish>) {
+    let will = availability.map(|x| LastWill::new(x, OFFLINE, QoS::AtLeastOnce, true));
+    let mut brokers = vec![(server.to_owned(), port)];
+    brokers.extend(fallback_brokers_from_env(port));
+    let failover_attempts = std::env::var("MQTT_FAILOVER_ATTEMPTS")
+        .map(|x| x.parse().expect("Invalid MQTT_FAILOVER_ATTEMPTS"))
+        .unwrap_or(3);
+    let mqttoptions = options(client_id, server, port, will.as_ref());
+    if let Some((username, _)) = credentials_from_env() {
+        info!("Authenticating to MQTT broker as {}", username);
+    }
+
+    let (client, connection) = Client::new(mqttoptions, 10);
+    let state = Arc::new(ConnectionState::default());
+    let (incoming_tx, incoming_rx) = channel();
+    let supervisor = Supervisor {
+        client: client.clone(),
+        state: state.clone(),
+        subscriptions: subscriptions.iter().map(|x| x.to_string()).collect(),
+        availability: availability.map(|x| x.to_owned()),
+        incoming: incoming_tx,
+        client_id: client_id.to_owned(),
+        will,
+        brokers,
+        failover_attempts,
+    };
+    std::thread::spawn(move || supervisor.run(connection));
+    (client, state, incoming_rx)
+}
+
+struct Supervisor {
+    client: Client,
+    state: Arc<ConnectionState>,
+    subscriptions: Vec<String>,
+    availability: Option<String>,
+    incoming: Sender<Publish>,
+    client_id: String,
+    will: Option<LastWill>,
+    /// The primary broker followed by the fallback ones
+    brokers: Vec<(String, u16)>,
+    failover_attempts: usize,
+}
+
+impl Supervisor {
+    /// Drives the event loop, logging why the connection drops and backing off
+    /// exponentially between reconnection attempts, and switches to the next
+    /// broker when the current one keeps failing.
+    fn run(mut self, mut connection: Connection) {
+        let mut backoff = Duration::from_secs(1);
+        let mut ever_connected = false;
+        let mut current = 0;
+        let mut failures = 0;
+        let mut connected_at: Option<Instant> = None;
+        loop {
+            let mut failover = false;
+            for notification in connection.iter() {
+                match notification {
+                    Ok(Event::Incoming(Packet::ConnAck(ack))) => {
+                        info!("Connected to MQTT broker ({:?})", ack.code);
+                        self.state.connected.store(true, Ordering::Relaxed);
+                        if ever_connected {
+                            self.state.reconnections.fetch_add(1, Ordering::Relaxed);
+                        }
+                        ever_connected = true;
+                        connected_at = Some(Instant::now());
+                        backoff = Duration::from_secs(1);
+                        // The event loop is not running while this is executing, so
+                        // subscribing must not wait for room in the request queue
+                        for topic in &self.subscriptions {
+                            self.client
+                                .try_subscribe(topic.as_str(), QoS::AtLeastOnce)
+                                .unwrap_or_else(|e| {
+                                    error!("Could not subscribe to {}: {}", topic, e)
+                                });
+                        }
+                        if let Some(topic) = &self.availability {
+                            self.client
+                                .try_publish(topic.as_str(), QoS::AtLeastOnce, true, ONLINE)
+                                .unwrap_or_else(|e| {
+                                    error!("Could not publish availability: {}", e)
+                                });
+                        }
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        // The receiving end only goes away when the daemon is exiting
+                        let _ = self.incoming.send(publish);
+                    }
+                    Ok(Event::Incoming(Packet::Disconnect)) => {
+                        warn!("MQTT broker closed the connection");
+                        self.state.connected.store(false, Ordering::Relaxed);
+                    }
+                    Ok(Event::Outgoing(Outgoing::Disconnect)) => {
+                        info!("Disconnected from MQTT broker");
+                        self.state.connected.store(false, Ordering::Relaxed);
+                        return;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        if self.state.connected.swap(false, Ordering::Relaxed) {
+                            error!("Lost connection to MQTT broker: {}", e);
+                        } else {
+                            warn!(
+                                "Could not connect to MQTT broker: {}, retrying in {}s",
+                                e,
+                                backoff.as_secs()
+                            );
+                        }
+                        if connected_at
+                            .take()
+                            .is_some_and(|x| x.elapsed() >= STABLE_CONNECTION)
+                        {
+                            failures = 0;
+                        }
+                        failures += 1;
+                        std::thread::sleep(backoff);
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                        if self.brokers.len() > 1 && failures >= self.failover_attempts {
+                            failover = true;
+                            break;
+                        }
+                    }
+                }
+            }
+            // Otherwise the client was dropped
+            if !failover {
+                return;
+            }
+            failures = 0;
+            let (old_host, old_port) = &self.brokers[current];
+            current = (current + 1) % self.brokers.len();
+            let (host, port) = &self.brokers[current];
+            warn!(
+                "Switching from MQTT broker {}:{} to {}:{}",
+                old_host, old_port, host, port
+            );
+            connection.eventloop.options =
+                options(&self.client_id, host, *port, self.will.as_ref());
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Events firing at a fixed offset from a computed sun event, such as "30
+//! minutes before sunrise".
+
+use crate::phase::{SunPosition, Thresholds};
+use crate::schedule;
+use std::str::FromStr;
+
+/// Seconds added on both sides of the window searched for reference events.
+const REFERENCE_MARGIN: i64 = 3600;
+
+#[derive(Debug, Clone)]
+pub struct OffsetEvent {
+    pub reference: SunPosition,
+    /// Seconds after the reference event, negative for before
+    pub offset: i64,
+    pub topic: String,
+    pub payload: String,
+}
+
+/// Parses `event±minutes:topic:payload`, e.g. `sunset-30:home/blinds:close`.
+impl FromStr for OffsetEvent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(3, ':');
+        let reference = parts.next().unwrap_or_default();
+        let (topic, payload) = match (parts.next(), parts.next()) {
+            (Some(topic), Some(payload)) if !topic.is_empty() => (topic, payload),
+            _ => return Err(format!("missing topic or payload in offset event `{}`", s)),
+        };
+        let sign = reference
+            .find(['+', '-'])
+            .ok_or_else(|| format!("missing offset in offset event `{}`", s))?;
+        let (name, minutes) = reference.split_at(sign);
+        let reference = *SunPosition::ALL
+            .iter()
+            .find(|x| <&'static str>::from(*x) == name)
+            .ok_or_else(|| format!("unknown event `{}` in offset event `{}`", name, s))?;
+        let minutes: i64 = minutes
+            .trim_start_matches('+')
+            .parse()
+            .map_err(|_| format!("invalid offset `{}` in offset event `{}`", minutes, s))?;
+        Ok(Self {
+            reference,
+            offset: minutes * 60,
+            topic: topic.to_owned(),
+            payload: payload.to_owned(),
+        })
+    }
+}
+
+/// Returns the events whose time falls within `[from, to)`, with that time.
+fn between<'a>(
+    events: &'a [OffsetEvent],
+    from: i64,
+    to: i64,
+    coords: &astro::coords::GeographPoint,
+    thresholds: &Thresholds,
+) -> Vec<(i64, &'a OffsetEvent)> {
+    let (min_offset, max_offset) = match (
+        events.iter().map(|e| e.offset).min(),
+        events.iter().map(|e| e.offset).max(),
+    ) {
+        (Some(min), Some(max)) => (min, max),
+        _ => return Vec::new(),
+    };
+    // Solar noon is only found within a window wide enough to see the sun
+    // rise and fall around it, so look a little beyond both ends
+    let references = schedule::events_between(
+        from - max_offset - REFERENCE_MARGIN,
+        to - min_offset + REFERENCE_MARGIN,
+        coords,
+        thresholds,
+    );
+    let mut due: Vec<_> = references
+        .iter()
+        .flat_map(|reference| {
+            events.iter().filter_map(move |e| {
+                let at = reference.timestamp + e.offset;
+                if e.reference == reference.position && at >= from && at < to {
+                    Some((at, e))
+                } else {
+                    None
+                }
+            })
+        })
+        .collect();
+    due.sort_by_key(|(at, _)| *at);
+    due
+}
+
+/// Returns the events whose time falls within `[from, to)`.
+pub fn due<'a>(
+    events: &'a [OffsetEvent],
+    from: i64,
+    to: i64,
+    coords: &astro::coords::GeographPoint,
+    thresholds: &Thresholds,
+) -> Vec<&'a OffsetEvent> {
+    between(events, from, to, coords, thresholds)
+        .into_iter()
+        .map(|(_, e)| e)
+        .collect()
+}
+
+/// Time of the first event within `[from, to)`.
+pub fn next(
+    events: &[OffsetEvent],
+    from: i64,
+    to: i64,
+    coords: &astro::coords::GeographPoint,
+    thresholds: &Thresholds,
+) -> Option<i64> {
+    between(events, from, to, coords, thresholds)
+        .first()
+        .map(|(at, _)| *at)
+}
+
+/// Reads the events of `LEGAL_LIGHT_OFFSETS` and those of `OFFSET_EVENTS`,
+/// separated by semicolons, such as
+/// `sunset-30:home/blinds:close;sunrise+15:sun/morning:wakeUp`.
+pub fn from_env() -> Vec<OffsetEvent> {
+    let mut events = legal_light_from_env();
+    if let Ok(x) = std::env::var("OFFSET_EVENTS") {
+        events.extend(x.split(';').filter(|e| !e.trim().is_empty()).map(|e| {
+            e.parse()
+                .unwrap_or_else(|e| panic!("Invalid offset event: {}", e))
+        }));
+    }
+    events
+}
+
+/// Reads `LEGAL_LIGHT_OFFSETS`, the minutes before sunrise and after sunset
+/// delimiting legal light (e.g. `30,30`), and returns its start and end
+/// events.
+fn legal_light_from_env() -> Vec<OffsetEvent> {
+    let offsets = match std::env::var("LEGAL_LIGHT_OFFSETS") {
+        Ok(x) => x,
+        Err(_) => return Vec::new(),
+    };
+    let (before, after) = offsets
+        .split_once(',')
+        .map(|(before, after)| (before.trim().parse::<i64>(), after.trim().parse::<i64>()))
+        .and_then(|x| match x {
+            (Ok(before), Ok(after)) => Some((before, after)),
+            _ => None,
+        })
+        .expect("Invalid legal light offsets");
+    vec![
+        OffsetEvent {
+            reference: SunPosition::Sunrise,
+            offset: -before * 60,
+            topic: "sun/legal_light".to_owned(),
+            payload: "legalLightStart".to_owned(),
+        },
+        OffsetEvent {
+            reference: SunPosition::Sunset,
+            offset: after * 60,
+            topic: "sun/legal_light".to_owned(),
+            payload: "legalLightEnd".to_owned(),
+        },
+    ]
+}
@@ -0,0 +1,69 @@
+//! Observer location following an OwnTracks device, for observers on the
+//! move.
+
+use serde::Deserialize;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    #[serde(rename = "_type")]
+    kind: String,
+    lat: Option<f64>,
+    lon: Option<f64>,
+}
+
+pub struct OwnTracks {
+    pub topic: String,
+    /// Distance in km the observer must move before the location is updated
+    min_distance: f64,
+}
+
+impl OwnTracks {
+    /// Follows the device publishing on `OWNTRACKS_TOPIC`, such as
+    /// `owntracks/user/phone`, ignoring moves shorter than
+    /// `OWNTRACKS_MIN_DISTANCE` km (default 5).
+    pub fn from_env() -> Option<Self> {
+        Some(Self {
+            topic: std::env::var("OWNTRACKS_TOPIC").ok()?,
+            min_distance: std::env::var("OWNTRACKS_MIN_DISTANCE")
+                .map(|x| x.parse().expect("Invalid OWNTRACKS_MIN_DISTANCE"))
+                .unwrap_or(5.0),
+        })
+    }
+
+    /// Returns the location in an OwnTracks message, if it is one and the
+    /// observer moved far enough from `current`.
+    pub fn location(
+        &self,
+        payload: &[u8],
+        current: &astro::coords::GeographPoint,
+    ) -> Option<astro::coords::GeographPoint> {
+        let message: Message = match serde_json::from_slice(payload) {
+            Ok(message) => message,
+            Err(e) => {
+                log::warn!("Invalid OwnTracks message: {}", e);
+                return None;
+            }
+        };
+        let (lat, long) = match (message.kind.as_str(), message.lat, message.lon) {
+            ("location", Some(lat), Some(lon))
+                if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) =>
+            {
+                (lat, lon)
+            }
+            _ => return None,
+        };
+        let distance = EARTH_RADIUS_KM
+            * astro::angle::anglr_sepr(
+                current.long.to_radians(),
+                current.lat.to_radians(),
+                long.to_radians(),
+                lat.to_radians(),
+            );
+        if distance < self.min_distance {
+            return None;
+        }
+        Some(astro::coords::GeographPoint { long, lat })
+    }
+}
@@ -0,0 +1,296 @@
+//! Payloads of the events and values that have a topic of their own: bare,
+//! such as `civilDusk` or `12.5`, or with `PAYLOAD_FORMAT=json` JSON objects
+//! that also carry the time and, for events, the position of the sun and of
+//! the observer.
+//!
+//! `PAYLOAD_EVENT_TEMPLATE` and `PAYLOAD_VALUE_TEMPLATE` replace either kind
+//! of payload with a string of their own, such as `SUN {{event}} {{iso_time}}`.
+//! Events can use `{{event}}`, `{{altitude}}`, `{{azimuth}}`, `{{latitude}}`,
+//! `{{longitude}}` and `{{location}}`, values `{{value}}`, and both
+//! `{{timestamp}}` and `{{iso_time}}`.
+//!
+//! `EVENT_NAMES` renames the phases in these payloads, such as
+//! `sunrise=alba,sunset=tramonto` or `night=NIGHT,sunrise=DAY`.
+//!
+//! With `PHASE_CODES=true` the phases are also published as integers on
+//! `sun/code`, following [`SunPosition::CODES`].
+
+use crate::phase::SunPosition;
+use chrono::TimeZone;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Plain,
+    Json,
+}
+
+#[derive(Debug, Serialize)]
+struct Event<'a> {
+    event: &'a str,
+    time: String,
+    timestamp: i64,
+    altitude: f64,
+    azimuth: f64,
+    latitude: f64,
+    longitude: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    location: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+struct Value<T> {
+    value: T,
+    time: String,
+    timestamp: i64,
+}
+
+/// ISO 8601 time in UTC, such as `2021-08-01T18:30:00Z`.
+fn iso_time(timestamp: i64) -> String {
+    chrono::Utc
+        .timestamp(timestamp, 0)
+        .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+const EVENT_PLACEHOLDERS: [&str; 8] = [
+    "event",
+    "altitude",
+    "azimuth",
+    "latitude",
+    "longitude",
+    "location",
+    "timestamp",
+    "iso_time",
+];
+const VALUE_PLACEHOLDERS: [&str; 3] = ["value", "timestamp", "iso_time"];
+
+/// Names between `{{` and `}}` in `template`, trimmed.
+fn placeholders(template: &str) -> Vec<&str> {
+    template
+        .split("{{")
+        .skip(1)
+        .filter_map(|x| x.split_once("}}"))
+        .map(|(name, _)| name.trim())
+        .collect()
+}
+
+/// Reads the template in `name`, checking that it only uses `allowed`.
+fn template_from_env(name: &str, allowed: &[&str]) -> Option<String> {
+    let template = std::env::var(name).ok()?;
+    if let Some(x) = placeholders(&template)
+        .into_iter()
+        .find(|x| !allowed.contains(x))
+    {
+        panic!(
+            "Invalid {}, unknown placeholder {{{{{}}}}}, expected one of {}",
+            name,
+            x,
+            allowed.join(", ")
+        );
+    }
+    Some(template)
+}
+
+/// Fills the placeholders of `template` with `fields`.
+fn render(template: &str, fields: &[(&str, String)]) -> String {
+    let mut parts = template.split("{{");
+    let mut rendered = parts.next().unwrap_or_default().to_owned();
+    for part in parts {
+        match part.split_once("}}") {
+            Some((name, rest)) => {
+                if let Some((_, value)) = fields.iter().find(|(x, _)| *x == name.trim()) {
+                    rendered.push_str(value);
+                }
+                rendered.push_str(rest);
+            }
+            None => {
+                rendered.push_str("{{");
+                rendered.push_str(part);
+            }
+        }
+    }
+    rendered
+}
+
+/// Parses the comma separated `event=name` pairs of `EVENT_NAMES`.
+pub fn parse_names(names: &str) -> Result<Vec<(SunPosition, String)>, String> {
+    let mut parsed: Vec<(SunPosition, String)> = Vec::new();
+    for pair in names.split(',').filter(|x| !x.trim().is_empty()) {
+        let (event, name) = pair
+            .split_once('=')
+            .ok_or_else(|| format!("{} is not event=name", pair))?;
+        let event = event.trim();
+        let position = *SunPosition::ALL
+            .iter()
+            .find(|x| <&'static str>::from(*x) == event)
+            .ok_or_else(|| format!("unknown event {}", event))?;
+        if parsed.iter().any(|(x, _)| *x == position) {
+            return Err(format!("{} is renamed twice", event));
+        }
+        parsed.push((position, name.trim().to_owned()));
+    }
+    Ok(parsed)
+}
+
+#[derive(Debug, Clone)]
+pub struct Payloads {
+    format: Format,
+    event_template: Option<String>,
+    value_template: Option<String>,
+    names: Vec<(SunPosition, String)>,
+    codes: bool,
+    location: Option<String>,
+}
+
+impl Payloads {
+    /// Reads `PAYLOAD_FORMAT`, `plain` (the default) or `json`, the templates,
+    /// the names of the events, `PHASE_CODES` and the name of the location
+    /// from `LOCATION_NAME`.
+    pub fn from_env() -> Self {
+        let format = match std::env::var("PAYLOAD_FORMAT").as_deref() {
+            Ok("plain") | Err(_) => Format::Plain,
+            Ok("json") => Format::Json,
+            Ok(x) => panic!("Invalid PAYLOAD_FORMAT {}, expected plain or json", x),
+        };
+        Self {
+            format,
+            event_template: template_from_env("PAYLOAD_EVENT_TEMPLATE", &EVENT_PLACEHOLDERS),
+            value_template: template_from_env("PAYLOAD_VALUE_TEMPLATE", &VALUE_PLACEHOLDERS),
+            names: std::env::var("EVENT_NAMES")
+                .map(|x| parse_names(&x).unwrap_or_else(|e| panic!("Invalid EVENT_NAMES: {}", e)))
+                .unwrap_or_default(),
+            codes: std::env::var("PHASE_CODES")
+                .map(|x| {
+                    x.parse()
+                        .expect("Invalid PHASE_CODES, expected true or false")
+                })
+                .unwrap_or(false),
+            location: std::env::var("LOCATION_NAME").ok(),
+        }
+    }
+
+    /// Uses `location` in place of `LOCATION_NAME`.
+    pub fn with_location(self, location: &str) -> Self {
+        Self {
+            location: Some(location.to_owned()),
+            ..self
+        }
+    }
+
+    /// Whether the payloads are the built-in JSON objects, so that consumers
+    /// must extract the `event` or `value` member.
+    pub fn is_json(&self) -> bool {
+        self.format == Format::Json
+            && self.event_template.is_none()
+            && self.value_template.is_none()
+    }
+
+    /// Payload announcing the phase `position` at `now`, under the name given
+    /// in `EVENT_NAMES` if any.
+    pub fn phase(
+        &self,
+        position: &SunPosition,
+        now: i64,
+        coords: &astro::coords::GeographPoint,
+    ) -> String {
+        let name = self
+            .names
+            .iter()
+            .find(|(x, _)| x == position)
+            .map(|(_, name)| name.as_str())
+            .unwrap_or_else(|| position.into());
+        self.event(name, now, coords)
+    }
+
+    /// Phase announced by `payload`, as published by [`Payloads::phase`],
+    /// unless it comes from a template.
+    pub fn parse_phase(&self, payload: &str) -> Option<SunPosition> {
+        if self.event_template.is_some() {
+            return None;
+        }
+        let name = match self.format {
+            Format::Plain => payload.trim().to_owned(),
+            Format::Json => serde_json::from_str::<serde_json::Value>(payload)
+                .ok()?
+                .get("event")?
+                .as_str()?
+                .to_owned(),
+        };
+        self.names
+            .iter()
+            .find(|(_, x)| *x == name)
+            .map(|(position, _)| *position)
+            .or_else(|| {
+                SunPosition::ALL
+                    .iter()
+                    .find(|x| <&'static str>::from(*x) == name)
+                    .copied()
+            })
+    }
+
+    /// Payload of `sun/code` for the phase `position`, if enabled.
+    pub fn code(&self, position: &SunPosition) -> Option<String> {
+        Some(position.code().to_string()).filter(|_| self.codes)
+    }
+
+    /// Payload announcing `event` at `now`.
+    pub fn event(&self, event: &str, now: i64, coords: &astro::coords::GeographPoint) -> String {
+        let position = || crate::solar::pos(now * 1000, coords.lat, coords.long);
+        if let Some(template) = &self.event_template {
+            let position = position();
+            return render(
+                template,
+                &[
+                    ("event", event.to_owned()),
+                    ("altitude", position.altitude.to_degrees().to_string()),
+                    ("azimuth", position.azimuth.to_degrees().to_string()),
+                    ("latitude", coords.lat.to_string()),
+                    ("longitude", coords.long.to_string()),
+                    ("location", self.location.clone().unwrap_or_default()),
+                    ("timestamp", now.to_string()),
+                    ("iso_time", iso_time(now)),
+                ],
+            );
+        }
+        match self.format {
+            Format::Plain => event.to_owned(),
+            Format::Json => {
+                let position = position();
+                serde_json::to_string(&Event {
+                    event,
+                    time: iso_time(now),
+                    timestamp: now,
+                    altitude: position.altitude.to_degrees(),
+                    azimuth: position.azimuth.to_degrees(),
+                    latitude: coords.lat,
+                    longitude: coords.long,
+                    location: self.location.as_deref(),
+                })
+                .expect("Could not serialize the event")
+            }
+        }
+    }
+
+    /// Payload of a value sampled at `now`.
+    pub fn value<T: Serialize + std::fmt::Display>(&self, value: T, now: i64) -> String {
+        if let Some(template) = &self.value_template {
+            return render(
+                template,
+                &[
+                    ("value", value.to_string()),
+                    ("timestamp", now.to_string()),
+                    ("iso_time", iso_time(now)),
+                ],
+            );
+        }
+        match self.format {
+            Format::Plain => value.to_string(),
+            Format::Json => serde_json::to_string(&Value {
+                value,
+                time: iso_time(now),
+                timestamp: now,
+            })
+            .expect("Could not serialize the value"),
+        }
+    }
+}
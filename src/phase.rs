@@ -0,0 +1,94 @@
+use std::time::{Duration, Instant};
+
+pub use mqtt_sun_core::phase::{SunPosition, Thresholds};
+
+/// Reads the four comma separated thresholds from `PHASE_THRESHOLDS`,
+/// e.g. `-18,-12,-6,-0.833` to account for refraction and the solar
+/// radius at sunrise and sunset, corrected for the observer if given.
+pub fn thresholds_from_env() -> Thresholds {
+    let thresholds = match std::env::var("PHASE_THRESHOLDS") {
+        Ok(x) => x
+            .split(',')
+            .map(|x| x.trim().parse().expect("Invalid phase threshold"))
+            .collect::<Vec<f64>>(),
+        Err(_) => return crate::solar::correct(Thresholds::default()),
+    };
+    let thresholds = match thresholds.as_slice() {
+        &[astronomical, nautical, civil, horizon] => Thresholds {
+            astronomical,
+            nautical,
+            civil,
+            horizon,
+        },
+        _ => panic!("PHASE_THRESHOLDS needs exactly four values"),
+    };
+    assert!(
+        thresholds.astronomical < thresholds.nautical
+            && thresholds.nautical < thresholds.civil
+            && thresholds.civil < thresholds.horizon,
+        "Phase thresholds must be increasing"
+    );
+    crate::solar::correct(thresholds)
+}
+
+/// Debounces phase transitions, so that each one fires exactly once even
+/// when the altitude lingers around a threshold.
+///
+/// A new phase is only accepted once the sun is past its threshold by
+/// `hysteresis` degrees in the direction it is moving, and after the current
+/// phase has lasted at least `min_dwell`.
+#[derive(Debug)]
+pub struct PhaseTracker {
+    pub thresholds: Thresholds,
+    hysteresis: f64,
+    min_dwell: Duration,
+    current: Option<(SunPosition, Instant)>,
+}
+
+impl PhaseTracker {
+    /// Reads `PHASE_HYSTERESIS` (degrees) and `PHASE_MIN_DWELL` (seconds),
+    /// both disabled by default.
+    pub fn from_env() -> Self {
+        Self {
+            thresholds: thresholds_from_env(),
+            hysteresis: std::env::var("PHASE_HYSTERESIS")
+                .map(|x| x.parse().expect("Invalid phase hysteresis"))
+                .unwrap_or(0.0),
+            min_dwell: Duration::from_secs(
+                std::env::var("PHASE_MIN_DWELL")
+                    .map(|x| x.parse().expect("Invalid phase minimum dwell time"))
+                    .unwrap_or(0),
+            ),
+            current: None,
+        }
+    }
+
+    pub fn current(&self) -> Option<SunPosition> {
+        self.current.map(|(phase, _)| phase)
+    }
+
+    /// Forgets the current phase, so that the next update announces it
+    /// again.
+    pub fn reset(&mut self) {
+        self.current = None;
+    }
+
+    /// Feeds the current altitude in radians, returning the new phase if a
+    /// transition happened.
+    pub fn update(&mut self, altitude: f64, is_morning: bool) -> Option<SunPosition> {
+        let candidate = self.thresholds.classify(altitude, is_morning);
+        if let Some((current, since)) = self.current {
+            if current == candidate || since.elapsed() < self.min_dwell {
+                return None;
+            }
+            // The sun rises in the morning and sets in the afternoon
+            let direction = if is_morning { 1.0 } else { -1.0 };
+            let lagging = altitude - direction * self.hysteresis.to_radians();
+            if self.thresholds.classify(lagging, is_morning) != candidate {
+                return None;
+            }
+        }
+        self.current = Some((candidate, Instant::now()));
+        Some(candidate)
+    }
+}
@@ -0,0 +1,50 @@
+//! One retained topic per phase, such as `sun/phase/civilDusk`, switched on
+//! while the sun is in it and off otherwise, for controllers that can only
+//! match a topic with a payload.
+
+use crate::phase::SunPosition;
+use crate::switch::Switch;
+
+pub struct PhaseTopics {
+    switch: Switch,
+    current: Option<SunPosition>,
+}
+
+impl PhaseTopics {
+    /// Enabled by `PHASE_TOPICS`, with the payloads of `PHASE_PAYLOADS`
+    /// (default `ON,OFF`).
+    pub fn from_env() -> Option<Self> {
+        let enabled = std::env::var("PHASE_TOPICS")
+            .map(|x| {
+                x.parse()
+                    .expect("Invalid PHASE_TOPICS, expected true or false")
+            })
+            .unwrap_or(false);
+        Some(Self {
+            switch: Switch::from_env("PHASE_PAYLOADS", "ON,OFF"),
+            current: None,
+        })
+        .filter(|_| enabled)
+    }
+
+    /// Returns the topics and payloads to publish as the sun enters `phase`:
+    /// every phase the first time, then the one left and the one entered.
+    pub fn update(&mut self, phase: SunPosition) -> Vec<(String, String)> {
+        let changed: Vec<SunPosition> = match self.current {
+            Some(current) if current == phase => Vec::new(),
+            Some(current) => vec![current, phase],
+            None => SunPosition::ALL.to_vec(),
+        };
+        self.current = Some(phase);
+        changed
+            .iter()
+            .map(|x| {
+                let name: &'static str = x.into();
+                (
+                    format!("sun/phase/{}", name),
+                    self.switch.payload(*x == phase).to_owned(),
+                )
+            })
+            .collect()
+    }
+}
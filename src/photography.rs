@@ -0,0 +1,58 @@
+//! Golden hour, when the sun is low enough for warm and soft light, and blue
+//! hour, when it is just below the horizon and the sky turns deep blue,
+//! announced on `sun/photography` as they begin and end, in the morning and in
+//! the evening alike.
+
+use crate::band::Band;
+
+#[derive(Debug)]
+pub struct Window {
+    /// Prefix of the event names, such as `goldenHour` for `goldenHourStart`
+    name: &'static str,
+    band: Band,
+}
+
+impl Window {
+    /// Reads the bounds of the window from `variable`, such as `-4,6`.
+    fn from_env(name: &'static str, variable: &str, default: &str) -> Self {
+        Self {
+            name,
+            band: Band::from_env(variable, default),
+        }
+    }
+
+    /// Lowest and highest altitude of the window in degrees.
+    pub fn bounds(&self) -> [f64; 2] {
+        self.band.bounds()
+    }
+
+    /// Feeds the sun altitude in degrees, returning the event if the sun
+    /// entered or left the window.
+    pub fn update(&mut self, altitude: f64) -> Option<String> {
+        match self.band.update(altitude)? {
+            true => Some(format!("{}Start", self.name)),
+            false => Some(format!("{}End", self.name)),
+        }
+    }
+}
+
+/// The golden hour, between `GOLDEN_HOUR_BAND` (default `-4,6`), and the
+/// blue hour, between `BLUE_HOUR_BAND` (default `-6,-4`).
+pub fn from_env() -> Vec<Window> {
+    vec![
+        Window::from_env("goldenHour", "GOLDEN_HOUR_BAND", "-4,6"),
+        Window::from_env("blueHour", "BLUE_HOUR_BAND", "-6,-4"),
+    ]
+}
+
+/// Feeds the sun altitude in degrees to every window, returning the events
+/// with those leaving a window first, so that the one entered next is the
+/// last state published.
+pub fn update(windows: &mut [Window], altitude: f64) -> Vec<String> {
+    let mut events: Vec<_> = windows
+        .iter_mut()
+        .filter_map(|x| x.update(altitude))
+        .collect();
+    events.sort_by_key(|x| !x.ends_with("End"));
+    events
+}
@@ -0,0 +1,36 @@
+//! Polar day and polar night, when the sun stays above or below the horizon
+//! for the whole local day and neither rises nor sets.
+
+use crate::phase::Thresholds;
+use crate::schedule;
+
+pub const TOPIC: &str = "sun/polar";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Polar {
+    Day,
+    Night,
+}
+
+impl Polar {
+    pub fn name(self) -> &'static str {
+        match self {
+            Polar::Day => "polarDay",
+            Polar::Night => "polarNight",
+        }
+    }
+}
+
+/// Whether the local day `date` is a polar day or a polar night.
+pub fn on(
+    date: chrono::NaiveDate,
+    coords: &astro::coords::GeographPoint,
+    thresholds: &Thresholds,
+) -> Option<Polar> {
+    let length = schedule::local_midnight(date.succ()) - schedule::local_midnight(date);
+    match schedule::daylight(date, coords, thresholds) {
+        0 => Some(Polar::Night),
+        x if x >= length => Some(Polar::Day),
+        _ => None,
+    }
+}
@@ -0,0 +1,155 @@
+//! Islamic prayer times, enabled by choosing a calculation method in
+//! `PRAYER_METHOD`: `mwl` (Muslim World League), `isna`, `egypt`, `makkah`,
+//! `karachi` or `tehran`. `PRAYER_ANGLES`, such as `18,17`, replaces the
+//! depression of the sun at Fajr and Isha, and `PRAYER_ASR=hanafi` waits for
+//! shadows twice as long as the objects instead of as long.
+//!
+//! Each prayer is announced on `sun/prayer` as it begins, and the times of
+//! the day are kept retained on `sun/prayer/timetable`, `null` for those the
+//! sun never reaches the angle for.
+
+use crate::{ephemeris, schedule};
+
+pub const TOPIC: &str = "sun/prayer";
+pub const TIMETABLE_TOPIC: &str = "sun/prayer/timetable";
+
+/// Altitude of the sun at sunrise and sunset in degrees, for refraction and
+/// the solar radius.
+const HORIZON: f64 = -0.833;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Isha {
+    /// Depression of the sun in degrees
+    Angle(f64),
+    /// Minutes after Maghrib
+    Minutes(i64),
+}
+
+#[derive(Debug)]
+pub struct Prayers {
+    /// Depression of the sun at Fajr in degrees
+    fajr: f64,
+    isha: Isha,
+    /// Depression of the sun at Maghrib in degrees, if not at sunset
+    maghrib: Option<f64>,
+    /// Length of the shadows at Asr, as a multiple of the objects
+    asr_shadow: f64,
+}
+
+impl Prayers {
+    pub fn from_env() -> Option<Self> {
+        let method = std::env::var("PRAYER_METHOD").ok()?;
+        let (fajr, isha, maghrib) = match method.as_str() {
+            "mwl" => (18.0, Isha::Angle(17.0), None),
+            "isna" => (15.0, Isha::Angle(15.0), None),
+            "egypt" => (19.5, Isha::Angle(17.5), None),
+            "makkah" => (18.5, Isha::Minutes(90), None),
+            "karachi" => (18.0, Isha::Angle(18.0), None),
+            "tehran" => (17.7, Isha::Angle(14.0), Some(4.5)),
+            x => panic!(
+                "Invalid PRAYER_METHOD {}, expected mwl, isna, egypt, makkah, karachi or tehran",
+                x
+            ),
+        };
+        let (fajr, isha) = match std::env::var("PRAYER_ANGLES") {
+            Ok(angles) => {
+                let angles: Vec<f64> = angles
+                    .split(',')
+                    .map(|x| x.trim().parse().expect("Invalid PRAYER_ANGLES"))
+                    .collect();
+                match angles.as_slice() {
+                    &[fajr, isha] if fajr > 0.0 && isha > 0.0 => (fajr, Isha::Angle(isha)),
+                    _ => panic!("PRAYER_ANGLES needs the positive depressions at Fajr and Isha"),
+                }
+            }
+            Err(_) => (fajr, isha),
+        };
+        let asr_shadow = match std::env::var("PRAYER_ASR").as_deref() {
+            Ok("standard") | Err(_) => 1.0,
+            Ok("hanafi") => 2.0,
+            Ok(x) => panic!("Invalid PRAYER_ASR {}, expected standard or hanafi", x),
+        };
+        Some(Self {
+            fajr,
+            isha,
+            maghrib,
+            asr_shadow,
+        })
+    }
+
+    /// Times of the prayers on the local day `date`, in order.
+    pub fn day(
+        &self,
+        date: chrono::NaiveDate,
+        coords: &astro::coords::GeographPoint,
+    ) -> Vec<(&'static str, Option<i64>)> {
+        let start = schedule::local_midnight(date);
+        let end = schedule::local_midnight(date.succ());
+        let dhuhr = ephemeris::transit_on(date, coords.long);
+        // The last crossing before noon is the one rising
+        let morning = |altitude| {
+            let mut crossing = None;
+            while let Some(x) = schedule::next_crossing(
+                crossing.map(|x| x + 1).unwrap_or(start),
+                dhuhr,
+                altitude,
+                coords,
+            ) {
+                crossing = Some(x);
+            }
+            crossing
+        };
+        let evening = |altitude| schedule::next_crossing(dhuhr, end, altitude, coords);
+
+        // Shadows as long as the objects times the factor, plus the noon one
+        let (_, declination) = ephemeris::sun_equatorial(ephemeris::julian_day(dhuhr));
+        let noon_shadow = (coords.lat.to_radians() - declination).abs().tan();
+        let asr = (1.0 / (self.asr_shadow + noon_shadow)).atan().to_degrees();
+
+        let sunset = evening(HORIZON);
+        let maghrib = match self.maghrib {
+            Some(angle) => evening(-angle),
+            None => sunset,
+        };
+        let isha = match self.isha {
+            Isha::Angle(angle) => evening(-angle),
+            Isha::Minutes(minutes) => maghrib.map(|x| x + minutes * 60),
+        };
+        vec![
+            ("fajr", morning(-self.fajr)),
+            ("sunrise", morning(HORIZON)),
+            ("dhuhr", Some(dhuhr).filter(|x| (start..end).contains(x))),
+            ("asr", evening(asr)),
+            ("maghrib", maghrib),
+            ("isha", isha),
+        ]
+    }
+
+    /// Message of `sun/prayer/timetable` for the local day `date`.
+    pub fn timetable(
+        &self,
+        date: chrono::NaiveDate,
+        coords: &astro::coords::GeographPoint,
+    ) -> serde_json::Value {
+        let mut timetable = serde_json::json!({ "date": date.to_string() });
+        for (name, time) in self.day(date, coords) {
+            timetable[name] = time.into();
+        }
+        timetable
+    }
+
+    /// First prayer after `from`, on the local day `date` or the next.
+    pub fn next(
+        &self,
+        from: i64,
+        date: chrono::NaiveDate,
+        coords: &astro::coords::GeographPoint,
+    ) -> Option<(&'static str, i64)> {
+        [date, date.succ()].iter().find_map(|date| {
+            self.day(*date, coords)
+                .into_iter()
+                .filter_map(|(name, time)| Some((name, time?)))
+                .find(|(_, time)| *time > from)
+        })
+    }
+}
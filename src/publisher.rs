@@ -0,0 +1,215 @@
+use crate::encryption::Encrypter;
+use crate::mqtt::ConnectionState;
+use crate::signing::Signer;
+use crate::topics::Topics;
+use rumqttc::{Client, QoS};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+/// Quality of service of each class of topics.
+#[derive(Debug, Clone, Copy)]
+pub struct Qos {
+    /// Phase changes and the other discrete events
+    pub events: QoS,
+    /// Values published on every iteration, such as the altitude
+    pub telemetry: QoS,
+    /// Retained documents
+    pub state: QoS,
+}
+
+impl Qos {
+    /// Reads the levels from `MQTT_QOS_EVENTS`, `MQTT_QOS_TELEMETRY` and
+    /// `MQTT_QOS_STATE`, all 2 by default.
+    pub fn from_env() -> Self {
+        let level = |name| match std::env::var(name).as_deref() {
+            Ok("0") => QoS::AtMostOnce,
+            Ok("1") => QoS::AtLeastOnce,
+            Ok("2") | Err(_) => QoS::ExactlyOnce,
+            Ok(x) => panic!("Invalid {} {}, expected 0, 1 or 2", name, x),
+        };
+        Self {
+            events: level("MQTT_QOS_EVENTS"),
+            telemetry: level("MQTT_QOS_TELEMETRY"),
+            state: level("MQTT_QOS_STATE"),
+        }
+    }
+}
+
+/// Publishes messages to the broker, remembering the last payload of every
+/// retained topic so that it can be published again if the broker loses it.
+/// JSON payloads are signed if a signer is given, then every payload is
+/// encrypted if an encrypter is given. Topics are given in their built-in
+/// form and mapped through [`Topics`] when sent.
+///
+/// While the broker is unreachable the events are kept in memory, up to
+/// `EVENT_BUFFER_SIZE` (default 100) of them, and replayed in order once the
+/// connection is back. Retained topics are published again from their last
+/// payload instead, and telemetry is dropped as it would be stale anyway.
+pub struct Publisher {
+    client: Client,
+    state: Arc<ConnectionState>,
+    topics: Topics,
+    signer: Option<Signer>,
+    encrypter: Option<Encrypter>,
+    qos: Qos,
+    retain_state: bool,
+    retained: HashMap<String, String>,
+    buffer: VecDeque<(String, String, QoS)>,
+    buffer_size: usize,
+    /// Whether retained topics were skipped while disconnected
+    retained_missed: bool,
+}
+
+impl Publisher {
+    pub fn new(
+        client: Client,
+        state: Arc<ConnectionState>,
+        topics: Topics,
+        signer: Option<Signer>,
+        encrypter: Option<Encrypter>,
+        qos: Qos,
+        retain_state: bool,
+    ) -> Self {
+        Self {
+            client,
+            state,
+            topics,
+            signer,
+            encrypter,
+            qos,
+            retain_state,
+            retained: HashMap::new(),
+            buffer: VecDeque::new(),
+            buffer_size: std::env::var("EVENT_BUFFER_SIZE")
+                .map(|x| x.parse().expect("Invalid EVENT_BUFFER_SIZE"))
+                .unwrap_or(100),
+            retained_missed: false,
+        }
+    }
+
+    /// Payload received on the full `topic` as it was before publishing, if
+    /// it can be decrypted.
+    pub fn decrypt(&self, topic: &str, payload: &str) -> Option<String> {
+        match &self.encrypter {
+            Some(encrypter) => encrypter.decrypt(topic, payload),
+            None => Some(payload.to_owned()),
+        }
+    }
+
+    /// Sends a message, or buffers it if `buffered` and the broker is
+    /// unreachable.
+    fn send(&mut self, topic: &str, payload: &str, qos: QoS, retain: bool, buffered: bool) {
+        let topic = self.topics.topic(topic);
+        let mut payload = payload.to_owned();
+        if let Some(signer) = &self.signer {
+            payload = signer.sign(&payload);
+        }
+        if let Some(encrypter) = &self.encrypter {
+            payload = encrypter.encrypt(&topic, &payload);
+        }
+        if !self.state.is_connected() {
+            if retain {
+                self.retained_missed = true;
+            } else if buffered && self.buffer_size > 0 {
+                if self.buffer.len() == self.buffer_size {
+                    log::warn!("Event buffer full, dropping the oldest event");
+                    self.buffer.pop_front();
+                }
+                self.buffer.push_back((topic, payload, qos));
+            }
+            return;
+        }
+        // Keep the events in order
+        self.flush();
+        self.client
+            .publish(topic, qos, retain, payload.as_bytes())
+            .unwrap_or_else(|_| log::error!("Could not publish event to MQTT server"));
+    }
+
+    pub fn publish(&mut self, topic: &str, payload: &str) {
+        self.send(topic, payload, self.qos.events, false, true);
+    }
+
+    /// Publishes the current value of a state, such as the phase, retained
+    /// if so configured so that new subscribers receive it immediately.
+    pub fn publish_state(&mut self, topic: &str, payload: &str) {
+        if self.retain_state {
+            self.send(topic, payload, self.qos.events, true, false);
+            self.retained.insert(topic.to_owned(), payload.to_owned());
+        } else {
+            self.publish(topic, payload);
+        }
+    }
+
+    /// Publishes a value sampled on every iteration.
+    pub fn publish_telemetry(&mut self, topic: &str, payload: &str) {
+        self.send(topic, payload, self.qos.telemetry, false, false);
+    }
+
+    pub fn publish_retained(&mut self, topic: &str, payload: &str) {
+        self.send(topic, payload, self.qos.state, true, false);
+        self.retained.insert(topic.to_owned(), payload.to_owned());
+    }
+
+    /// Clears every retained topic on the broker by publishing an empty
+    /// payload to it.
+    pub fn clear_retained(&mut self) {
+        for topic in std::mem::take(&mut self.retained).keys() {
+            // Acknowledged as soon as the broker receives it, so that it is
+            // not lost when disconnecting right after
+            self.client
+                .publish(self.topics.topic(topic), QoS::AtLeastOnce, true, Vec::new())
+                .unwrap_or_else(|_| log::error!("Could not clear {}", topic));
+        }
+    }
+
+    /// Publishes `payload` retained on `topic` as is, neither signed nor
+    /// encrypted, for the availability and discovery messages that other
+    /// software must be able to read.
+    pub fn publish_plain_retained(&mut self, topic: &str, payload: &str) {
+        self.client
+            .publish(
+                self.topics.topic(topic),
+                QoS::AtLeastOnce,
+                true,
+                payload.as_bytes(),
+            )
+            .unwrap_or_else(|_| log::error!("Could not publish to {}", topic));
+    }
+
+    pub fn disconnect(&mut self) {
+        self.client
+            .disconnect()
+            .unwrap_or_else(|_| log::error!("Could not disconnect from MQTT server"));
+    }
+
+    /// Publishes again the last payload of every retained topic.
+    pub fn republish_retained(&mut self) {
+        let retained: Vec<_> = self.retained.clone().into_iter().collect();
+        for (topic, payload) in retained {
+            self.send(&topic, &payload, self.qos.state, true, false);
+        }
+    }
+
+    /// Once connected, replays the events buffered while the broker was
+    /// unreachable and publishes again the retained topics skipped meanwhile.
+    pub fn flush(&mut self) {
+        if !self.state.is_connected() {
+            return;
+        }
+        if !self.buffer.is_empty() {
+            log::info!(
+                "Replaying {} events buffered while disconnected",
+                self.buffer.len()
+            );
+        }
+        while let Some((topic, payload, qos)) = self.buffer.pop_front() {
+            self.client
+                .publish(topic, qos, false, payload.into_bytes())
+                .unwrap_or_else(|_| log::error!("Could not publish event to MQTT server"));
+        }
+        if std::mem::take(&mut self.retained_missed) {
+            self.republish_retained();
+        }
+    }
+}
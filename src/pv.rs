@@ -0,0 +1,135 @@
+use crate::clear_sky;
+
+pub const ESTIMATE_TOPIC: &str = "sun/pv_estimate";
+
+/// Irradiance of the standard test conditions in W/m².
+const STC_IRRADIANCE: f64 = 1000.0;
+/// Seconds between the samples of the daily estimate.
+const DAY_STEP: i64 = 10 * 60;
+
+/// A photovoltaic array, with `azimuth` and `tilt` in degrees, `area` in m²
+/// and `efficiency` as a fraction, or `peak_power` in W.
+#[derive(Debug)]
+pub struct PvArray {
+    pub azimuth: f64,
+    pub tilt: f64,
+    pub area: f64,
+    pub efficiency: f64,
+    pub peak_power: Option<f64>,
+    /// Fraction lost in the inverter, the wiring and to soiling
+    pub losses: f64,
+}
+
+impl PvArray {
+    /// Reads the array from the `PV_*` variables, if `PV_AREA` or
+    /// `PV_PEAK_POWER` is set.
+    pub fn from_env() -> Option<Self> {
+        let var = |name: &str, default: f64| {
+            std::env::var(name)
+                .map(|x| x.parse().unwrap_or_else(|_| panic!("Invalid {}", name)))
+                .unwrap_or(default)
+        };
+        let peak_power = std::env::var("PV_PEAK_POWER")
+            .ok()
+            .map(|x| x.parse::<f64>().expect("Invalid PV_PEAK_POWER"));
+        if peak_power.is_none() {
+            std::env::var("PV_AREA").ok()?;
+        }
+        assert!(
+            peak_power.map(|x| x > 0.0).unwrap_or(true),
+            "PV_PEAK_POWER must be positive"
+        );
+        let losses = var("PV_LOSSES", 0.0);
+        assert!(
+            (0.0..1.0).contains(&losses),
+            "PV_LOSSES must be a fraction between 0 and 1"
+        );
+        Some(Self {
+            azimuth: var("PV_AZIMUTH", 180.0),
+            tilt: var("PV_TILT", 30.0),
+            area: var("PV_AREA", 0.0),
+            efficiency: var("PV_EFFICIENCY", 0.2),
+            peak_power,
+            losses,
+        })
+    }
+
+    /// Irradiance on the plane of the array in W/m², for the sun at the given
+    /// azimuth and altitude in degrees.
+    pub fn plane_of_array_irradiance(&self, azimuth: f64, altitude: f64) -> f64 {
+        let tilt = self.tilt.to_radians();
+        let cos_incidence = altitude.to_radians().sin() * tilt.cos()
+            + altitude.to_radians().cos()
+                * tilt.sin()
+                * (azimuth - self.azimuth).to_radians().cos();
+        clear_sky::direct_normal_irradiance(altitude) * cos_incidence.max(0.0)
+            + clear_sky::diffuse_horizontal_irradiance(altitude) * (1.0 + tilt.cos()) / 2.0
+    }
+
+    /// Estimated clear-sky output power in W.
+    pub fn power(&self, azimuth: f64, altitude: f64) -> f64 {
+        let irradiance = self.plane_of_array_irradiance(azimuth, altitude);
+        let power = match self.peak_power {
+            Some(peak_power) => irradiance / STC_IRRADIANCE * peak_power,
+            None => irradiance * self.area * self.efficiency,
+        };
+        power * (1.0 - self.losses)
+    }
+
+    /// Estimated clear-sky energy in Wh produced over the local day `date`.
+    pub fn day_energy(
+        &self,
+        date: chrono::NaiveDate,
+        coords: &astro::coords::GeographPoint,
+    ) -> f64 {
+        let start = crate::schedule::local_midnight(date);
+        let end = crate::schedule::local_midnight(date.succ());
+        (start..end)
+            .step_by(DAY_STEP as usize)
+            .map(|timestamp| {
+                let position = crate::solar::pos(timestamp * 1000, coords.lat, coords.long);
+                self.power(
+                    position.azimuth.to_degrees(),
+                    position.altitude.to_degrees(),
+                )
+            })
+            .sum::<f64>()
+            * DAY_STEP as f64
+            / 3600.0
+    }
+}
+
+/// Message of `sun/pv_estimate`, with the power in W and the energy in Wh
+/// produced so far today and expected over the whole day.
+pub fn estimate(power: f64, energy: f64, day_energy: f64) -> serde_json::Value {
+    serde_json::json!({
+        "power": power.round(),
+        "energy": energy.round(),
+        "day_energy": day_energy.round(),
+    })
+}
+
+/// Integrates power samples into the energy produced since local midnight.
+#[derive(Debug, Default)]
+pub struct EnergyMeter {
+    day: Option<chrono::NaiveDate>,
+    last_sample: Option<(i64, f64)>,
+    energy: f64,
+}
+
+impl EnergyMeter {
+    /// Adds a power sample in W taken at `timestamp` (Unix seconds) and
+    /// returns today's energy in Wh.
+    pub fn add(&mut self, timestamp: i64, power: f64) -> f64 {
+        let today = chrono::Local::today().naive_local();
+        if self.day != Some(today) {
+            self.day = Some(today);
+            self.energy = 0.0;
+        } else if let Some((last_timestamp, last_power)) = self.last_sample {
+            let hours = (timestamp - last_timestamp) as f64 / 3600.0;
+            self.energy += (last_power + power) / 2.0 * hours;
+        }
+        self.last_sample = Some((timestamp, power));
+        self.energy
+    }
+}
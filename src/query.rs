@@ -0,0 +1,158 @@
+//! Request/response interface for computing historical or future events.
+//!
+//! A request such as
+//! `{"from": "2021-08-01T00:00:00Z", "to": "2021-08-03T00:00:00Z", "events": ["sunrise", "sunset"]}`
+//! published on [`QUERY_TOPIC`] is answered on `reply_to` (defaulting to
+//! [`RESPONSE_TOPIC`]) with the matching events in that interval, of at most
+//! a month. Omitting `events` returns all of them. Replies only go to
+//! `sun/.../response` topics.
+
+use crate::phase::Thresholds;
+use crate::schedule;
+use serde::Deserialize;
+
+pub const QUERY_TOPIC: &str = "sun/cmd/query";
+pub const RESPONSE_TOPIC: &str = "sun/cmd/query/response";
+
+/// Longest interval a single query may span, to bound the time the main
+/// loop spends answering it.
+const MAX_INTERVAL: i64 = 31 * 24 * 3600;
+
+#[derive(Debug, Deserialize)]
+struct Query {
+    from: String,
+    to: String,
+    events: Option<Vec<String>>,
+    reply_to: Option<String>,
+}
+
+fn parse_time(time: &str) -> Result<i64, String> {
+    chrono::DateTime::parse_from_rfc3339(time)
+        .map(|x| x.timestamp())
+        .map_err(|e| format!("invalid time `{}`: {}", time, e))
+}
+
+fn answer(
+    query: &Query,
+    coords: &astro::coords::GeographPoint,
+    thresholds: &Thresholds,
+) -> Result<serde_json::Value, String> {
+    let from = parse_time(&query.from)?;
+    let to = parse_time(&query.to)?;
+    if to < from {
+        return Err("`to` precedes `from`".to_owned());
+    }
+    if to - from > MAX_INTERVAL {
+        return Err("the requested interval is longer than 31 days".to_owned());
+    }
+    let events: Vec<_> = schedule::phases_between(from, to, coords, thresholds)
+        .into_iter()
+        .filter(|e| {
+            let name: &'static str = (&e.position).into();
+            query
+                .events
+                .as_ref()
+                .map(|wanted| wanted.iter().any(|w| w == name))
+                .unwrap_or(true)
+        })
+        .map(|e| e.to_json())
+        .collect();
+    Ok(serde_json::json!({
+        "from": query.from,
+        "to": query.to,
+        "events": events,
+    }))
+}
+
+/// Topic to answer on, `reply_to` if given, which must be a
+/// `sun/.../response` topic so that clients cannot make the daemon publish
+/// elsewhere.
+pub fn reply_topic(reply_to: Option<String>, default: &str) -> Result<String, String> {
+    let topic = match reply_to {
+        Some(x) => x,
+        None => return Ok(default.to_owned()),
+    };
+    let levels: Vec<_> = topic.split('/').collect();
+    let valid = levels.len() >= 3
+        && levels.first() == Some(&"sun")
+        && levels.last() == Some(&"response")
+        && levels
+            .iter()
+            .all(|x| !x.is_empty() && !x.contains(['+', '#']));
+    if valid {
+        Ok(topic)
+    } else {
+        Err(format!(
+            "`reply_to` {} is not a sun/.../response topic",
+            topic
+        ))
+    }
+}
+
+/// Handles a query payload, returning the topic and payload of the reply.
+pub fn handle(
+    payload: &[u8],
+    coords: &astro::coords::GeographPoint,
+    thresholds: &Thresholds,
+) -> (String, String) {
+    match serde_json::from_slice::<Query>(payload) {
+        Ok(query) => match reply_topic(query.reply_to.clone(), RESPONSE_TOPIC) {
+            Ok(topic) => {
+                let reply = answer(&query, coords, thresholds)
+                    .unwrap_or_else(|e| serde_json::json!({ "error": e }));
+                (topic, reply.to_string())
+            }
+            Err(e) => (
+                RESPONSE_TOPIC.to_owned(),
+                serde_json::json!({ "error": e }).to_string(),
+            ),
+        },
+        Err(e) => (
+            RESPONSE_TOPIC.to_owned(),
+            serde_json::json!({ "error": format!("invalid query: {}", e) }).to_string(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reply_topics() {
+        assert_eq!(reply_topic(None, RESPONSE_TOPIC).unwrap(), RESPONSE_TOPIC);
+        assert_eq!(
+            reply_topic(
+                Some("sun/cmd/query/kitchen/response".to_owned()),
+                RESPONSE_TOPIC
+            )
+            .unwrap(),
+            "sun/cmd/query/kitchen/response"
+        );
+        for topic in [
+            "homeassistant/sensor/sun/config",
+            "sun/response",
+            "sun/cmd/response/x",
+            "sun/+/response",
+            "sun//response",
+        ] {
+            assert!(reply_topic(Some(topic.to_owned()), RESPONSE_TOPIC).is_err());
+        }
+    }
+
+    #[test]
+    fn long_intervals() {
+        let coords = astro::coords::GeographPoint {
+            lat: 44.5,
+            long: 11.3,
+        };
+        let query = |to: &str| {
+            let payload = format!(r#"{{"from": "2021-08-01T00:00:00Z", "to": "{}"}}"#, to);
+            let (topic, reply) = handle(payload.as_bytes(), &coords, &Thresholds::default());
+            assert_eq!(topic, RESPONSE_TOPIC);
+            serde_json::from_str::<serde_json::Value>(&reply).unwrap()
+        };
+        assert!(query("2021-08-31T00:00:00Z")["events"].is_array());
+        assert!(query("2021-09-02T00:00:00Z")["error"].is_string());
+    }
+}
@@ -0,0 +1,208 @@
+//! Interval between iterations of the main loop, which sleeps until the sun
+//! crosses the next phase threshold or the telemetry is due, whichever comes
+//! first. Around noon and midnight, far from every threshold, it idles for up
+//! to `SAMPLING_INTERVAL_MAX`, ten minutes by default.
+
+use crate::phase::Thresholds;
+use crate::schedule;
+use std::time::Duration;
+
+/// Distance from a threshold, in degrees, below which the loop runs at the
+/// shortest interval if no crossing is coming, until a transition held back
+/// by the hysteresis or the dwell time is accepted.
+const NEAR_THRESHOLD: f64 = 1.0;
+/// Seconds to wait past a crossing, so that the sun is beyond the threshold.
+const CROSSING_MARGIN: i64 = 1;
+/// Seconds between the positions sampled to find the next bearing crossing.
+const AZIMUTH_STEP: i64 = 60;
+
+#[derive(Debug)]
+pub struct Sampling {
+    min: Duration,
+    max: Duration,
+    /// Other altitudes and bearings, in degrees, whose crossing is an event
+    altitudes: Vec<f64>,
+    azimuths: Vec<f64>,
+}
+
+impl Sampling {
+    /// Reads the bounds in seconds from `SAMPLING_INTERVAL_MIN` (default 5)
+    /// and `SAMPLING_INTERVAL_MAX` (default 600).
+    pub fn from_env() -> Self {
+        let seconds = |name, default| {
+            Duration::from_secs(
+                std::env::var(name)
+                    .map(|x| x.parse().expect("Invalid sampling interval"))
+                    .unwrap_or(default),
+            )
+        };
+        let sampling = Self {
+            min: seconds("SAMPLING_INTERVAL_MIN", 5),
+            max: seconds("SAMPLING_INTERVAL_MAX", 600),
+            altitudes: Vec::new(),
+            azimuths: Vec::new(),
+        };
+        assert!(
+            !sampling.min.is_zero() && sampling.min <= sampling.max,
+            "The sampling intervals must be positive and increasing"
+        );
+        sampling
+    }
+
+    /// Also wakes up when the sun crosses any of `altitudes` or `azimuths`,
+    /// such as the edges of the elevation bands or the configured bearings.
+    pub fn watch(
+        &mut self,
+        altitudes: impl IntoIterator<Item = f64>,
+        azimuths: impl IntoIterator<Item = f64>,
+    ) {
+        self.altitudes.extend(altitudes);
+        self.azimuths.extend(azimuths);
+    }
+
+    /// Interval until the next iteration at `now`, which is right after the
+    /// next threshold, altitude or bearing crossing, but never later than
+    /// `until` (the earliest other deadline) or `SAMPLING_INTERVAL_MAX`.
+    pub fn interval(
+        &self,
+        now: i64,
+        until: Duration,
+        coords: &astro::coords::GeographPoint,
+        thresholds: &Thresholds,
+    ) -> Duration {
+        let until = until.min(self.max);
+        let horizon = now + until.as_secs() as i64;
+        let crossing = thresholds
+            .crossings()
+            .iter()
+            .map(|(threshold, _, _)| *threshold)
+            .chain(self.altitudes.iter().copied())
+            .filter_map(|threshold| schedule::next_crossing(now, horizon, threshold, coords))
+            .chain(next_bearing(now, horizon, &self.azimuths, coords))
+            .min();
+        if let Some(crossing) = crossing {
+            return Duration::from_secs((crossing - now + CROSSING_MARGIN).max(0) as u64);
+        }
+        let current = crate::solar::pos(now * 1000, coords.lat, coords.long)
+            .altitude
+            .to_degrees();
+        let distance = thresholds
+            .crossings()
+            .iter()
+            .map(|(threshold, _, _)| (threshold - current).abs())
+            .fold(f64::INFINITY, f64::min);
+        if distance < NEAR_THRESHOLD {
+            self.min.min(until)
+        } else {
+            until
+        }
+    }
+}
+
+/// First time in `[from, to]` at which the azimuth of the sun has moved
+/// through any of `bearings`, to within `AZIMUTH_STEP`.
+fn next_bearing(
+    from: i64,
+    to: i64,
+    bearings: &[f64],
+    coords: &astro::coords::GeographPoint,
+) -> Option<i64> {
+    if bearings.is_empty() {
+        return None;
+    }
+    let azimuth = |t: i64| {
+        crate::solar::pos(t * 1000, coords.lat, coords.long)
+            .azimuth
+            .to_degrees()
+    };
+    let mut current = (from, azimuth(from));
+    while current.0 < to {
+        let next_time = (current.0 + AZIMUTH_STEP).min(to);
+        let next = (next_time, azimuth(next_time));
+        if bearings
+            .iter()
+            .any(|&bearing| crate::bearing::passes(current.1, next.1, bearing))
+        {
+            return Some(next_time);
+        }
+        current = next;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2021-03-01 at midnight UTC
+    const DAY: i64 = 1_614_556_800;
+
+    fn bologna() -> astro::coords::GeographPoint {
+        astro::coords::GeographPoint {
+            lat: 44.5,
+            long: 11.3,
+        }
+    }
+
+    fn sampling() -> Sampling {
+        Sampling {
+            min: Duration::from_secs(5),
+            max: Duration::from_secs(600),
+            altitudes: Vec::new(),
+            azimuths: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn idles_far_from_thresholds() {
+        let noon = DAY + 11 * 3600 + 30 * 60;
+        let interval = sampling().interval(noon, Duration::MAX, &bologna(), &Thresholds::default());
+        assert_eq!(interval, Duration::from_secs(600));
+        let interval = sampling().interval(
+            noon,
+            Duration::from_secs(120),
+            &bologna(),
+            &Thresholds::default(),
+        );
+        assert_eq!(interval, Duration::from_secs(120));
+    }
+
+    #[test]
+    fn wakes_up_for_the_next_crossing() {
+        let thresholds = Thresholds::default();
+        let sunrise = schedule::next_crossing(
+            DAY + 4 * 3600,
+            DAY + 8 * 3600,
+            thresholds.horizon,
+            &bologna(),
+        )
+        .unwrap();
+        let interval = sampling().interval(sunrise - 300, Duration::MAX, &bologna(), &thresholds);
+        assert_eq!(interval, Duration::from_secs(300 + CROSSING_MARGIN as u64));
+    }
+
+    #[test]
+    fn wakes_up_for_watched_altitudes_and_bearings() {
+        let mut sampling = sampling();
+        sampling.watch(Some(15.0), None);
+        let thresholds = Thresholds::default();
+        let at =
+            schedule::next_crossing(DAY + 4 * 3600, DAY + 10 * 3600, 15.0, &bologna()).unwrap();
+        let interval = sampling.interval(at - 300, Duration::MAX, &bologna(), &thresholds);
+        assert_eq!(interval, Duration::from_secs(300 + CROSSING_MARGIN as u64));
+
+        let mut sampling = self::sampling();
+        sampling.watch(None, Some(180.0));
+        let transit = crate::ephemeris::transit_on(
+            chrono::NaiveDate::from_ymd_opt(2021, 3, 1).unwrap(),
+            11.3,
+        );
+        let interval = sampling.interval(transit - 300, Duration::MAX, &bologna(), &thresholds);
+        assert!(
+            interval >= Duration::from_secs(300)
+                && interval <= Duration::from_secs((300 + AZIMUTH_STEP + CROSSING_MARGIN) as u64),
+            "{:?}",
+            interval
+        );
+    }
+}
@@ -0,0 +1,385 @@
+//! Computation of the times at which the sun reaches the phase thresholds.
+//!
+//! [`events_between`] follows the usual astronomical definitions: dawns and
+//! dusks are the instants the sun crosses the twilight thresholds (by default
+//! -18°, -12° and -6°), sunrise and sunset are the horizon crossings and solar
+//! noon is the highest point of the day.
+//!
+//! [`phases_between`] names the same instants after the phases the `sun`
+//! topic enters at each, following [`Thresholds::classify`]: the afternoon is
+//! already `sunset`, so that going below the horizon enters `civilDusk` and
+//! going below the astronomical threshold enters `night`. The schedules
+//! published by the daemon use these.
+
+use crate::phase::{SunPosition, Thresholds};
+use chrono::TimeZone;
+
+const SAMPLE_STEP: i64 = 10 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Event {
+    pub position: SunPosition,
+    /// Unix timestamp in seconds
+    pub timestamp: i64,
+}
+
+impl Event {
+    pub fn to_json(self) -> serde_json::Value {
+        let name: &'static str = (&self.position).into();
+        serde_json::json!({
+            "event": name,
+            "timestamp": self.timestamp,
+        })
+    }
+}
+
+fn altitude(timestamp: i64, coords: &astro::coords::GeographPoint) -> f64 {
+    crate::solar::pos(timestamp * 1000, coords.lat, coords.long)
+        .altitude
+        .to_degrees()
+}
+
+/// Whether the sun is rising at `timestamp`, from the change of its altitude
+/// over the surrounding minute, so that the morning is told from the evening
+/// whatever the time zone and the latitude.
+pub fn is_rising(timestamp: i64, coords: &astro::coords::GeographPoint) -> bool {
+    altitude(timestamp + 30, coords) > altitude(timestamp - 30, coords)
+}
+
+/// Bisects the instant in `[from, to]` at which the altitude crosses
+/// `threshold`, knowing it is on opposite sides at the two ends.
+fn refine_crossing(
+    mut from: i64,
+    mut to: i64,
+    threshold: f64,
+    coords: &astro::coords::GeographPoint,
+) -> i64 {
+    let rising = altitude(from, coords) < threshold;
+    while to - from > 1 {
+        let mid = (from + to) / 2;
+        if (altitude(mid, coords) < threshold) == rising {
+            from = mid;
+        } else {
+            to = mid;
+        }
+    }
+    to
+}
+
+/// Ternary search for the upper culmination in `[from, to]`, or the lower
+/// one unless `upper`.
+fn refine_culmination(
+    mut from: i64,
+    mut to: i64,
+    upper: bool,
+    coords: &astro::coords::GeographPoint,
+) -> i64 {
+    while to - from > 2 {
+        let a = from + (to - from) / 3;
+        let b = to - (to - from) / 3;
+        if (altitude(a, coords) < altitude(b, coords)) == upper {
+            from = a;
+        } else {
+            to = b;
+        }
+    }
+    (from + to) / 2
+}
+
+/// Returns the events happening in `[from, to)`, sorted by time.
+pub fn events_between(
+    from: i64,
+    to: i64,
+    coords: &astro::coords::GeographPoint,
+    thresholds: &Thresholds,
+) -> Vec<Event> {
+    let mut events = Vec::new();
+    // Start one step early so that a culmination right at `from` is seen
+    let mut previous = (from - SAMPLE_STEP, altitude(from - SAMPLE_STEP, coords));
+    let mut current = (from, altitude(from, coords));
+    while current.0 < to {
+        let next_time = (current.0 + SAMPLE_STEP).min(to);
+        let next = (next_time, altitude(next_time, coords));
+        for (threshold, rising_event, setting_event) in thresholds.crossings().iter() {
+            let position = if current.1 < *threshold && next.1 >= *threshold {
+                *rising_event
+            } else if current.1 >= *threshold && next.1 < *threshold {
+                *setting_event
+            } else {
+                continue;
+            };
+            events.push(Event {
+                position,
+                timestamp: refine_crossing(current.0, next.0, *threshold, coords),
+            });
+        }
+        if previous.1 < current.1 && current.1 >= next.1 {
+            let timestamp = refine_culmination(previous.0, next.0, true, coords);
+            if timestamp >= from && timestamp < to {
+                events.push(Event {
+                    position: SunPosition::SolarNoon,
+                    timestamp,
+                });
+            }
+        }
+        previous = current;
+        current = next;
+    }
+    events.sort_by_key(|e| e.timestamp);
+    events.dedup();
+    events
+}
+
+/// Returns the phases entered in `[from, to)`, sorted by time, named as on
+/// the `sun` topic. Solar noon is kept, followed by the phase the sun enters
+/// as it starts setting, and the lower culmination enters the phase of the
+/// rising sun.
+pub fn phases_between(
+    from: i64,
+    to: i64,
+    coords: &astro::coords::GeographPoint,
+    thresholds: &Thresholds,
+) -> Vec<Event> {
+    let classify = |timestamp, is_morning| {
+        thresholds.classify(altitude(timestamp, coords).to_radians(), is_morning)
+    };
+    let mut phases = Vec::new();
+    for event in events_between(from, to, coords, thresholds) {
+        let timestamp = event.timestamp;
+        if event.position == SunPosition::SolarNoon {
+            phases.push(event);
+            let position = classify(timestamp, false);
+            if position != classify(timestamp, true) {
+                phases.push(Event {
+                    position,
+                    timestamp,
+                });
+            }
+        } else {
+            let rising = thresholds
+                .crossings()
+                .iter()
+                .any(|(_, dawn, _)| *dawn == event.position);
+            phases.push(Event {
+                position: classify(timestamp, rising),
+                timestamp,
+            });
+        }
+    }
+    let mut previous = (from - SAMPLE_STEP, altitude(from - SAMPLE_STEP, coords));
+    let mut current = (from, altitude(from, coords));
+    while current.0 < to {
+        let next_time = (current.0 + SAMPLE_STEP).min(to);
+        let next = (next_time, altitude(next_time, coords));
+        if previous.1 > current.1 && current.1 <= next.1 {
+            let timestamp = refine_culmination(previous.0, next.0, false, coords);
+            let position = classify(timestamp, true);
+            if timestamp >= from && timestamp < to && position != classify(timestamp, false) {
+                phases.push(Event {
+                    position,
+                    timestamp,
+                });
+            }
+        }
+        previous = current;
+        current = next;
+    }
+    // Stable, so that solar noon stays before the phase entered with it
+    phases.sort_by_key(|e| e.timestamp);
+    phases.dedup();
+    phases
+}
+
+/// Returns the first instant in `[from, to)` at which the altitude crosses
+/// `threshold` in either direction.
+pub fn next_crossing(
+    from: i64,
+    to: i64,
+    threshold: f64,
+    coords: &astro::coords::GeographPoint,
+) -> Option<i64> {
+    let mut current = (from, altitude(from, coords) < threshold);
+    while current.0 < to {
+        let next_time = (current.0 + SAMPLE_STEP).min(to);
+        let next = (next_time, altitude(next_time, coords) < threshold);
+        if next.1 != current.1 {
+            return Some(refine_crossing(current.0, next.0, threshold, coords));
+        }
+        current = next;
+    }
+    None
+}
+
+/// Returns the next `count` phases after `from`, looking at most two days
+/// ahead.
+pub fn upcoming(
+    from: i64,
+    count: usize,
+    coords: &astro::coords::GeographPoint,
+    thresholds: &Thresholds,
+) -> Vec<Event> {
+    let mut events = phases_between(from, from + 2 * 24 * 3600, coords, thresholds);
+    events.truncate(count);
+    events
+}
+
+/// Unix timestamp of the local midnight starting `date`.
+pub fn local_midnight(date: chrono::NaiveDate) -> i64 {
+    let midnight = date.and_hms(0, 0, 0);
+    chrono::Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .map(|x| x.timestamp())
+        // Midnight skipped by a clock change
+        .unwrap_or_else(|| chrono::Local.from_utc_datetime(&midnight).timestamp())
+}
+
+/// Times at which the phases begin on the local day `date`, as Unix
+/// timestamps under the name of each phase, `null` for those that do not
+/// begin that day.
+pub fn day(
+    date: chrono::NaiveDate,
+    coords: &astro::coords::GeographPoint,
+    thresholds: &Thresholds,
+) -> serde_json::Value {
+    let start = local_midnight(date);
+    let end = local_midnight(date.succ());
+    let events = phases_between(start, end, coords, thresholds);
+    let mut day = serde_json::json!({ "date": date.to_string() });
+    for position in SunPosition::ALL.iter() {
+        let name: &'static str = position.into();
+        day[name] = events
+            .iter()
+            .find(|e| e.position == *position)
+            .map(|e| e.timestamp)
+            .into();
+    }
+    day
+}
+
+/// Seconds the sun spends above the horizon on the local day `date`, adding
+/// up the stretches between each sunrise and the following sunset.
+pub fn daylight(
+    date: chrono::NaiveDate,
+    coords: &astro::coords::GeographPoint,
+    thresholds: &Thresholds,
+) -> i64 {
+    let start = local_midnight(date);
+    let end = local_midnight(date.succ());
+    let mut risen = (altitude(start, coords) >= thresholds.horizon).then_some(start);
+    let mut daylight = 0;
+    for event in events_between(start, end, coords, thresholds) {
+        match event.position {
+            SunPosition::Sunrise => risen = Some(event.timestamp),
+            SunPosition::Sunset => {
+                if let Some(risen) = risen.take() {
+                    daylight += event.timestamp - risen;
+                }
+            }
+            _ => (),
+        }
+    }
+    daylight + risen.map(|x| end - x).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Phases published by the live loop sampling every `step` seconds,
+    /// without hysteresis, leaving solar noon to its own timer.
+    fn live(
+        from: i64,
+        to: i64,
+        step: usize,
+        coords: &astro::coords::GeographPoint,
+        thresholds: &Thresholds,
+    ) -> Vec<Event> {
+        let mut phases = Vec::new();
+        let mut current = None;
+        for timestamp in (from..to).step_by(step) {
+            let position = thresholds.classify(
+                altitude(timestamp, coords).to_radians(),
+                is_rising(timestamp, coords),
+            );
+            if current.is_some() && current != Some(position) {
+                phases.push(Event {
+                    position,
+                    timestamp,
+                });
+            }
+            current = Some(position);
+        }
+        phases
+    }
+
+    fn assert_matches_live(lat: f64, long: f64, from: i64) {
+        let coords = astro::coords::GeographPoint { lat, long };
+        let thresholds = Thresholds::default();
+        let to = from + 24 * 3600;
+        let step = 30;
+        let live = live(from, to, step, &coords, &thresholds);
+        let scheduled: Vec<_> = phases_between(from + step as i64, to, &coords, &thresholds)
+            .into_iter()
+            .filter(|e| e.position != SunPosition::SolarNoon)
+            .collect();
+        assert!(!live.is_empty());
+        assert_eq!(
+            live.iter().map(|e| e.position).collect::<Vec<_>>(),
+            scheduled.iter().map(|e| e.position).collect::<Vec<_>>()
+        );
+        for (live, scheduled) in live.iter().zip(&scheduled) {
+            // The live loop is late by up to a step, and tells the rising sun
+            // from the setting one over a minute
+            assert!(
+                (live.timestamp - scheduled.timestamp).abs() <= 2 * step as i64,
+                "{:?} at {} instead of {}",
+                live.position,
+                scheduled.timestamp,
+                live.timestamp
+            );
+        }
+    }
+
+    #[test]
+    fn phases_match_the_live_topic() {
+        // Bologna on 2021-03-01
+        assert_matches_live(44.5, 11.3, 1_614_556_800);
+    }
+
+    #[test]
+    fn phases_match_the_live_topic_under_the_midnight_sun() {
+        // Svalbard on 2021-06-21
+        assert_matches_live(78.2, 15.6, 1_624_233_600);
+    }
+
+    #[test]
+    fn dusk_names() {
+        let coords = astro::coords::GeographPoint {
+            lat: 44.5,
+            long: 11.3,
+        };
+        let thresholds = Thresholds::default();
+        let from = 1_614_556_800;
+        let crossings = events_between(from, from + 24 * 3600, &coords, &thresholds);
+        let phases = phases_between(from, from + 24 * 3600, &coords, &thresholds);
+        let at = |events: &[Event], position| {
+            events
+                .iter()
+                .find(|e| e.position == position)
+                .map(|e| e.timestamp)
+        };
+        assert_eq!(
+            at(&phases, SunPosition::CivilDusk),
+            at(&crossings, SunPosition::Sunset)
+        );
+        assert_eq!(
+            at(&phases, SunPosition::Night),
+            at(&crossings, SunPosition::AstronomicalDusk)
+        );
+        assert_eq!(
+            at(&phases, SunPosition::Sunset),
+            at(&crossings, SunPosition::SolarNoon)
+        );
+    }
+}
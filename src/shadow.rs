@@ -0,0 +1,41 @@
+//! Length and direction of the shadow of an object `SHADOW_OBJECT_HEIGHT`
+//! metres tall standing on level ground, on `sun/shadow`.
+
+pub const TOPIC: &str = "sun/shadow";
+
+#[derive(Debug)]
+pub struct Shadow {
+    /// Height of the object in metres
+    height: f64,
+}
+
+impl Shadow {
+    pub fn from_env() -> Option<Self> {
+        let height: f64 = std::env::var("SHADOW_OBJECT_HEIGHT")
+            .ok()?
+            .parse()
+            .expect("Invalid SHADOW_OBJECT_HEIGHT");
+        assert!(height > 0.0, "SHADOW_OBJECT_HEIGHT must be positive");
+        Some(Self { height })
+    }
+
+    /// Message of `sun/shadow` for the sun at `azimuth` and `altitude`
+    /// degrees, with the length in metres and the direction the shadow
+    /// points to, clockwise from north in degrees, both `null` while the
+    /// sun is below the horizon and casts no shadow.
+    pub fn message(&self, azimuth: f64, altitude: f64) -> serde_json::Value {
+        if altitude <= 0.0 {
+            return serde_json::json!({
+                "height": self.height,
+                "length": null,
+                "direction": null,
+            });
+        }
+        let length = self.height / altitude.to_radians().tan();
+        serde_json::json!({
+            "height": self.height,
+            "length": (length * 100.0).round() / 100.0,
+            "direction": ((azimuth + 180.0).rem_euclid(360.0) * 10.0).round() / 10.0,
+        })
+    }
+}
@@ -0,0 +1,141 @@
+use super::{list_from_env, render, selected, Event, Sink};
+use crate::phase::Thresholds;
+use crate::schedule;
+use chrono::TimeZone;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+
+/// Sends an email for the selected events and, optionally, a daily digest
+/// with the day's schedule.
+pub struct Email {
+    transport: SmtpTransport,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    events: Option<Vec<String>>,
+    subject: String,
+    body: String,
+    /// Local time of the daily digest
+    digest_at: Option<chrono::NaiveTime>,
+    last_digest: Option<chrono::NaiveDate>,
+    coords: astro::coords::GeographPoint,
+    thresholds: Thresholds,
+}
+
+impl Email {
+    /// Reads `SMTP_SERVER` and the comma separated `SMTP_TO` recipients,
+    /// with the optional `SMTP_PORT`, `SMTP_SECURITY` (`starttls`, the
+    /// default, `tls` or `none`), `SMTP_USERNAME`, `SMTP_PASSWORD`,
+    /// `SMTP_FROM`, the comma separated `SMTP_EVENTS` (all by default),
+    /// `SMTP_SUBJECT` and `SMTP_BODY` templates and `SMTP_DIGEST_TIME`
+    /// (e.g. `06:00`).
+    pub fn from_env(
+        coords: &astro::coords::GeographPoint,
+        thresholds: &Thresholds,
+    ) -> Option<Self> {
+        let server = std::env::var("SMTP_SERVER").ok()?;
+        let mut builder = match std::env::var("SMTP_SECURITY").as_deref() {
+            Ok("tls") => SmtpTransport::relay(&server),
+            Ok("none") => Ok(SmtpTransport::builder_dangerous(&server)),
+            Ok("starttls") | Err(_) => SmtpTransport::starttls_relay(&server),
+            Ok(x) => panic!("Invalid SMTP security `{}`", x),
+        }
+        .expect("Invalid SMTP server");
+        if let Ok(port) = std::env::var("SMTP_PORT") {
+            builder = builder.port(port.parse().expect("Invalid SMTP port"));
+        }
+        if let Ok(username) = std::env::var("SMTP_USERNAME") {
+            builder = builder.credentials(Credentials::new(
+                username,
+                std::env::var("SMTP_PASSWORD").unwrap_or_default(),
+            ));
+        }
+        Some(Self {
+            transport: builder.build(),
+            from: std::env::var("SMTP_FROM")
+                .unwrap_or_else(|_| "mqtt_sun <mqtt_sun@localhost>".to_owned())
+                .parse()
+                .expect("Invalid SMTP sender"),
+            to: list_from_env("SMTP_TO")
+                .expect("Missing SMTP recipients")
+                .iter()
+                .map(|x| x.parse().expect("Invalid SMTP recipient"))
+                .collect(),
+            events: list_from_env("SMTP_EVENTS"),
+            subject: std::env::var("SMTP_SUBJECT")
+                .unwrap_or_else(|_| "Sun event: {event}".to_owned()),
+            body: std::env::var("SMTP_BODY").unwrap_or_else(|_| "{event} at {time}".to_owned()),
+            digest_at: std::env::var("SMTP_DIGEST_TIME").ok().map(|x| {
+                chrono::NaiveTime::parse_from_str(&x, "%H:%M").expect("Invalid SMTP digest time")
+            }),
+            last_digest: None,
+            coords: astro::coords::GeographPoint {
+                long: coords.long,
+                lat: coords.lat,
+            },
+            thresholds: *thresholds,
+        })
+    }
+
+    fn mail(&self, subject: String, body: String) -> Result<(), String> {
+        let mut message = Message::builder().from(self.from.clone());
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+        let message = message
+            .subject(subject)
+            .body(body)
+            .map_err(|e| e.to_string())?;
+        self.transport
+            .send(&message)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl Sink for Email {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    fn send(&mut self, event: &Event) -> Result<(), String> {
+        if !selected(&self.events, event) {
+            return Ok(());
+        }
+        self.mail(render(&self.subject, event), render(&self.body, event))
+    }
+
+    fn poll(&mut self) -> Result<(), String> {
+        let digest_at = match self.digest_at {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let now = chrono::Local::now();
+        let today = now.date().naive_local();
+        if now.time() < digest_at || self.last_digest == Some(today) {
+            return Ok(());
+        }
+        self.last_digest = Some(today);
+        let body: Vec<_> = schedule::phases_between(
+            schedule::local_midnight(today),
+            schedule::local_midnight(today.succ()),
+            &self.coords,
+            &self.thresholds,
+        )
+        .iter()
+        .map(|e| {
+            let name: &'static str = (&e.position).into();
+            format!(
+                "{} {}",
+                chrono::Local.timestamp(e.timestamp, 0).format("%H:%M"),
+                name
+            )
+        })
+        .collect();
+        self.mail(format!("Sun schedule for {}", today), body.join("\n"))
+    }
+
+    fn relocate(&mut self, coords: astro::coords::GeographPoint) {
+        self.coords = coords;
+    }
+}
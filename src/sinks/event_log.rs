@@ -0,0 +1,51 @@
+use super::{Event, Sink};
+use crate::event_log::{self, Database};
+
+/// Records every event, and a sample of the sun position every time it is
+/// polled, in the SQLite event log.
+pub struct EventLog {
+    database: Database,
+    coords: astro::coords::GeographPoint,
+}
+
+impl EventLog {
+    pub fn from_env(coords: &astro::coords::GeographPoint) -> Option<Self> {
+        let path = event_log::path_from_env()?;
+        Some(Self {
+            database: Database::open(&path)
+                .unwrap_or_else(|e| panic!("Could not open the event log {}: {}", path, e)),
+            coords: astro::coords::GeographPoint {
+                long: coords.long,
+                lat: coords.lat,
+            },
+        })
+    }
+}
+
+impl Sink for EventLog {
+    fn name(&self) -> &'static str {
+        "event log"
+    }
+
+    fn send(&mut self, event: &Event) -> Result<(), String> {
+        self.database
+            .add_event(event.timestamp, &event.name)
+            .map_err(|e| e.to_string())
+    }
+
+    fn poll(&mut self) -> Result<(), String> {
+        let now = chrono::Utc::now().timestamp();
+        let position = crate::solar::pos(now * 1000, self.coords.lat, self.coords.long);
+        self.database
+            .add_sample(
+                now,
+                position.altitude.to_degrees(),
+                position.azimuth.to_degrees(),
+            )
+            .map_err(|e| e.to_string())
+    }
+
+    fn relocate(&mut self, coords: astro::coords::GeographPoint) {
+        self.coords = coords;
+    }
+}
@@ -0,0 +1,56 @@
+use super::{http_error, Event, Sink};
+
+/// Creates a Grafana annotation for every event through the HTTP API.
+pub struct Grafana {
+    url: String,
+    token: Option<String>,
+    tags: Vec<String>,
+    dashboard_uid: Option<String>,
+}
+
+impl Grafana {
+    /// Reads `GRAFANA_URL` (e.g. `http://grafana:3000`), with the optional
+    /// `GRAFANA_TOKEN`, comma separated `GRAFANA_TAGS` and
+    /// `GRAFANA_DASHBOARD_UID`.
+    pub fn from_env() -> Option<Self> {
+        let url = std::env::var("GRAFANA_URL").ok()?;
+        Some(Self {
+            url: format!("{}/api/annotations", url.trim_end_matches('/')),
+            token: std::env::var("GRAFANA_TOKEN").ok(),
+            tags: std::env::var("GRAFANA_TAGS")
+                .unwrap_or_else(|_| "sun".to_owned())
+                .split(',')
+                .map(|x| x.trim().to_owned())
+                .filter(|x| !x.is_empty())
+                .collect(),
+            dashboard_uid: std::env::var("GRAFANA_DASHBOARD_UID").ok(),
+        })
+    }
+}
+
+impl Sink for Grafana {
+    fn name(&self) -> &'static str {
+        "Grafana"
+    }
+
+    fn send(&mut self, event: &Event) -> Result<(), String> {
+        let mut tags = self.tags.clone();
+        tags.push(event.name.clone());
+        let mut annotation = serde_json::json!({
+            "time": event.timestamp * 1000,
+            "tags": tags,
+            "text": format!("Sun event: {}", event.name),
+        });
+        if let Some(uid) = &self.dashboard_uid {
+            annotation["dashboardUID"] = uid.as_str().into();
+        }
+        let mut request = ureq::post(&self.url).set("Content-Type", "application/json");
+        if let Some(token) = &self.token {
+            request = request.set("Authorization", &format!("Bearer {}", token));
+        }
+        request
+            .send_string(&annotation.to_string())
+            .map(|_| ())
+            .map_err(http_error)
+    }
+}
@@ -0,0 +1,262 @@
+use super::{Event, Sink};
+use crate::clear_sky;
+use crate::phase::Thresholds;
+use log::info;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+/// Message types of the knxd client protocol.
+const EIB_OPEN_GROUPCON: u16 = 0x0026;
+const EIB_GROUP_PACKET: u16 = 0x0027;
+/// Relative brightness change for which a new value is written right away.
+const BRIGHTNESS_CHANGE: f64 = 0.05;
+
+/// Writes sun events, day/night (DPT 1.024) and the clear-sky brightness
+/// (DPT 9.004) to KNX group addresses through knxd.
+pub struct Knx {
+    gateway: String,
+    connection: Option<TcpStream>,
+    events: Vec<(String, u16)>,
+    day_night: Option<u16>,
+    brightness: Option<u16>,
+    brightness_interval: Duration,
+    /// When the brightness was last written, and its value
+    last_brightness: Option<(Instant, f64)>,
+    is_night: Option<bool>,
+    coords: astro::coords::GeographPoint,
+    thresholds: Thresholds,
+}
+
+/// Parses a three level (`1/2/3`) or two level (`1/234`) group address.
+fn parse_group_address(address: &str) -> Result<u16, String> {
+    let parts: Vec<u16> = address
+        .trim()
+        .split('/')
+        .map(|x| {
+            x.parse()
+                .map_err(|_| format!("invalid group address `{}`", address))
+        })
+        .collect::<Result<_, _>>()?;
+    match parts[..] {
+        [main, middle, sub] if main < 32 && middle < 8 && sub < 256 => {
+            Ok(main << 11 | middle << 8 | sub)
+        }
+        [main, sub] if main < 32 && sub < 2048 => Ok(main << 11 | sub),
+        _ => Err(format!("invalid group address `{}`", address)),
+    }
+}
+
+fn group_address_from_env(name: &str) -> Option<u16> {
+    std::env::var(name)
+        .ok()
+        .map(|x| parse_group_address(&x).unwrap_or_else(|e| panic!("Invalid {}: {}", name, e)))
+}
+
+/// Encodes `value` as a KNX 2-byte float (DPT 9).
+fn dpt9(value: f64) -> [u8; 2] {
+    let mut mantissa = (value * 100.0).round();
+    let mut exponent = 0;
+    while !(-2048.0..=2047.0).contains(&mantissa) && exponent < 15 {
+        mantissa = (mantissa / 2.0).round();
+        exponent += 1;
+    }
+    let mantissa = (mantissa.clamp(-2048.0, 2047.0) as i16) as u16 & 0x0FFF;
+    let sign = if mantissa & 0x0800 != 0 { 0x80 } else { 0 };
+    [
+        sign | exponent << 3 | (mantissa >> 8) as u8 & 0x07,
+        mantissa as u8,
+    ]
+}
+
+/// Frames a knxd message of type `kind`.
+fn message(kind: u16, data: &[u8]) -> Vec<u8> {
+    let mut message = ((data.len() + 2) as u16).to_be_bytes().to_vec();
+    message.extend_from_slice(&kind.to_be_bytes());
+    message.extend_from_slice(data);
+    message
+}
+
+impl Knx {
+    /// Reads the knxd address from `KNXD_ADDRESS` (e.g. `localhost:6720`),
+    /// with the optional `KNX_DAY_NIGHT_ADDRESS` and
+    /// `KNX_BRIGHTNESS_ADDRESS` group addresses and `KNX_EVENTS`, a comma
+    /// separated list of `event=group address` pairs written with 1 when the
+    /// event happens. The brightness is written when it changes by more than
+    /// 5%, and otherwise every `KNX_BRIGHTNESS_INTERVAL` seconds (default
+    /// 600).
+    pub fn from_env(
+        coords: &astro::coords::GeographPoint,
+        thresholds: &Thresholds,
+    ) -> Option<Self> {
+        let gateway = std::env::var("KNXD_ADDRESS").ok()?;
+        let events = std::env::var("KNX_EVENTS")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|x| !x.trim().is_empty())
+            .map(|x| {
+                let (event, address) = x.split_once('=').expect("Invalid KNX event mapping");
+                (
+                    event.trim().to_owned(),
+                    parse_group_address(address).expect("Invalid KNX event group address"),
+                )
+            })
+            .collect();
+        let brightness_interval = Duration::from_secs(
+            std::env::var("KNX_BRIGHTNESS_INTERVAL")
+                .map(|x| x.parse().expect("Invalid KNX brightness interval"))
+                .unwrap_or(600),
+        );
+        Some(Self {
+            gateway,
+            connection: None,
+            events,
+            day_night: group_address_from_env("KNX_DAY_NIGHT_ADDRESS"),
+            brightness: group_address_from_env("KNX_BRIGHTNESS_ADDRESS"),
+            brightness_interval,
+            last_brightness: None,
+            is_night: None,
+            coords: astro::coords::GeographPoint {
+                long: coords.long,
+                lat: coords.lat,
+            },
+            thresholds: *thresholds,
+        })
+    }
+
+    fn connect(&self) -> std::io::Result<TcpStream> {
+        let mut stream = TcpStream::connect(&self.gateway)?;
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
+        // Write only group socket, so that knxd does not send us the bus traffic
+        stream.write_all(&message(EIB_OPEN_GROUPCON, &[0, 0, 0xFF]))?;
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply)?;
+        if u16::from_be_bytes([reply[2], reply[3]]) != EIB_OPEN_GROUPCON {
+            return Err(std::io::Error::other("knxd refused the group connection"));
+        }
+        info!("Connected to knxd at {}", self.gateway);
+        Ok(stream)
+    }
+
+    /// Sends a group write of `data` (DPT 1 values fit in the APCI) to
+    /// `address`, reconnecting if needed.
+    fn write(&mut self, address: u16, data: &[u8]) -> Result<(), String> {
+        let mut apdu = vec![0x00, 0x80];
+        match *data {
+            [small] if small < 0x40 => apdu[1] |= small,
+            _ => apdu.extend_from_slice(data),
+        }
+        let mut packet = address.to_be_bytes().to_vec();
+        packet.extend_from_slice(&apdu);
+        if self.connection.is_none() {
+            self.connection = Some(self.connect().map_err(|e| e.to_string())?);
+        }
+        let result = self
+            .connection
+            .as_mut()
+            .unwrap()
+            .write_all(&message(EIB_GROUP_PACKET, &packet));
+        if result.is_err() {
+            self.connection = None;
+        }
+        result.map_err(|e| e.to_string())
+    }
+}
+
+impl Sink for Knx {
+    fn name(&self) -> &'static str {
+        "KNX"
+    }
+
+    fn send(&mut self, event: &Event) -> Result<(), String> {
+        let addresses: Vec<u16> = self
+            .events
+            .iter()
+            .filter(|(name, _)| *name == event.name)
+            .map(|(_, address)| *address)
+            .collect();
+        for address in addresses {
+            self.write(address, &[1])?;
+        }
+        Ok(())
+    }
+
+    fn poll(&mut self) -> Result<(), String> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let altitude = crate::solar::pos(now, self.coords.lat, self.coords.long)
+            .altitude
+            .to_degrees();
+        if let Some(address) = self.day_night {
+            let is_night = altitude < self.thresholds.horizon;
+            if self.is_night != Some(is_night) {
+                self.write(address, &[is_night as u8])?;
+                self.is_night = Some(is_night);
+            }
+        }
+        if let Some(address) = self.brightness {
+            let brightness = clear_sky::illuminance(altitude);
+            let due = match self.last_brightness {
+                Some((at, last)) => {
+                    at.elapsed() >= self.brightness_interval
+                        || ((brightness - last).abs() > last * BRIGHTNESS_CHANGE
+                            && dpt9(brightness) != dpt9(last))
+                }
+                None => true,
+            };
+            if due {
+                self.write(address, &dpt9(brightness))?;
+                self.last_brightness = Some((Instant::now(), brightness));
+            }
+        }
+        Ok(())
+    }
+
+    fn relocate(&mut self, coords: astro::coords::GeographPoint) {
+        self.coords = coords;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes a KNX 2-byte float.
+    fn decode(bytes: [u8; 2]) -> f64 {
+        let raw = u16::from_be_bytes(bytes);
+        let exponent = (raw >> 11) & 0x0F;
+        let mantissa = ((raw & 0x07FF) as i32) - if raw & 0x8000 != 0 { 2048 } else { 0 };
+        0.01 * mantissa as f64 * 2f64.powi(exponent as i32)
+    }
+
+    #[test]
+    fn dpt9_values() {
+        assert_eq!(dpt9(0.0), [0x00, 0x00]);
+        assert_eq!(dpt9(0.01), [0x00, 0x01]);
+        assert_eq!(dpt9(1.0), [0x00, 0x64]);
+        assert_eq!(dpt9(-1.0), [0x87, 0x9C]);
+        assert_eq!(dpt9(20.48), [0x0C, 0x00]);
+        assert_eq!(dpt9(-671088.64), [0xF8, 0x00]);
+        for lux in [0.5, 400.0, 10_000.0, 120_000.0] {
+            let decoded = decode(dpt9(lux));
+            assert!(
+                (decoded - lux).abs() <= lux * 0.001,
+                "{} read as {}",
+                lux,
+                decoded
+            );
+        }
+    }
+
+    #[test]
+    fn group_addresses() {
+        assert_eq!(parse_group_address("1/2/3"), Ok(0x0A03));
+        assert_eq!(parse_group_address(" 31/7/255 "), Ok(0xFFFF));
+        assert_eq!(parse_group_address("1/234"), Ok(0x08EA));
+        assert_eq!(parse_group_address("0/0/0"), Ok(0));
+        for invalid in [
+            "", "1", "32/0/0", "1/8/0", "1/2/256", "1/2048", "a/b/c", "1/2/3/4",
+        ] {
+            assert!(parse_group_address(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
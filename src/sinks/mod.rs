@@ -0,0 +1,144 @@
+//! Optional destinations, besides the MQTT broker, that sun events are
+//! forwarded to.
+//!
+//! Every sink runs on its own thread, so that a slow or unreachable service
+//! never delays event detection.
+
+use crate::phase::Thresholds;
+use chrono::TimeZone;
+use log::error;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+mod email;
+mod event_log;
+mod grafana;
+mod knx;
+mod ntfy;
+mod telegram;
+
+/// How often sinks get to do periodic work between events.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub name: String,
+    /// Unix timestamp in seconds
+    pub timestamp: i64,
+}
+
+pub trait Sink: Send {
+    fn name(&self) -> &'static str;
+    fn send(&mut self, event: &Event) -> Result<(), String>;
+    /// Called periodically, for sinks that report on a schedule.
+    fn poll(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+    /// Called when the observer moves, for sinks that compute the position of
+    /// the sun themselves.
+    fn relocate(&mut self, _coords: astro::coords::GeographPoint) {}
+}
+
+enum Message {
+    Event(Event),
+    Relocate(astro::coords::GeographPoint),
+}
+
+/// Reads a comma separated list from the environment variable `name`.
+fn list_from_env(name: &str) -> Option<Vec<String>> {
+    std::env::var(name).ok().map(|x| {
+        x.split(',')
+            .map(|x| x.trim().to_owned())
+            .filter(|x| !x.is_empty())
+            .collect()
+    })
+}
+
+/// Whether `event` is among `events`, with `None` selecting every event.
+fn selected(events: &Option<Vec<String>>, event: &Event) -> bool {
+    events
+        .as_ref()
+        .map(|events| events.contains(&event.name))
+        .unwrap_or(true)
+}
+
+/// Replaces `{event}` and `{time}` (local RFC 3339) in `template`.
+fn render(template: &str, event: &Event) -> String {
+    template.replace("{event}", &event.name).replace(
+        "{time}",
+        &chrono::Local.timestamp(event.timestamp, 0).to_rfc3339(),
+    )
+}
+
+/// Describes a failed HTTP request by its status or the kind of transport
+/// error only, as the URL it names may hold a secret such as a bot token.
+fn http_error(error: ureq::Error) -> String {
+    match error {
+        ureq::Error::Status(status, _) => format!("HTTP status {}", status),
+        ureq::Error::Transport(transport) => transport.kind().to_string(),
+    }
+}
+
+#[derive(Default)]
+pub struct Sinks {
+    senders: Vec<Sender<Message>>,
+}
+
+impl Sinks {
+    /// Starts every sink configured in the environment.
+    pub fn from_env(coords: &astro::coords::GeographPoint, thresholds: &Thresholds) -> Self {
+        let mut sinks = Self::default();
+        if let Some(email) = email::Email::from_env(coords, thresholds) {
+            sinks.spawn(email);
+        }
+        if let Some(event_log) = event_log::EventLog::from_env(coords) {
+            sinks.spawn(event_log);
+        }
+        if let Some(knx) = knx::Knx::from_env(coords, thresholds) {
+            sinks.spawn(knx);
+        }
+        if let Some(ntfy) = ntfy::Ntfy::from_env() {
+            sinks.spawn(ntfy);
+        }
+        if let Some(telegram) = telegram::Telegram::from_env() {
+            sinks.spawn(telegram);
+        }
+        if let Some(grafana) = grafana::Grafana::from_env() {
+            sinks.spawn(grafana);
+        }
+        sinks
+    }
+
+    fn spawn<S: Sink + 'static>(&mut self, mut sink: S) {
+        let (tx, rx) = channel::<Message>();
+        std::thread::spawn(move || loop {
+            match rx.recv_timeout(POLL_INTERVAL) {
+                Ok(Message::Relocate(coords)) => sink.relocate(coords),
+                Ok(Message::Event(event)) => sink.send(&event).unwrap_or_else(|e| {
+                    error!("Could not send {} to {}: {}", event.name, sink.name(), e)
+                }),
+                Err(RecvTimeoutError::Timeout) => sink
+                    .poll()
+                    .unwrap_or_else(|e| error!("{} failed: {}", sink.name(), e)),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        });
+        self.senders.push(tx);
+    }
+
+    pub fn notify(&self, event: Event) {
+        for sender in &self.senders {
+            // A sink thread only stops if it panicked, which has been logged
+            let _ = sender.send(Message::Event(event.clone()));
+        }
+    }
+
+    pub fn relocate(&self, coords: &astro::coords::GeographPoint) {
+        for sender in &self.senders {
+            let _ = sender.send(Message::Relocate(astro::coords::GeographPoint {
+                long: coords.long,
+                lat: coords.lat,
+            }));
+        }
+    }
+}
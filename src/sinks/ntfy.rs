@@ -0,0 +1,46 @@
+use super::{http_error, list_from_env, render, selected, Event, Sink};
+
+/// Pushes the selected events to an ntfy topic.
+pub struct Ntfy {
+    url: String,
+    token: Option<String>,
+    events: Option<Vec<String>>,
+    title: String,
+    message: String,
+}
+
+impl Ntfy {
+    /// Reads the topic URL from `NTFY_URL` (e.g. `https://ntfy.sh/my-sun`),
+    /// with the optional `NTFY_TOKEN`, comma separated `NTFY_EVENTS` (all by
+    /// default) and `NTFY_TITLE` and `NTFY_MESSAGE` templates.
+    pub fn from_env() -> Option<Self> {
+        Some(Self {
+            url: std::env::var("NTFY_URL").ok()?,
+            token: std::env::var("NTFY_TOKEN").ok(),
+            events: list_from_env("NTFY_EVENTS"),
+            title: std::env::var("NTFY_TITLE").unwrap_or_else(|_| "Sun event".to_owned()),
+            message: std::env::var("NTFY_MESSAGE")
+                .unwrap_or_else(|_| "{event} at {time}".to_owned()),
+        })
+    }
+}
+
+impl Sink for Ntfy {
+    fn name(&self) -> &'static str {
+        "ntfy"
+    }
+
+    fn send(&mut self, event: &Event) -> Result<(), String> {
+        if !selected(&self.events, event) {
+            return Ok(());
+        }
+        let mut request = ureq::post(&self.url).set("Title", &render(&self.title, event));
+        if let Some(token) = &self.token {
+            request = request.set("Authorization", &format!("Bearer {}", token));
+        }
+        request
+            .send_string(&render(&self.message, event))
+            .map(|_| ())
+            .map_err(http_error)
+    }
+}
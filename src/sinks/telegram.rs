@@ -0,0 +1,46 @@
+use super::{http_error, list_from_env, render, selected, Event, Sink};
+
+/// Sends the selected events to a Telegram chat through a bot.
+pub struct Telegram {
+    url: String,
+    chat_id: String,
+    events: Option<Vec<String>>,
+    message: String,
+}
+
+impl Telegram {
+    /// Reads the bot token from `TELEGRAM_TOKEN` and the destination from
+    /// `TELEGRAM_CHAT_ID`, with the optional comma separated
+    /// `TELEGRAM_EVENTS` (all by default) and `TELEGRAM_MESSAGE` template.
+    pub fn from_env() -> Option<Self> {
+        let token = std::env::var("TELEGRAM_TOKEN").ok()?;
+        Some(Self {
+            url: format!("https://api.telegram.org/bot{}/sendMessage", token),
+            chat_id: std::env::var("TELEGRAM_CHAT_ID").expect("Missing Telegram chat ID"),
+            events: list_from_env("TELEGRAM_EVENTS"),
+            message: std::env::var("TELEGRAM_MESSAGE")
+                .unwrap_or_else(|_| "{event} at {time}".to_owned()),
+        })
+    }
+}
+
+impl Sink for Telegram {
+    fn name(&self) -> &'static str {
+        "Telegram"
+    }
+
+    fn send(&mut self, event: &Event) -> Result<(), String> {
+        if !selected(&self.events, event) {
+            return Ok(());
+        }
+        let message = serde_json::json!({
+            "chat_id": self.chat_id,
+            "text": render(&self.message, event),
+        });
+        ureq::post(&self.url)
+            .set("Content-Type", "application/json")
+            .send_string(&message.to_string())
+            .map(|_| ())
+            .map_err(http_error)
+    }
+}
@@ -0,0 +1,211 @@
+//! Engines computing the position of the sun, selected for the whole process
+//! by `SOLAR_BACKEND`: `simple` (the default), the approximation of the sun
+//! crate, or `spa`, the NREL Solar Position Algorithm on top of the VSOP87
+//! theory of the astro crate, accurate to a few thousandths of a degree for
+//! heliostats and trackers.
+//!
+//! `OBSERVER_ELEVATION` (metres above sea level), `ATMOSPHERIC_PRESSURE` (hPa,
+//! by default estimated from the elevation) and `AIR_TEMPERATURE` (°C,
+//! default 10) describe the observer, lowering the phase thresholds by the dip
+//! of the horizon and, with the simple backend, by the refraction at the
+//! horizon, which the SPA includes in the altitude itself.
+
+use crate::ephemeris;
+use crate::phase::Thresholds;
+use std::sync::OnceLock;
+
+/// Difference between terrestrial and universal time, in seconds.
+const DELTA_T: f64 = 69.0;
+const EARTH_RADIUS_M: f64 = 6_378_140.0;
+/// Altitude in degrees below which no refraction is applied, the sun being
+/// out of sight even with it.
+const REFRACTION_LIMIT: f64 = -(0.26667 + 0.5667);
+/// Refraction at the horizon in degrees, at 1010 hPa and 10 °C.
+const HORIZON_REFRACTION: f64 = 0.5667;
+
+/// Position of the sun in radians, the azimuth clockwise from north.
+#[derive(Debug, Clone, Copy)]
+pub struct Position {
+    pub altitude: f64,
+    pub azimuth: f64,
+}
+
+pub trait SolarBackend: Send + Sync {
+    /// Position of the sun at `timestamp` milliseconds for an observer at
+    /// `lat` and `lon` degrees.
+    fn position(&self, timestamp: i64, lat: f64, lon: f64) -> Position;
+
+    /// Whether the altitude includes the atmospheric refraction.
+    fn refracts(&self) -> bool {
+        false
+    }
+}
+
+/// Where the observer stands and the air the sunlight goes through.
+#[derive(Debug, Clone, Copy)]
+pub struct Observer {
+    /// Metres above sea level
+    pub elevation: f64,
+    /// Atmospheric pressure in hPa
+    pub pressure: f64,
+    /// Air temperature in °C
+    pub temperature: f64,
+}
+
+impl Default for Observer {
+    fn default() -> Self {
+        Self {
+            elevation: 0.0,
+            pressure: 1010.0,
+            temperature: 10.0,
+        }
+    }
+}
+
+impl Observer {
+    /// Reads the observer from the environment, if any of it is given.
+    pub fn from_env() -> Option<Self> {
+        let read = |name| {
+            std::env::var(name).ok().map(|x| {
+                x.parse::<f64>()
+                    .unwrap_or_else(|_| panic!("Invalid {}", name))
+            })
+        };
+        let elevation = read("OBSERVER_ELEVATION");
+        let pressure = read("ATMOSPHERIC_PRESSURE");
+        let temperature = read("AIR_TEMPERATURE");
+        if elevation.is_none() && pressure.is_none() && temperature.is_none() {
+            return None;
+        }
+        let elevation = elevation.unwrap_or(0.0);
+        let observer = Self {
+            elevation,
+            // Standard atmosphere
+            pressure: pressure
+                .unwrap_or_else(|| 1013.25 * (1.0 - 2.25577e-5 * elevation).powf(5.25588)),
+            temperature: temperature.unwrap_or(10.0),
+        };
+        assert!(
+            observer.pressure > 0.0 && observer.temperature > -273.15,
+            "The atmospheric pressure and the air temperature must be physical"
+        );
+        Some(observer)
+    }
+
+    /// Dip of the horizon in degrees, seen from above sea level.
+    pub fn dip(&self) -> f64 {
+        0.0293 * self.elevation.max(0.0).sqrt()
+    }
+
+    /// Scale of the refraction relative to 1010 hPa and 10 °C.
+    fn refraction_scale(&self) -> f64 {
+        self.pressure / 1010.0 * 283.0 / (273.0 + self.temperature)
+    }
+
+    /// `thresholds` lowered by the dip of the horizon and, unless the
+    /// altitudes already include it, the horizon by the refraction.
+    pub fn correct(&self, thresholds: Thresholds, refracted: bool) -> Thresholds {
+        let dip = self.dip();
+        let refraction = if refracted {
+            0.0
+        } else {
+            HORIZON_REFRACTION * self.refraction_scale()
+        };
+        Thresholds {
+            astronomical: thresholds.astronomical - dip,
+            nautical: thresholds.nautical - dip,
+            civil: thresholds.civil - dip,
+            horizon: thresholds.horizon - dip - refraction,
+        }
+    }
+}
+
+/// The approximation of the sun crate, good to about a tenth of a degree.
+pub struct Simple;
+
+impl SolarBackend for Simple {
+    fn position(&self, timestamp: i64, lat: f64, lon: f64) -> Position {
+        let position = sun::pos(timestamp, lat, lon);
+        Position {
+            altitude: position.altitude,
+            azimuth: position.azimuth,
+        }
+    }
+}
+
+/// The NREL Solar Position Algorithm, giving the topocentric position
+/// corrected for parallax and, above the horizon, for refraction.
+#[derive(Default)]
+pub struct Spa {
+    pub observer: Observer,
+}
+
+impl SolarBackend for Spa {
+    fn position(&self, timestamp: i64, lat: f64, lon: f64) -> Position {
+        let jd = ephemeris::julian_day(0) + timestamp as f64 / 86_400_000.0;
+        let (ra, dec) = ephemeris::sun_equatorial(jd + DELTA_T / 86400.0);
+        let (_, distance) = astro::sun::geocent_ecl_pos(jd + DELTA_T / 86400.0);
+        let hour_angle = ephemeris::apparent_sidereal_time(jd) + lon.to_radians() - ra;
+
+        // Topocentric correction
+        let lat = lat.to_radians();
+        let parallax = (8.794 / 3600.0f64).to_radians().sin() / distance;
+        let u = (0.99664719 * lat.tan()).atan();
+        let height = self.observer.elevation / EARTH_RADIUS_M;
+        let x = u.cos() + height * lat.cos();
+        let y = 0.99664719 * u.sin() + height * lat.sin();
+        let denominator = dec.cos() - x * parallax * hour_angle.cos();
+        let delta_ra = (-x * parallax * hour_angle.sin()).atan2(denominator);
+        let dec = ((dec.sin() - y * parallax) * delta_ra.cos()).atan2(denominator);
+        let hour_angle = hour_angle - delta_ra;
+
+        let altitude = (lat.sin() * dec.sin() + lat.cos() * dec.cos() * hour_angle.cos())
+            .asin()
+            .to_degrees();
+        let refraction = if altitude >= REFRACTION_LIMIT {
+            self.observer.refraction_scale() * 1.02
+                / (60.0 * (altitude + 10.3 / (altitude + 5.11)).to_radians().tan())
+        } else {
+            0.0
+        };
+        let azimuth = hour_angle
+            .sin()
+            .atan2(hour_angle.cos() * lat.sin() - dec.tan() * lat.cos())
+            + std::f64::consts::PI;
+        Position {
+            altitude: (altitude + refraction).to_radians(),
+            azimuth: azimuth.rem_euclid(std::f64::consts::TAU),
+        }
+    }
+
+    fn refracts(&self) -> bool {
+        true
+    }
+}
+
+fn backend() -> &'static dyn SolarBackend {
+    static BACKEND: OnceLock<Box<dyn SolarBackend>> = OnceLock::new();
+    BACKEND
+        .get_or_init(|| match std::env::var("SOLAR_BACKEND").as_deref() {
+            Ok("simple") | Err(_) => Box::new(Simple),
+            Ok("spa") => Box::new(Spa {
+                observer: Observer::from_env().unwrap_or_default(),
+            }),
+            Ok(x) => panic!("Invalid SOLAR_BACKEND {}, expected simple or spa", x),
+        })
+        .as_ref()
+}
+
+/// `thresholds` corrected for the observer given in the environment, if any.
+pub fn correct(thresholds: Thresholds) -> Thresholds {
+    match Observer::from_env() {
+        Some(observer) => observer.correct(thresholds, backend().refracts()),
+        None => thresholds,
+    }
+}
+
+/// Position of the sun at `timestamp` milliseconds for an observer at `lat`
+/// and `lon` degrees, from the backend chosen in `SOLAR_BACKEND`.
+pub fn pos(timestamp: i64, lat: f64, lon: f64) -> Position {
+    backend().position(timestamp, lat, lon)
+}
@@ -0,0 +1,92 @@
+//! The 24 solar terms (jieqi) of the Chinese calendar, reached every 15° of
+//! apparent ecliptic longitude of the sun and announced on
+//! `sun/solar_terms`, under their pinyin names, with `SOLAR_TERMS=true`.
+//! The next one is kept retained on `sun/solar_terms/next`.
+
+use crate::ephemeris;
+
+pub const TOPIC: &str = "sun/solar_terms";
+pub const NEXT_TOPIC: &str = "sun/solar_terms/next";
+
+/// Names of the terms from the March equinox, 0° of longitude, on.
+const NAMES: [&str; 24] = [
+    "chunfen",
+    "qingming",
+    "guyu",
+    "lixia",
+    "xiaoman",
+    "mangzhong",
+    "xiazhi",
+    "xiaoshu",
+    "dashu",
+    "liqiu",
+    "chushu",
+    "bailu",
+    "qiufen",
+    "hanlu",
+    "shuangjiang",
+    "lidong",
+    "xiaoxue",
+    "daxue",
+    "dongzhi",
+    "xiaohan",
+    "dahan",
+    "lichun",
+    "yushui",
+    "jingzhe",
+];
+
+/// Mean motion of the sun in degrees per second.
+const MEAN_MOTION: f64 = 360.0 / (365.2422 * 86400.0);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Term {
+    pub name: &'static str,
+    /// Apparent ecliptic longitude of the sun in degrees
+    pub longitude: f64,
+    /// Unix timestamp in seconds
+    pub timestamp: i64,
+}
+
+impl Term {
+    pub fn to_json(self) -> serde_json::Value {
+        serde_json::json!({
+            "name": self.name,
+            "longitude": self.longitude,
+            "timestamp": self.timestamp,
+        })
+    }
+}
+
+/// Apparent ecliptic longitude of the sun at `timestamp` in degrees.
+fn longitude(timestamp: f64) -> f64 {
+    let jd = ephemeris::julian_day(0) + timestamp / 86400.0;
+    ephemeris::sun_ecliptic(jd).0.to_degrees().rem_euclid(360.0)
+}
+
+/// Whether solar terms are announced, from `SOLAR_TERMS`.
+pub fn enabled_from_env() -> bool {
+    std::env::var("SOLAR_TERMS")
+        .map(|x| {
+            x.parse()
+                .expect("Invalid SOLAR_TERMS, expected true or false")
+        })
+        .unwrap_or(false)
+}
+
+/// First solar term after `from`.
+pub fn next(from: i64) -> Term {
+    let index = (longitude(from as f64) / 15.0).floor() as usize + 1;
+    let target = (index * 15) as f64;
+    let mut timestamp = from as f64;
+    // Newton iterations on the longitude, moving almost uniformly
+    for _ in 0..5 {
+        let left = (target - longitude(timestamp) + 180.0).rem_euclid(360.0) - 180.0;
+        timestamp += left / MEAN_MOTION;
+    }
+    Term {
+        name: NAMES[index % NAMES.len()],
+        longitude: target % 360.0,
+        timestamp: timestamp.round() as i64,
+    }
+}
@@ -0,0 +1,211 @@
+//! State kept across restarts in the JSON file named by `STATE_FILE`, such
+//! as `/var/lib/mqtt_sun/state.json`, so that a restart neither announces
+//! again the phase already published, nor forgets a pending solar noon, nor
+//! publishes every periodic topic at once.
+//!
+//! With several locations each keeps its own file, named after the location
+//! as in `state.office.json`.
+
+use crate::phase::SunPosition;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Seconds after which the phase saved is too old to be trusted, the sun
+/// having possibly gone through every other phase since.
+const MAX_AGE: i64 = 12 * 3600;
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct State {
+    /// Last phase published and when, as a Unix timestamp
+    phase: Option<String>,
+    phase_at: Option<i64>,
+    /// Solar noon still to be published
+    solar_noon: Option<i64>,
+    /// When each periodic topic was last published, as Unix timestamps
+    published: BTreeMap<String, i64>,
+}
+
+pub struct Store {
+    path: PathBuf,
+    state: State,
+}
+
+impl Store {
+    /// Loads the state of `location` from `STATE_FILE`, starting afresh if
+    /// the file does not exist yet or cannot be read.
+    pub fn from_env(location: Option<&str>) -> Option<Self> {
+        let path = PathBuf::from(std::env::var("STATE_FILE").ok()?);
+        let path = match location {
+            Some(name) => {
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                let file = match path.extension() {
+                    Some(extension) => {
+                        format!("{}.{}.{}", stem, name, extension.to_string_lossy())
+                    }
+                    None => format!("{}.{}", stem, name),
+                };
+                path.with_file_name(file)
+            }
+            None => path,
+        };
+        let state = match std::fs::read_to_string(&path) {
+            Ok(x) => serde_json::from_str(&x).unwrap_or_else(|e| {
+                warn!("Ignoring the state in {}: {}", path.display(), e);
+                State::default()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => State::default(),
+            Err(e) => {
+                warn!("Could not read the state in {}: {}", path.display(), e);
+                State::default()
+            }
+        };
+        Some(Self { path, state })
+    }
+
+    /// Phase published before the restart, if recent enough at `now`.
+    pub fn phase(&self, now: i64) -> Option<SunPosition> {
+        if self
+            .state
+            .phase_at
+            .map(|x| now - x > MAX_AGE)
+            .unwrap_or(true)
+        {
+            return None;
+        }
+        let name = self.state.phase.as_deref()?;
+        SunPosition::ALL
+            .iter()
+            .find(|x| <&'static str>::from(*x) == name)
+            .copied()
+    }
+
+    /// When the last phase was published before the restart.
+    pub fn published_at(&self) -> Option<i64> {
+        self.state.phase_at
+    }
+
+    /// Solar noon pending before the restart, if still ahead of `now`.
+    pub fn solar_noon(&self, now: i64) -> Option<i64> {
+        self.state.solar_noon.filter(|x| *x > now)
+    }
+
+    /// When the periodic topic `name` was last published before the
+    /// restart, as an instant of this run.
+    pub fn last_published(&self, name: &str, now: i64) -> Option<Instant> {
+        let at = *self.state.published.get(name)?;
+        let ago = u64::try_from(now - at).ok()?;
+        Instant::now().checked_sub(Duration::from_secs(ago))
+    }
+
+    /// Records the last phase published, the pending solar noon and when
+    /// each of the periodic topics in `published` was last published,
+    /// writing the file if they changed.
+    pub fn sync(
+        &mut self,
+        now: i64,
+        phase: Option<SunPosition>,
+        solar_noon: Option<i64>,
+        published: &[(&str, Option<Instant>)],
+    ) {
+        let mut state = self.state.clone();
+        let phase = phase.map(|x| <&'static str>::from(&x).to_owned());
+        if phase != state.phase {
+            state.phase = phase;
+            state.phase_at = Some(now);
+        }
+        state.solar_noon = solar_noon;
+        for (name, last) in published {
+            let at = match last {
+                Some(last) => now - last.elapsed().as_secs() as i64,
+                None => continue,
+            };
+            // Rounding moves the same instant by a second between calls
+            if state
+                .published
+                .get(*name)
+                .is_none_or(|x| (x - at).abs() > 1)
+            {
+                state.published.insert((*name).to_owned(), at);
+            }
+        }
+        if state == self.state {
+            return;
+        }
+        self.state = state;
+        if let Err(e) = self.save() {
+            warn!("Could not save the state in {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Writes the state through a temporary file, so that a crash never
+    /// leaves half of it behind.
+    fn save(&self) -> std::io::Result<()> {
+        if let Some(directory) = self.path.parent().filter(|x| !x.as_os_str().is_empty()) {
+            std::fs::create_dir_all(directory)?;
+        }
+        let temporary = self.path.with_extension("tmp");
+        std::fs::write(&temporary, serde_json::to_string(&self.state)?)?;
+        std::fs::rename(&temporary, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(name: &str) -> Store {
+        let path = std::env::temp_dir().join(format!("state-{}-{}.json", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        Store {
+            path,
+            state: State::default(),
+        }
+    }
+
+    fn reload(store: &Store) -> Store {
+        let state = std::fs::read_to_string(&store.path).unwrap();
+        Store {
+            path: store.path.clone(),
+            state: serde_json::from_str(&state).unwrap(),
+        }
+    }
+
+    #[test]
+    fn publish_times_survive_restarts() {
+        let mut store = store("published");
+        let now = 1_614_556_800;
+        let info = Instant::now() - Duration::from_secs(30);
+        store.sync(
+            now,
+            Some(SunPosition::Sunrise),
+            None,
+            &[("info", Some(info)), ("tracker", None)],
+        );
+        let restored = reload(&store);
+        assert_eq!(restored.phase(now), Some(SunPosition::Sunrise));
+        assert_eq!(restored.state.published.get("info"), Some(&(now - 30)));
+        let last = restored.last_published("info", now + 60).unwrap();
+        assert!((last.elapsed().as_secs() as i64 - 90).abs() <= 1);
+        assert!(restored.last_published("tracker", now).is_none());
+        // Nor from the future, after the clock went back
+        assert!(restored.last_published("info", now - 60).is_none());
+        std::fs::remove_file(&store.path).unwrap();
+    }
+
+    #[test]
+    fn unchanged_publish_times_are_not_written() {
+        let mut store = store("unchanged");
+        let now = 1_614_556_800;
+        let info = Instant::now();
+        store.sync(now, None, None, &[("info", Some(info))]);
+        std::fs::remove_file(&store.path).unwrap();
+        // The same instant a second later, rounded the other way
+        store.sync(now + 1, None, None, &[("info", Some(info))]);
+        assert!(!store.path.exists());
+    }
+}
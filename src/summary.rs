@@ -0,0 +1,111 @@
+//! One-line description of the current state of the sun, such as
+//! "Daylight — sunset at 18:42 (in 2 h 13 m), golden hour from 17:55", for
+//! displays and voice assistants.
+
+use crate::phase::{SunPosition, Thresholds};
+use crate::schedule;
+use chrono::TimeZone;
+use std::convert::TryFrom;
+
+pub const TOPIC: &str = "sun/summary";
+
+/// Altitude in degrees below which the daylight turns into golden hour.
+const GOLDEN_HOUR: f64 = 6.0;
+
+pub struct Summary {
+    locale: chrono::Locale,
+    time_format: String,
+}
+
+/// Locale name from the environment, without the encoding.
+fn system_locale() -> Option<String> {
+    ["LC_ALL", "LC_TIME", "LANG"]
+        .iter()
+        .filter_map(|x| std::env::var(x).ok())
+        .find(|x| !x.is_empty())
+        .map(|x| x.split('.').next().unwrap().to_owned())
+}
+
+/// Name of the part of the day with the sun at `altitude` degrees.
+pub fn label(altitude: f64, thresholds: &Thresholds) -> &'static str {
+    if altitude >= thresholds.horizon {
+        "Daylight"
+    } else if altitude >= thresholds.civil {
+        "Civil twilight"
+    } else if altitude >= thresholds.nautical {
+        "Nautical twilight"
+    } else if altitude >= thresholds.astronomical {
+        "Astronomical twilight"
+    } else {
+        "Night"
+    }
+}
+
+impl Summary {
+    /// Formats times with `SUMMARY_TIME_FORMAT` (default `%H:%M`) in the
+    /// `SUMMARY_LOCALE` locale, falling back to the system one.
+    pub fn from_env() -> Self {
+        let locale = match std::env::var("SUMMARY_LOCALE") {
+            Ok(x) => chrono::Locale::try_from(x.as_str())
+                .unwrap_or_else(|_| panic!("Unknown SUMMARY_LOCALE {}", x)),
+            Err(_) => system_locale()
+                .and_then(|x| chrono::Locale::try_from(x.as_str()).ok())
+                .unwrap_or(chrono::Locale::POSIX),
+        };
+        Self {
+            locale,
+            time_format: std::env::var("SUMMARY_TIME_FORMAT")
+                .unwrap_or_else(|_| "%H:%M".to_owned()),
+        }
+    }
+
+    fn time(&self, timestamp: i64) -> String {
+        chrono::Local
+            .timestamp(timestamp, 0)
+            .format_localized(&self.time_format, self.locale)
+            .to_string()
+    }
+
+    /// Describes the sun at `altitude` degrees at `now`.
+    pub fn text(
+        &self,
+        now: i64,
+        altitude: f64,
+        coords: &astro::coords::GeographPoint,
+        thresholds: &Thresholds,
+    ) -> String {
+        let mut text = label(altitude, thresholds).to_owned();
+        let next = schedule::events_between(now, now + 2 * 24 * 3600, coords, thresholds)
+            .into_iter()
+            .find(|e| matches!(e.position, SunPosition::Sunrise | SunPosition::Sunset));
+        if let Some(next) = next {
+            let name: &'static str = (&next.position).into();
+            let minutes = (next.timestamp - now) / 60;
+            let remaining = if minutes >= 60 {
+                format!("{} h {} m", minutes / 60, minutes % 60)
+            } else {
+                format!("{} m", minutes)
+            };
+            text += &format!(
+                " — {} at {} (in {})",
+                name,
+                self.time(next.timestamp),
+                remaining
+            );
+        }
+        let golden_hour = schedule::next_crossing(now, now + 24 * 3600, GOLDEN_HOUR, coords);
+        if altitude >= GOLDEN_HOUR {
+            if let Some(start) = golden_hour {
+                text += &format!(", golden hour from {}", self.time(start));
+            }
+        } else if altitude >= thresholds.horizon {
+            text += ", golden hour now";
+        } else if let (Some(end), Some(next)) = (golden_hour, next) {
+            // The morning golden hour follows sunrise
+            if next.position == SunPosition::Sunrise && end > next.timestamp {
+                text += &format!(", golden hour until {}", self.time(end));
+            }
+        }
+        text
+    }
+}
@@ -0,0 +1,51 @@
+//! Approximate time to sunburn for unprotected skin, from the clear-sky UV
+//! index.
+
+use crate::clear_sky;
+
+/// Erythemally weighted irradiance of one UV index unit, in W/m².
+const UV_INDEX_IRRADIANCE: f64 = 0.025;
+
+/// Minimal erythemal dose in J/m² for the Fitzpatrick skin types I to VI.
+const MINIMAL_ERYTHEMAL_DOSE: [f64; 6] = [200.0, 250.0, 350.0, 450.0, 600.0, 1000.0];
+
+#[derive(Debug)]
+pub struct Sunburn {
+    minimal_erythemal_dose: f64,
+    /// Total ozone column in Dobson units
+    ozone: f64,
+}
+
+impl Sunburn {
+    /// Reads the Fitzpatrick skin type (1 to 6, default 2) from `SKIN_TYPE`
+    /// and the total ozone column from `OZONE_COLUMN` (default 300 DU).
+    pub fn from_env() -> Self {
+        let skin_type: usize = std::env::var("SKIN_TYPE")
+            .map(|x| x.parse().expect("Invalid skin type"))
+            .unwrap_or(2);
+        assert!(
+            (1..=6).contains(&skin_type),
+            "SKIN_TYPE must be between 1 and 6"
+        );
+        Self {
+            minimal_erythemal_dose: MINIMAL_ERYTHEMAL_DOSE[skin_type - 1],
+            ozone: std::env::var("OZONE_COLUMN")
+                .map(|x| x.parse().expect("Invalid ozone column"))
+                .unwrap_or(300.0),
+        }
+    }
+
+    pub fn uv_index(&self, altitude: f64) -> f64 {
+        clear_sky::uv_index(altitude, self.ozone)
+    }
+
+    /// Minutes of exposure to the sun at `altitude` degrees before the skin
+    /// burns, if there is any UV radiation at all.
+    pub fn minutes(&self, altitude: f64) -> Option<f64> {
+        let uv_index = self.uv_index(altitude);
+        if uv_index <= 0.0 {
+            return None;
+        }
+        Some(self.minimal_erythemal_dose / (uv_index * UV_INDEX_IRRADIANCE * 60.0))
+    }
+}
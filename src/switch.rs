@@ -0,0 +1,31 @@
+//! Payloads of the topics carrying a yes or no answer, for consumers that can
+//! only compare them with fixed strings.
+
+#[derive(Debug, Clone)]
+pub struct Switch {
+    on: String,
+    off: String,
+}
+
+impl Switch {
+    /// Reads the comma separated payloads for on and off from `variable`,
+    /// such as `ON,OFF`, defaulting to `default`.
+    pub fn from_env(variable: &str, default: &str) -> Self {
+        let payloads = std::env::var(variable).unwrap_or_else(|_| default.to_owned());
+        match payloads.split_once(',') {
+            Some((on, off)) if on.trim() != off.trim() => Self {
+                on: on.trim().to_owned(),
+                off: off.trim().to_owned(),
+            },
+            _ => panic!("{} needs two different payloads, such as ON,OFF", variable),
+        }
+    }
+
+    pub fn payload(&self, on: bool) -> &str {
+        if on {
+            &self.on
+        } else {
+            &self.off
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! GeoJSON representation of the day/night terminator.
+
+use crate::ephemeris;
+
+const LONGITUDE_STEP: usize = 2;
+
+/// Builds a GeoJSON feature collection with the terminator line, the polygon
+/// covering the night side of the Earth and the subsolar point.
+pub fn geojson(timestamp: i64) -> serde_json::Value {
+    let (sub_lat, sub_long) = ephemeris::subsolar_point(timestamp);
+    // Avoid dividing by zero at the equinoxes, when the terminator runs
+    // through the poles
+    let tan_dec = match sub_lat.to_radians().tan() {
+        x if x.abs() < 1e-9 => 1e-9,
+        x => x,
+    };
+    let line: Vec<[f64; 2]> = (0..=360 / LONGITUDE_STEP)
+        .map(|i| {
+            let long = -180.0 + (i * LONGITUDE_STEP) as f64;
+            let hour_angle = (long - sub_long).to_radians();
+            let lat = (-hour_angle.cos() / tan_dec).atan().to_degrees();
+            [long, lat]
+        })
+        .collect();
+    // The night side is closed through the pole not lit by the sun
+    let dark_pole = if sub_lat > 0.0 { -90.0 } else { 90.0 };
+    let mut night = line.clone();
+    night.push([180.0, dark_pole]);
+    night.push([-180.0, dark_pole]);
+    night.push(line[0]);
+    serde_json::json!({
+        "type": "FeatureCollection",
+        "features": [
+            {
+                "type": "Feature",
+                "properties": { "name": "terminator" },
+                "geometry": { "type": "LineString", "coordinates": line },
+            },
+            {
+                "type": "Feature",
+                "properties": { "name": "night" },
+                "geometry": { "type": "Polygon", "coordinates": [night] },
+            },
+            {
+                "type": "Feature",
+                "properties": { "name": "subsolar" },
+                "geometry": { "type": "Point", "coordinates": [sub_long, sub_lat] },
+            },
+        ],
+    })
+}
@@ -0,0 +1,29 @@
+//! Time zone of the local times, by default the one of the system.
+//!
+//! `TIMEZONE`, such as `Europe/Rome`, names a zone of the tz database
+//! installed with the system (under `TZDIR` or `/usr/share/zoneinfo`) and
+//! replaces the system one for the whole process, so that the daily
+//! schedules, the published local times and the log agree even in a
+//! container running on UTC.
+
+use std::path::Path;
+
+/// Checks `TIMEZONE` and exports it as `TZ`, before any local time is
+/// computed and any thread is started.
+pub fn apply_from_env() {
+    let name = match std::env::var("TIMEZONE") {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+    let database = std::env::var("TZDIR").unwrap_or_else(|_| "/usr/share/zoneinfo".to_owned());
+    assert!(
+        !name.is_empty()
+            && !name.starts_with('/')
+            && !name.split('/').any(|x| x == "..")
+            && Path::new(&database).join(&name).is_file(),
+        "Invalid TIMEZONE {}, it is not in the tz database under {}",
+        name,
+        database
+    );
+    std::env::set_var("TZ", name);
+}
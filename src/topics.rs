@@ -0,0 +1,114 @@
+//! Mapping of the built-in `sun/...` and `moon/...` topics to the ones
+//! actually used, so that several instances can share a broker.
+//!
+//! Every topic whose first level is `sun` or `moon` is rewritten with the
+//! template in `TOPIC_TEMPLATE` (default `{prefix}/{location}/{event}`),
+//! where `{prefix}` comes from `TOPIC_PREFIX` (default `sun`) for the sun and
+//! from `MOON_TOPIC_PREFIX` (default `moon`) for the moon, `{location}` is the
+//! name of the location (`LOCATION_NAME`, empty by default) and `{event}` is
+//! the rest of the topic, such as `info` for `sun/info`. Levels left empty
+//! are dropped, so that `sun` itself maps to `{prefix}` and an unset location
+//! does not leave a `//` behind. Other topics, such as those of Home
+//! Assistant or of external sensors, are used as they are.
+
+#[derive(Debug, Clone)]
+pub struct Topics {
+    template: String,
+    prefix: String,
+    moon_prefix: String,
+    location: String,
+}
+
+impl Topics {
+    pub fn from_env() -> Self {
+        let template = std::env::var("TOPIC_TEMPLATE")
+            .unwrap_or_else(|_| "{prefix}/{location}/{event}".to_owned());
+        assert!(
+            template.contains("{event}"),
+            "Invalid TOPIC_TEMPLATE {}, it must contain {{event}}",
+            template
+        );
+        let level = |name, default: &str| {
+            let value = std::env::var(name).unwrap_or_else(|_| default.to_owned());
+            assert!(
+                !value.contains(['+', '#']),
+                "Invalid {} {}, wildcards are not allowed",
+                name,
+                value
+            );
+            value
+        };
+        let prefix = level("TOPIC_PREFIX", "sun");
+        assert!(!prefix.is_empty(), "The TOPIC_PREFIX is empty");
+        let moon_prefix = level("MOON_TOPIC_PREFIX", "moon");
+        assert!(!moon_prefix.is_empty(), "The MOON_TOPIC_PREFIX is empty");
+        Self {
+            prefix,
+            moon_prefix,
+            location: level("LOCATION_NAME", ""),
+            template,
+        }
+    }
+
+    /// Uses `location` in place of `LOCATION_NAME`.
+    pub fn with_location(mut self, location: &str) -> Self {
+        self.location = location.to_owned();
+        self
+    }
+
+    /// Returns the topic to use in place of the built-in `topic`.
+    pub fn topic(&self, topic: &str) -> String {
+        let (root, event) = topic.split_once('/').unwrap_or((topic, ""));
+        let prefix = match root {
+            "sun" => &self.prefix,
+            "moon" => &self.moon_prefix,
+            _ => return topic.to_owned(),
+        };
+        self.template
+            .replace("{prefix}", prefix)
+            .replace("{location}", &self.location)
+            .replace("{event}", event)
+            .split('/')
+            .filter(|x| !x.is_empty())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topics(template: &str, location: &str) -> Topics {
+        Topics {
+            template: template.to_owned(),
+            prefix: "sun".to_owned(),
+            moon_prefix: "moon".to_owned(),
+            location: location.to_owned(),
+        }
+    }
+
+    #[test]
+    fn unchanged_by_default() {
+        let topics = topics("{prefix}/{location}/{event}", "");
+        assert_eq!(topics.topic("sun"), "sun");
+        assert_eq!(topics.topic("sun/info"), "sun/info");
+        assert_eq!(topics.topic("moon/full_moon"), "moon/full_moon");
+        assert_eq!(
+            topics.topic("homeassistant/sensor/x"),
+            "homeassistant/sensor/x"
+        );
+        assert_eq!(topics.topic("sunshine/x"), "sunshine/x");
+    }
+
+    #[test]
+    fn locations() {
+        let topics = topics("{prefix}/{location}/{event}", "office");
+        assert_eq!(topics.topic("sun"), "sun/office");
+        assert_eq!(topics.topic("sun/info"), "sun/office/info");
+        assert_eq!(
+            topics.topic("moon/illumination"),
+            "moon/office/illumination"
+        );
+    }
+}
@@ -0,0 +1,61 @@
+//! Setpoints for motorized solar trackers on `sun/tracker/azimuth` and
+//! `sun/tracker/elevation`, in degrees, pointing at the sun while it is high
+//! enough and at a stow position otherwise.
+
+use std::time::Duration;
+
+pub const AZIMUTH_TOPIC: &str = "sun/tracker/azimuth";
+pub const ELEVATION_TOPIC: &str = "sun/tracker/elevation";
+
+#[derive(Debug)]
+pub struct Tracker {
+    pub interval: Duration,
+    /// Altitude of the sun in degrees below which the tracker stows
+    min_elevation: f64,
+    /// Azimuth and elevation of the stow position in degrees
+    stow: (f64, f64),
+}
+
+impl Tracker {
+    /// Updates every `TRACKER_INTERVAL` seconds, following the sun above
+    /// `TRACKER_MIN_ELEVATION` (default 0) and going to `TRACKER_STOW`
+    /// otherwise, by default `180,90` to lie flat facing the zenith.
+    pub fn from_env() -> Option<Self> {
+        let interval = Duration::from_secs(
+            std::env::var("TRACKER_INTERVAL")
+                .ok()?
+                .parse()
+                .expect("Invalid TRACKER_INTERVAL"),
+        );
+        assert!(!interval.is_zero(), "The TRACKER_INTERVAL must be positive");
+        let min_elevation = std::env::var("TRACKER_MIN_ELEVATION")
+            .map(|x| x.parse().expect("Invalid TRACKER_MIN_ELEVATION"))
+            .unwrap_or(0.0);
+        let stow: Vec<f64> = std::env::var("TRACKER_STOW")
+            .unwrap_or_else(|_| "180,90".to_owned())
+            .split(',')
+            .map(|x| x.trim().parse().expect("Invalid TRACKER_STOW"))
+            .collect();
+        let stow = match stow.as_slice() {
+            &[azimuth, elevation] if (-90.0..=90.0).contains(&elevation) => {
+                (azimuth.rem_euclid(360.0), elevation)
+            }
+            _ => panic!("TRACKER_STOW needs an azimuth and an elevation"),
+        };
+        Some(Self {
+            interval,
+            min_elevation,
+            stow,
+        })
+    }
+
+    /// Azimuth and elevation to point at, for the sun at the given azimuth
+    /// and altitude in degrees.
+    pub fn setpoint(&self, azimuth: f64, altitude: f64) -> (f64, f64) {
+        if altitude >= self.min_elevation {
+            (azimuth, altitude)
+        } else {
+            self.stow
+        }
+    }
+}
@@ -0,0 +1,235 @@
+//! `mqtt_sun watch`, a live terminal dashboard with the position of the sun,
+//! its path over the day, the day's events and the state of the broker
+//! connection.
+
+use crate::phase::Thresholds;
+use crate::{broker, location, mqtt, phase, schedule, summary, topics};
+use chrono::TimeZone;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const REFRESH: Duration = Duration::from_secs(1);
+/// Rows of the sun path graph.
+const GRAPH_HEIGHT: usize = 11;
+
+const CLEAR: &str = "\x1b[H\x1b[2J";
+const HIDE_CURSOR: &str = "\x1b[?25l";
+const SHOW_CURSOR: &str = "\x1b[?25h";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const YELLOW: &str = "\x1b[33m";
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+fn altitude(timestamp: i64, coords: &astro::coords::GeographPoint) -> f64 {
+    crate::solar::pos(timestamp * 1000, coords.lat, coords.long)
+        .altitude
+        .to_degrees()
+}
+
+fn local_time(timestamp: i64) -> chrono::DateTime<chrono::Local> {
+    chrono::Local.timestamp(timestamp, 0)
+}
+
+/// Plots the altitude over the local day from `midnight` to `next_midnight`,
+/// marking the sun at `now`.
+fn graph(
+    midnight: i64,
+    next_midnight: i64,
+    now: i64,
+    width: usize,
+    coords: &astro::coords::GeographPoint,
+    out: &mut String,
+) {
+    let length = next_midnight - midnight;
+    let column_time = |i: usize| midnight + (i as i64 * length) / width as i64;
+    let altitudes: Vec<_> = (0..width)
+        .map(|i| altitude(column_time(i), coords))
+        .collect();
+    let top = altitudes.iter().cloned().fold(0.0, f64::max).ceil();
+    let bottom = altitudes.iter().cloned().fold(0.0, f64::min).floor();
+    let row = |x: f64| {
+        (((top - x) / (top - bottom).max(1.0)) * (GRAPH_HEIGHT - 1) as f64).round() as usize
+    };
+    let horizon = row(0.0);
+    let now_column = (((now - midnight) * width as i64) / length) as usize;
+    for r in 0..GRAPH_HEIGHT {
+        let label = if r == 0 {
+            format!("{:>4}°", top)
+        } else if r == horizon {
+            "   0°".to_owned()
+        } else if r == GRAPH_HEIGHT - 1 {
+            format!("{:>4}°", bottom)
+        } else {
+            "     ".to_owned()
+        };
+        out.push_str(&label);
+        out.push(' ');
+        for (i, x) in altitudes.iter().enumerate() {
+            if i == now_column && r == row(altitude(now, coords)) {
+                let _ = write!(out, "{}{}O{}", BOLD, YELLOW, RESET);
+            } else if r == row(*x) {
+                out.push(if *x >= 0.0 { '*' } else { '.' });
+            } else if r == horizon {
+                out.push('-');
+            } else if i == now_column {
+                let _ = write!(out, "{}|{}", DIM, RESET);
+            } else {
+                out.push(' ');
+            }
+        }
+        out.push('\n');
+    }
+    let _ = write!(out, "      ");
+    for i in 0..8 {
+        let label = local_time(column_time(i * width / 8))
+            .format("%H:%M")
+            .to_string();
+        let _ = write!(out, "{:<w$}", label, w = width / 8);
+    }
+    out.push('\n');
+}
+
+fn render(
+    now: i64,
+    coords: &astro::coords::GeographPoint,
+    thresholds: &Thresholds,
+    summary: &summary::Summary,
+    broker: &str,
+    connected: bool,
+    messages: &[(String, String)],
+) -> String {
+    let width = std::env::var("COLUMNS")
+        .ok()
+        .and_then(|x| x.parse::<usize>().ok())
+        .unwrap_or(80)
+        .max(40)
+        - 8;
+    let position = crate::solar::pos(now * 1000, coords.lat, coords.long);
+    let alt = position.altitude.to_degrees();
+
+    let mut out = String::new();
+    out.push_str(CLEAR);
+    let _ = writeln!(
+        out,
+        "{}mqtt_sun watch{}  {}  ({:.4}, {:.4})",
+        BOLD,
+        RESET,
+        local_time(now).format("%Y-%m-%d %H:%M:%S %Z"),
+        coords.lat,
+        coords.long
+    );
+    let _ = writeln!(
+        out,
+        "Broker {}: {}\n",
+        broker,
+        if connected {
+            format!("{}connected{}", GREEN, RESET)
+        } else {
+            format!("{}disconnected{}", RED, RESET)
+        }
+    );
+    let _ = writeln!(
+        out,
+        "Altitude {}{:7.2}°{}   Azimuth {}{:7.2}°{}",
+        BOLD,
+        alt,
+        RESET,
+        BOLD,
+        position.azimuth.to_degrees(),
+        RESET
+    );
+    let _ = writeln!(out, "{}\n", summary.text(now, alt, coords, thresholds));
+
+    let today = local_time(now).date().naive_local();
+    let midnight = schedule::local_midnight(today);
+    let next_midnight = schedule::local_midnight(today.succ());
+    graph(midnight, next_midnight, now, width, coords, &mut out);
+
+    out.push_str("\nToday's events\n");
+    let events = schedule::phases_between(midnight, next_midnight, coords, thresholds);
+    let next = events.iter().position(|e| e.timestamp > now);
+    for (i, event) in events.iter().enumerate() {
+        let name: &'static str = (&event.position).into();
+        let time = local_time(event.timestamp).format("%H:%M:%S");
+        if Some(i) == next {
+            let _ = writeln!(out, "{}> {}  {}{}", BOLD, time, name, RESET);
+        } else if event.timestamp <= now {
+            let _ = writeln!(out, "{}  {}  {}{}", DIM, time, name, RESET);
+        } else {
+            let _ = writeln!(out, "  {}  {}", time, name);
+        }
+    }
+    if events.is_empty() {
+        let _ = writeln!(out, "{}  none{}", DIM, RESET);
+    }
+
+    if !messages.is_empty() {
+        out.push_str("\nPublished by the daemon\n");
+        for (topic, payload) in messages {
+            let _ = writeln!(out, "  {:<14} {}", topic, payload);
+        }
+    }
+    out
+}
+
+/// Runs the dashboard until interrupted.
+pub fn run() -> ! {
+    let coords = location::from_env();
+    let thresholds = phase::thresholds_from_env();
+    let summary = summary::Summary::from_env();
+    let (host, port) = broker::resolve();
+    let mapping = topics::Topics::from_env();
+    let event_topic =
+        mapping.topic(&std::env::var("EVENT_TOPIC").unwrap_or_else(|_| "sun".to_owned()));
+    let summary_topic = mapping.topic(summary::TOPIC);
+    let topics = [event_topic.as_str(), summary_topic.as_str()];
+    let (mut client, state, incoming) = mqtt::get_mqtt_conn(
+        &format!("{}_watch", mqtt::client_id_from_env()),
+        &host,
+        port,
+        &topics,
+        None,
+    );
+    let mut messages: Vec<(String, String)> = Vec::new();
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in &[signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+        signal_hook::flag::register(*signal, shutdown.clone()).expect("Could not handle signals");
+    }
+    print!("{}", HIDE_CURSOR);
+    while !shutdown.load(Ordering::Relaxed) {
+        while let Ok(message) = incoming.try_recv() {
+            let payload = String::from_utf8_lossy(&message.payload).into_owned();
+            match messages
+                .iter_mut()
+                .find(|(topic, _)| *topic == message.topic)
+            {
+                Some(entry) => entry.1 = payload,
+                None => messages.push((message.topic, payload)),
+            }
+        }
+        let now = chrono::Utc::now().timestamp();
+        print!(
+            "{}",
+            render(
+                now,
+                &coords,
+                &thresholds,
+                &summary,
+                &format!("{}:{}", host, port),
+                state.is_connected(),
+                &messages,
+            )
+        );
+        let _ = std::io::stdout().flush();
+        std::thread::sleep(REFRESH);
+    }
+    let _ = client.try_disconnect();
+    println!("{}", SHOW_CURSOR);
+    std::process::exit(0)
+}